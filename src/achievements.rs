@@ -0,0 +1,330 @@
+use log::{info, warn};
+use mysql::{params, prelude::*, Transaction};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::sync::LazyLock;
+
+// PlayerStats counters an achievement can be defined on.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AchievementStat {
+    Kills,
+    BestStreak,
+    BestMultiKill,
+    DoubleKills,
+    TripleKills,
+    FirstBloods,
+    LongestKill,
+    WeaponRecords,
+}
+
+impl AchievementStat {
+    pub fn column(&self) -> &'static str {
+        match self {
+            AchievementStat::Kills => "total_kills",
+            AchievementStat::BestStreak => "best_streak",
+            AchievementStat::BestMultiKill => "best_multi_kill",
+            AchievementStat::DoubleKills => "double_kills",
+            AchievementStat::TripleKills => "triple_kills",
+            AchievementStat::FirstBloods => "first_bloods",
+            AchievementStat::LongestKill => "longest_kill",
+            AchievementStat::WeaponRecords => "weapon_records",
+        }
+    }
+
+    // How the per-server PlayerStats rows combine into the player's network-wide value.
+    pub fn aggregate(&self) -> &'static str {
+        match self {
+            AchievementStat::BestStreak | AchievementStat::BestMultiKill | AchievementStat::LongestKill => "MAX",
+            _ => "SUM",
+        }
+    }
+}
+
+// Unlocked once the player's `stat` reaches `threshold`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AchievementDefinition {
+    pub key: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub stat: AchievementStat,
+    pub threshold: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AchievementConfig {
+    // Kills by the same player this many seconds apart (or less) chain into a multi-kill.
+    pub multi_kill_seconds: u32,
+    pub achievements: Vec<AchievementDefinition>,
+}
+
+impl Default for AchievementConfig {
+    fn default() -> Self {
+        let achievement = |key: &str, name: &str, description: &str, stat, threshold| AchievementDefinition {
+            key: key.to_string(),
+            name: name.to_string(),
+            description: description.to_string(),
+            stat,
+            threshold,
+        };
+        Self {
+            multi_kill_seconds: 5,
+            achievements: vec![
+                achievement("first-kill", "First Kill", "Kill an enemy", AchievementStat::Kills, 1.0),
+                achievement("centurion", "Centurion", "100 kills", AchievementStat::Kills, 100.0),
+                achievement("first-blood", "First Blood", "First kill of a match", AchievementStat::FirstBloods, 1.0),
+                achievement("streak-5", "Killing Spree", "5 kills without dying", AchievementStat::BestStreak, 5.0),
+                achievement("streak-10", "Rampage", "10 kills without dying", AchievementStat::BestStreak, 10.0),
+                achievement("streak-20", "Unstoppable", "20 kills without dying", AchievementStat::BestStreak, 20.0),
+                achievement("double-kill", "Double Kill", "2 kills in quick succession", AchievementStat::DoubleKills, 1.0),
+                achievement("triple-kill", "Triple Kill", "3 kills in quick succession", AchievementStat::TripleKills, 1.0),
+                achievement("long-shot", "Long Shot", "A kill from 500 m or more", AchievementStat::LongestKill, 500.0),
+                achievement("marksman", "Marksman", "A kill from 1000 m or more", AchievementStat::LongestKill, 1000.0),
+                achievement("record-holder", "Record Holder", "Set a weapon's longest-kill record", AchievementStat::WeaponRecords, 1.0),
+            ],
+        }
+    }
+}
+
+// ACHIEVEMENTS_PATH (default `achievements.json`); a file with an `achievements` list replaces the built-in ones.
+static CONFIG: LazyLock<AchievementConfig> = LazyLock::new(|| {
+    let explicit_path = env::var("ACHIEVEMENTS_PATH").ok();
+    let path = explicit_path.clone().unwrap_or_else(|| "achievements.json".to_string());
+    match fs::read_to_string(&path) {
+        Ok(content) => match serde_json::from_str::<AchievementConfig>(&content) {
+            Ok(config) => {
+                info!("Loaded {} achievement(s) from {}", config.achievements.len(), path);
+                return config;
+            }
+            Err(e) => warn!("Ignoring achievements {}: {}", path, e),
+        },
+        // The file is optional unless it was configured explicitly.
+        Err(e) if explicit_path.is_some() => warn!("Failed to read achievements {}: {}", path, e),
+        Err(_) => {}
+    }
+    AchievementConfig::default()
+});
+
+pub fn definitions() -> &'static [AchievementDefinition] {
+    &CONFIG.achievements
+}
+
+#[derive(Debug, Clone)]
+pub struct UnlockedAchievement {
+    pub key: String,
+    pub name: String,
+}
+
+// What a kill meant for the killer beyond the plain counters.
+#[derive(Debug, Default)]
+pub struct KillFeats {
+    // Kills since the killer last died
+    pub streak: u32,
+    // Length of the multi-kill chain this kill extended (1 = not a multi-kill)
+    pub multi_kill: u32,
+    pub first_blood: bool,
+    pub weapon_record: bool,
+    pub unlocked: Vec<UnlockedAchievement>,
+}
+
+// Updates streaks, multi-kills, first blood and weapon records for a stored kill on `server_id`, then unlocks any
+// achievements the killer now qualifies for. Team kills and suicides end the victim's streak but earn nothing.
+// Streaks and records are kept per server; dying on any server ends the victim's streaks everywhere.
+pub fn record_kill(
+    conn: &mut Transaction,
+    server_id: &str,
+    kill_id: u64,
+    killer_id: u64,
+    victim_id: u64,
+    is_team_kill: bool,
+    match_id: Option<u64>,
+) -> Result<KillFeats, mysql::Error> {
+    conn.exec_drop(
+        "UPDATE PlayerStats SET current_streak = 0, multi_kill = 0 WHERE player_id = :pid",
+        params! { "pid" => victim_id },
+    )?;
+    if is_team_kill || killer_id == victim_id {
+        return Ok(KillFeats::default());
+    }
+
+    let killed_at: Option<String> = conn.exec_first(
+        "SELECT DATE_FORMAT(killed_at, '%Y-%m-%d %H:%i:%s.%f') FROM PlayerKills WHERE kill_id = :kid",
+        params! { "kid" => kill_id },
+    )?;
+    let Some(killed_at) = killed_at else {
+        return Ok(KillFeats::default());
+    };
+
+    // MySQL applies single-table SET clauses left to right, so later ones see the updated values.
+    conn.exec_drop(
+        r"UPDATE PlayerStats SET
+            current_streak = current_streak + 1,
+            best_streak = GREATEST(best_streak, current_streak),
+            multi_kill = IF(last_kill_at IS NOT NULL
+                AND CAST(:at AS DATETIME(3)) BETWEEN last_kill_at AND last_kill_at + INTERVAL :secs SECOND,
+                multi_kill + 1, 1),
+            best_multi_kill = GREATEST(best_multi_kill, multi_kill),
+            double_kills = double_kills + (multi_kill = 2),
+            triple_kills = triple_kills + (multi_kill = 3),
+            last_kill_at = CAST(:at AS DATETIME(3))
+        WHERE player_id = :pid AND server_id = :server",
+        params! { "at" => &killed_at, "secs" => CONFIG.multi_kill_seconds, "pid" => killer_id, "server" => server_id },
+    )?;
+
+    let mut feats = KillFeats::default();
+    if let Some(match_id) = match_id {
+        conn.exec_drop(
+            "UPDATE Matches SET first_blood_kill_id = :kid WHERE match_id = :mid AND first_blood_kill_id IS NULL",
+            params! { "kid" => kill_id, "mid" => match_id },
+        )?;
+        feats.first_blood = conn.affected_rows() == 1;
+    }
+
+    // Replaces the weapon's record only when this kill is longer; distance is assigned last so the
+    // other columns still compare against the old record.
+    conn.exec_drop(
+        r"INSERT INTO WeaponRecords (weapon, server_id, player_id, kill_id, distance, set_at)
+        SELECT weapon, server_id, killer_id, kill_id, distance, killed_at FROM PlayerKills
+        WHERE kill_id = :kid AND weapon <> '' AND distance IS NOT NULL
+        ON DUPLICATE KEY UPDATE
+            player_id = IF(VALUES(distance) > distance, VALUES(player_id), player_id),
+            kill_id = IF(VALUES(distance) > distance, VALUES(kill_id), kill_id),
+            set_at = IF(VALUES(distance) > distance, VALUES(set_at), set_at),
+            distance = GREATEST(distance, VALUES(distance))",
+        params! { "kid" => kill_id },
+    )?;
+    feats.weapon_record = conn.affected_rows() > 0;
+
+    conn.exec_drop(
+        r"UPDATE PlayerStats SET first_bloods = first_bloods + :fb, weapon_records = weapon_records + :wr
+        WHERE player_id = :pid AND server_id = :server",
+        params! { "fb" => feats.first_blood, "wr" => feats.weapon_record, "pid" => killer_id, "server" => server_id },
+    )?;
+
+    let counters: Option<(u32, u32)> = conn.exec_first(
+        "SELECT current_streak, multi_kill FROM PlayerStats WHERE player_id = :pid AND server_id = :server",
+        params! { "pid" => killer_id, "server" => server_id },
+    )?;
+    (feats.streak, feats.multi_kill) = counters.unwrap_or((0, 0));
+
+    feats.unlocked = unlock_achievements(conn, killer_id, kill_id, &killed_at)?;
+    Ok(feats)
+}
+
+// A player's streak and multi-kill on one server while their kills are replayed.
+#[derive(Default)]
+struct Chains {
+    streak: u32,
+    best_streak: u32,
+    multi_kill: u32,
+    best_multi_kill: u32,
+    // Milliseconds since the epoch, and as stored
+    last_kill_at: Option<(i64, String)>,
+}
+
+// Replays a player's kills and deaths from PlayerKills the way record_kill counts them, for when kills
+// were taken off the player (undoing a merge) and the best streak and multi-kill may have gone with them.
+pub fn recompute_streaks(conn: &mut Transaction, player_id: u64) -> Result<(), mysql::Error> {
+    let kills: Vec<(String, u64, u64, bool, i64, String)> = conn.exec(
+        r"SELECT server_id, killer_id, victim_id, is_team_kill,
+            CAST(UNIX_TIMESTAMP(killed_at) * 1000 AS SIGNED), DATE_FORMAT(killed_at, '%Y-%m-%d %H:%i:%s.%f')
+        FROM PlayerKills WHERE killer_id = :pid OR victim_id = :pid
+        ORDER BY killed_at, kill_id",
+        params! { "pid" => player_id },
+    )?;
+    let window = i64::from(CONFIG.multi_kill_seconds) * 1000;
+    let mut servers: BTreeMap<String, Chains> = BTreeMap::new();
+    for (server_id, killer_id, victim_id, is_team_kill, at, killed_at) in kills {
+        if victim_id == player_id {
+            for chains in servers.values_mut() {
+                chains.streak = 0;
+                chains.multi_kill = 0;
+            }
+        }
+        if killer_id != player_id || victim_id == player_id || is_team_kill {
+            continue;
+        }
+        let chains = servers.entry(server_id).or_default();
+        chains.streak += 1;
+        chains.best_streak = chains.best_streak.max(chains.streak);
+        let chained = chains.last_kill_at.as_ref().is_some_and(|(last, _)| (*last..=last + window).contains(&at));
+        chains.multi_kill = if chained { chains.multi_kill + 1 } else { 1 };
+        chains.best_multi_kill = chains.best_multi_kill.max(chains.multi_kill);
+        chains.last_kill_at = Some((at, killed_at));
+    }
+
+    conn.exec_drop(
+        r"UPDATE PlayerStats SET current_streak = 0, best_streak = 0, multi_kill = 0, best_multi_kill = 0, last_kill_at = NULL
+        WHERE player_id = :pid",
+        params! { "pid" => player_id },
+    )?;
+    conn.exec_batch(
+        r"UPDATE PlayerStats SET
+            current_streak = :streak,
+            best_streak = :best_streak,
+            multi_kill = :multi_kill,
+            best_multi_kill = :best_multi_kill,
+            last_kill_at = CAST(:at AS DATETIME(3))
+        WHERE player_id = :pid AND server_id = :server",
+        servers.iter().map(|(server_id, chains)| {
+            params! {
+                "pid" => player_id,
+                "server" => server_id,
+                "streak" => chains.streak,
+                "best_streak" => chains.best_streak,
+                "multi_kill" => chains.multi_kill,
+                "best_multi_kill" => chains.best_multi_kill,
+                "at" => chains.last_kill_at.as_ref().map(|(_, at)| at.as_str()),
+            }
+        }),
+    )
+}
+
+fn unlock_achievements(
+    conn: &mut Transaction,
+    player_id: u64,
+    kill_id: u64,
+    unlocked_at: &str,
+) -> Result<Vec<UnlockedAchievement>, mysql::Error> {
+    let unlocked: Vec<String> = conn.exec(
+        "SELECT achievement_key FROM PlayerAchievements WHERE player_id = :pid",
+        params! { "pid" => player_id },
+    )?;
+
+    let mut new = Vec::new();
+    for achievement in CONFIG.achievements.iter().filter(|a| !unlocked.contains(&a.key)) {
+        // Achievements are network-wide, so the stat is combined over the player's servers.
+        let value: Option<Option<f64>> = conn.exec_first(
+            format!(
+                "SELECT CAST({}({}) AS DOUBLE) FROM PlayerStats WHERE player_id = :pid",
+                achievement.stat.aggregate(),
+                achievement.stat.column()
+            ),
+            params! { "pid" => player_id },
+        )?;
+        if value.flatten().unwrap_or(0.0) < achievement.threshold {
+            continue;
+        }
+
+        conn.exec_drop(
+            r"INSERT IGNORE INTO PlayerAchievements (player_id, achievement_key, name, unlocked_at, kill_id)
+            VALUES (:pid, :key, :name, CAST(:at AS DATETIME(3)), :kid)",
+            params! {
+                "pid" => player_id,
+                "key" => &achievement.key,
+                "name" => &achievement.name,
+                "at" => unlocked_at,
+                "kid" => kill_id,
+            },
+        )?;
+        if conn.affected_rows() == 1 {
+            new.push(UnlockedAchievement { key: achievement.key.clone(), name: achievement.name.clone() });
+        }
+    }
+    Ok(new)
+}
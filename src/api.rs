@@ -0,0 +1,1198 @@
+use log::{error, info, warn};
+use mysql::{params, prelude::*, Pool, PooledConn, Row};
+use regex::Regex;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::io::{Read, Write};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, LazyLock};
+use std::thread;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::achievements;
+use crate::associations;
+use crate::chat_log::{self, escape_like, ChatSearch};
+use crate::discord;
+use crate::event_bus::{BusEvent, EventBus, Subscription, EVENT_TYPES};
+use crate::player_merge::{self, MergeError};
+use crate::rating;
+use crate::seasons;
+
+const DEFAULT_PER_PAGE: u32 = 25;
+const MAX_PER_PAGE: u32 = 100;
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+
+// Live streams send a keep-alive this often so proxies keep the connection open
+// and we notice clients that went away.
+const STREAM_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+// Timestamps are returned as ISO 8601 in the database server's local time.
+const SQL_ISO_FORMAT: &str = "%Y-%m-%dT%H:%i:%s";
+
+static DATETIME_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\d{4}-\d{2}-\d{2}(?:[ T]\d{2}:\d{2}(?::\d{2})?)?$").unwrap());
+
+#[derive(Clone)]
+pub struct ApiConfig {
+    pub bind: String,
+    pub threads: usize,
+    pub cache_seconds: u32,
+    // Bearer token for the moderation endpoints; they are disabled when unset.
+    pub admin_token: Option<String>,
+}
+
+impl ApiConfig {
+    // The API is optional; it is only started when API_BIND is set (e.g. "0.0.0.0:8080").
+    pub fn from_env() -> Option<Self> {
+        let bind = env::var("API_BIND").ok().filter(|b| !b.is_empty())?;
+        Some(Self {
+            bind,
+            threads: env::var("API_THREADS").ok().and_then(|v| v.parse().ok()).unwrap_or(4).max(1),
+            cache_seconds: env::var("API_CACHE_SECONDS").ok().and_then(|v| v.parse().ok()).unwrap_or(30),
+            admin_token: env::var("API_ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(400, message)
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        Self::new(404, message)
+    }
+}
+
+impl From<mysql::Error> for ApiError {
+    fn from(e: mysql::Error) -> Self {
+        error!("API database error: {}", e);
+        Self::new(500, "database error")
+    }
+}
+
+impl From<MergeError> for ApiError {
+    fn from(e: MergeError) -> Self {
+        match e {
+            MergeError::Database(e) => e.into(),
+            MergeError::SamePlayer => Self::bad_request(e.to_string()),
+            MergeError::PlayerNotFound(_) | MergeError::MergeNotFound(_) => Self::not_found(e.to_string()),
+            MergeError::MergedAway { .. } | MergeError::AlreadyUndone(_) | MergeError::LaterMerge(_) => {
+                Self::new(409, e.to_string())
+            }
+            MergeError::BadSnapshot(_) => {
+                error!("API merge error: {}", e);
+                Self::new(500, e.to_string())
+            }
+        }
+    }
+}
+
+enum Cache {
+    // Stats and feeds; cacheable by browsers and CDNs for API_CACHE_SECONDS
+    Public,
+    // Moderation data; never cached
+    Private,
+}
+
+type ApiResult = Result<(Value, Cache), ApiError>;
+
+struct Query(HashMap<String, String>);
+
+impl Query {
+    fn parse(raw: &str) -> Self {
+        Query(
+            raw.split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+                    (percent_decode(k), percent_decode(v))
+                })
+                .collect(),
+        )
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str).filter(|v| !v.is_empty())
+    }
+
+    fn datetime(&self, key: &str) -> Result<Option<String>, ApiError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(value) if DATETIME_REGEX.is_match(value) => Ok(Some(value.replace('T', " "))),
+            Some(_) => Err(ApiError::bad_request(format!(
+                "'{}' must be YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS",
+                key
+            ))),
+        }
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (escaped, bytes[i]) {
+            (Some(byte), _) => {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+            (None, b'+') => out.push(b' '),
+            (None, b) => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// ?page=1&per_page=25; we fetch one extra row to know whether there is a next page.
+struct Page {
+    page: u32,
+    per_page: u32,
+}
+
+impl Page {
+    fn from_query(query: &Query) -> Result<Self, ApiError> {
+        let page = match query.get("page") {
+            Some(v) => v.parse().ok().filter(|p| *p >= 1).ok_or_else(|| ApiError::bad_request("invalid 'page'"))?,
+            None => 1,
+        };
+        let per_page = match query.get("per_page") {
+            Some(v) => v
+                .parse()
+                .ok()
+                .filter(|p| (1..=MAX_PER_PAGE).contains(p))
+                .ok_or_else(|| ApiError::bad_request(format!("'per_page' must be 1-{}", MAX_PER_PAGE)))?,
+            None => DEFAULT_PER_PAGE,
+        };
+        Ok(Self { page, per_page })
+    }
+
+    fn limit(&self) -> u32 {
+        self.per_page + 1
+    }
+
+    fn offset(&self) -> u64 {
+        (self.page as u64 - 1) * self.per_page as u64
+    }
+
+    fn wrap(&self, mut rows: Vec<Value>) -> Value {
+        let has_more = rows.len() > self.per_page as usize;
+        rows.truncate(self.per_page as usize);
+        json!({
+            "data": rows,
+            "page": self.page,
+            "per_page": self.per_page,
+            "has_more": has_more,
+        })
+    }
+}
+
+pub fn run_api_server(config: ApiConfig, pool: Pool, bus: EventBus) {
+    let server = match Server::http(&config.bind) {
+        Ok(s) => Arc::new(s),
+        Err(e) => {
+            error!("Failed to start HTTP API on {}: {}", config.bind, e);
+            return;
+        }
+    };
+    info!("HTTP API listening on {}", config.bind);
+
+    let config = Arc::new(config);
+    let workers: Vec<_> = (0..config.threads)
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let pool = pool.clone();
+            let config = Arc::clone(&config);
+            let bus = bus.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle_request(request, &pool, &config, &bus);
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+fn handle_request(mut request: Request, pool: &Pool, config: &ApiConfig, bus: &EventBus) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((url.as_str(), ""));
+    let query = Query::parse(query);
+
+    // Live streams keep their connection open, so they get their own thread instead of a worker.
+    if *request.method() == Method::Get && (path == "/events/sse" || path == "/events/ws") {
+        start_stream(request, path, &query, bus, config);
+        return;
+    }
+
+    let result = match request.method() {
+        Method::Get | Method::Head => route(path, &query, &request, pool, config),
+        Method::Post => read_json_body(&mut request).and_then(|body| route_post(path, &body, &request, pool, config)),
+        _ => Err(ApiError::new(405, "only GET and POST are supported")),
+    };
+    respond(request, result, config);
+}
+
+fn respond(request: Request, result: ApiResult, config: &ApiConfig) {
+    let (status, body, cache) = match result {
+        Ok((body, cache)) => (200, body, cache),
+        Err(e) => (e.status, json!({ "error": e.message }), Cache::Private),
+    };
+    let body = body.to_string();
+
+    let cache_control = match cache {
+        Cache::Public => format!("public, max-age={}", config.cache_seconds),
+        Cache::Private => "private, no-store".to_string(),
+    };
+    let etag = format!("\"{:08x}\"", crc32fast::hash(body.as_bytes()));
+    let not_modified = status == 200
+        && request
+            .headers()
+            .iter()
+            .any(|h| h.field.equiv("If-None-Match") && h.value.as_str() == etag);
+
+    let response = if not_modified {
+        Response::from_data(Vec::new()).with_status_code(304)
+    } else {
+        Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header("Content-Type", "application/json"))
+    };
+    let response = response
+        .with_header(header("Cache-Control", &cache_control))
+        .with_header(header("ETag", &etag));
+
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to send API response: {}", e);
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+fn route(path: &str, query: &Query, request: &Request, pool: &Pool, config: &ApiConfig) -> ApiResult {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let mut conn = pool.get_conn()?;
+
+    match segments.as_slice() {
+        ["players", "search"] => search_players(&mut conn, query),
+        ["players", "merges"] => {
+            require_admin(request, config)?;
+            merge_list(&mut conn, query)
+        }
+        ["players", id] => player(&mut conn, parse_id(id)?, query.get("server")),
+        ["players", a, "vs", b] => head_to_head(&mut conn, parse_id(a)?, parse_id(b)?, query.get("server")),
+        ["players", id, "associates"] => {
+            require_admin(request, config)?;
+            associates(&mut conn, parse_id(id)?, query)
+        }
+        ["leaderboard"] => leaderboard(&mut conn, query),
+        ["servers"] => server_list(&mut conn),
+        ["seasons"] => season_list(&mut conn),
+        ["achievements"] => achievement_list(&mut conn),
+        ["records"] => weapon_records(&mut conn, query.get("server")),
+        ["weapons"] => weapons(&mut conn, query),
+        ["kills"] => kills(&mut conn, query),
+        ["chat"] => {
+            require_admin(request, config)?;
+            chat(&mut conn, query)
+        }
+        ["discord", discord_id] => {
+            require_admin(request, config)?;
+            discord_player(&mut conn, discord_id)
+        }
+        _ => Err(ApiError::not_found("no such endpoint")),
+    }
+}
+
+// Write endpoints; all of them are for trusted integrations (e.g. a Discord bot) and need the admin token.
+fn route_post(path: &str, body: &Value, request: &Request, pool: &Pool, config: &ApiConfig) -> ApiResult {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    require_admin(request, config)?;
+    let mut conn = pool.get_conn()?;
+
+    match segments.as_slice() {
+        ["discord", "link-codes"] => issue_link_code(&mut conn, body),
+        ["discord", "link"] => link_discord(&mut conn, body),
+        ["discord", "unlink"] => unlink_discord(&mut conn, body),
+        ["players", "merge"] => merge_players(&mut conn, body),
+        ["players", "unmerge"] => unmerge_players(&mut conn, body),
+        _ => Err(ApiError::not_found("no such endpoint")),
+    }
+}
+
+fn read_json_body(request: &mut Request) -> Result<Value, ApiError> {
+    let mut raw = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_BYTES)
+        .read_to_string(&mut raw)
+        .map_err(|_| ApiError::bad_request("request body must be UTF-8 JSON"))?;
+    serde_json::from_str(&raw).map_err(|e| ApiError::bad_request(format!("invalid JSON body: {}", e)))
+}
+
+fn body_str<'a>(body: &'a Value, key: &str) -> Result<&'a str, ApiError> {
+    body[key]
+        .as_str()
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| ApiError::bad_request(format!("'{}' is required", key)))
+}
+
+// ?type=kill,connect&player=Name&server=id; all optional.
+struct EventFilter {
+    types: Option<Vec<String>>,
+    player: Option<String>,
+    server: Option<String>,
+}
+
+impl EventFilter {
+    fn from_query(query: &Query) -> Result<Self, ApiError> {
+        let types = match query.get("type") {
+            Some(list) => {
+                let types: Vec<String> = list.split(',').map(|t| t.trim().to_lowercase()).collect();
+                if let Some(bad) = types.iter().find(|t| !EVENT_TYPES.contains(&t.as_str())) {
+                    return Err(ApiError::bad_request(format!(
+                        "unknown event type '{}' (expected {})",
+                        bad,
+                        EVENT_TYPES.join(", ")
+                    )));
+                }
+                Some(types)
+            }
+            None => None,
+        };
+        Ok(Self {
+            types,
+            player: query.get("player").map(str::to_string),
+            server: query.get("server").map(str::to_string),
+        })
+    }
+
+    fn matches(&self, event: &BusEvent) -> bool {
+        self.types.as_ref().is_none_or(|types| types.iter().any(|t| t == event.event_type()))
+            && self.player.as_ref().is_none_or(|name| event.involves(name))
+            && self.server.as_ref().is_none_or(|server| event.server_id() == server)
+    }
+}
+
+fn start_stream(request: Request, path: &str, query: &Query, bus: &EventBus, config: &ApiConfig) {
+    let filter = match EventFilter::from_query(query) {
+        Ok(f) => f,
+        Err(e) => return respond(request, Err(e), config),
+    };
+
+    if path == "/events/ws" {
+        let key = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Sec-WebSocket-Key"))
+            .map(|h| h.value.as_str().to_string());
+        let Some(key) = key else {
+            return respond(request, Err(ApiError::bad_request("expected a WebSocket upgrade request")), config);
+        };
+        let events = bus.subscribe("websocket");
+        thread::spawn(move || stream_websocket(request, &key, events, filter));
+    } else {
+        let events = bus.subscribe("sse");
+        thread::spawn(move || stream_sse(request, events, filter));
+    }
+}
+
+// Server-Sent Events: "event: <type>" + "data: <json>" per event, ": keep-alive" comments in between.
+fn stream_sse(request: Request, events: Subscription, filter: EventFilter) {
+    let mut writer = request.into_writer();
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    if writer.write_all(head.as_bytes()).and_then(|_| writer.flush()).is_err() {
+        return;
+    }
+
+    loop {
+        let chunk = match events.recv_timeout(STREAM_KEEP_ALIVE) {
+            Ok(event) if filter.matches(&event) => {
+                format!("event: {}\ndata: {}\n\n", event.event_type(), event.to_json())
+            }
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => ": keep-alive\n\n".to_string(),
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        if writer.write_all(chunk.as_bytes()).and_then(|_| writer.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+// WebSocket: one JSON text message per event, pings in between. Messages from the client are ignored.
+fn stream_websocket(request: Request, key: &str, events: Subscription, filter: EventFilter) {
+    let accept = tungstenite::handshake::derive_accept_key(key.as_bytes());
+    let response = Response::empty(101).with_header(header("Sec-WebSocket-Accept", &accept));
+    let stream = request.upgrade("websocket", response);
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+    loop {
+        let message = match events.recv_timeout(STREAM_KEEP_ALIVE) {
+            Ok(event) if filter.matches(&event) => Message::text(event.to_json().to_string()),
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => Message::Ping(Default::default()),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if socket.send(message).is_err() {
+            return;
+        }
+    }
+    let _ = socket.close(None);
+}
+
+// ?depth=1-3 (default 1)&min_score=0-1 (default 0.3)
+fn associates(conn: &mut PooledConn, player_id: u64, query: &Query) -> ApiResult {
+    let depth = match query.get("depth") {
+        Some(v) => v.parse().ok().filter(|d| (1..=3).contains(d)).ok_or_else(|| ApiError::bad_request("'depth' must be 1-3"))?,
+        None => 1,
+    };
+    let min_score = match query.get("min_score") {
+        Some(v) => v
+            .parse()
+            .ok()
+            .filter(|s| (0.0..=1.0).contains(s))
+            .ok_or_else(|| ApiError::bad_request("'min_score' must be between 0 and 1"))?,
+        None => 0.3,
+    };
+
+    let exists: Option<u64> =
+        conn.exec_first("SELECT player_id FROM Players WHERE player_id = :pid", params! { "pid" => player_id })?;
+    if exists.is_none() {
+        return Err(ApiError::not_found("player not found"));
+    }
+
+    let (nodes, edges) = associations::association_graph(conn, player_id, depth, min_score)?;
+    Ok((
+        json!({
+            "player_id": player_id,
+            "depth": depth,
+            "min_score": min_score,
+            "nodes": nodes.iter().map(|n| json!({
+                "player_id": n.player_id,
+                "name": n.name,
+                "banned": n.banned,
+                "depth": n.depth,
+            })).collect::<Vec<_>>(),
+            "edges": edges.iter().map(|e| json!({
+                "player_a": e.player_a,
+                "player_b": e.player_b,
+                "score": e.score,
+                "shared_ips": e.shared_ips,
+                "shared_guid": e.shared_guid,
+                "closest_seconds": e.closest_seconds,
+            })).collect::<Vec<_>>(),
+        }),
+        Cache::Private,
+    ))
+}
+
+fn issue_link_code(conn: &mut PooledConn, body: &Value) -> ApiResult {
+    let discord_id = body_str(body, "discord_id")?;
+    let code = discord::issue_link_code(conn, discord_id)?;
+    Ok((
+        json!({
+            "discord_id": discord_id,
+            "code": code,
+            "expires_in_minutes": discord::LINK_CODE_TTL_MINUTES,
+        }),
+        Cache::Private,
+    ))
+}
+
+// {"code": "...", "player_id": 42} - for linking outside the game, e.g. from a web panel.
+fn link_discord(conn: &mut PooledConn, body: &Value) -> ApiResult {
+    let code = body_str(body, "code")?;
+    let player_id = body["player_id"].as_u64().ok_or_else(|| ApiError::bad_request("'player_id' is required"))?;
+    let exists: Option<u64> =
+        conn.exec_first("SELECT player_id FROM Players WHERE player_id = :pid", params! { "pid" => player_id })?;
+    if exists.is_none() {
+        return Err(ApiError::not_found("player not found"));
+    }
+
+    let discord_id = discord::redeem_link_code(conn, code, player_id)?
+        .ok_or_else(|| ApiError::not_found("unknown or expired link code"))?;
+    Ok((json!({ "player_id": player_id, "discord_id": discord_id }), Cache::Private))
+}
+
+fn unlink_discord(conn: &mut PooledConn, body: &Value) -> ApiResult {
+    let discord_id = body_str(body, "discord_id")?;
+    if !discord::unlink_discord(conn, discord_id)? {
+        return Err(ApiError::not_found("Discord user is not linked"));
+    }
+    Ok((json!({ "discord_id": discord_id, "unlinked": true }), Cache::Private))
+}
+
+// {"source_id": 17, "target_id": 42, "by": "admin name"} - folds a duplicate player into the real one.
+fn merge_players(conn: &mut PooledConn, body: &Value) -> ApiResult {
+    let source = body["source_id"].as_u64().ok_or_else(|| ApiError::bad_request("'source_id' is required"))?;
+    let target = body["target_id"].as_u64().ok_or_else(|| ApiError::bad_request("'target_id' is required"))?;
+    let merged_by = body["by"].as_str().unwrap_or("api");
+    let report = player_merge::merge_players(conn, source, target, merged_by)?;
+    Ok((
+        json!({
+            "merge_id": report.merge_id,
+            "source_id": source,
+            "target_id": target,
+            "kills": report.kills,
+            "names": report.names,
+        }),
+        Cache::Private,
+    ))
+}
+
+// {"merge_id": 3, "by": "admin name"} - splits a merged player off again.
+fn unmerge_players(conn: &mut PooledConn, body: &Value) -> ApiResult {
+    let merge_id = body["merge_id"].as_u64().ok_or_else(|| ApiError::bad_request("'merge_id' is required"))?;
+    let undone_by = body["by"].as_str().unwrap_or("api");
+    let kills = player_merge::undo_merge(conn, merge_id, undone_by)?;
+    Ok((json!({ "merge_id": merge_id, "undone": true, "kills": kills }), Cache::Private))
+}
+
+// ?player=ID&page=1&per_page=25, most recent first.
+fn merge_list(conn: &mut PooledConn, query: &Query) -> ApiResult {
+    let page = Page::from_query(query)?;
+    let player_id = query.get("player").map(parse_id).transpose()?;
+    let data = player_merge::list_merges(conn, player_id, page.limit(), page.offset())?
+        .into_iter()
+        .map(|m| {
+            json!({
+                "merge_id": m.merge_id,
+                "source_id": m.source_id,
+                "target_id": m.target_id,
+                "merged_by": m.merged_by,
+                "merged_at": m.merged_at,
+                "undone_by": m.undone_by,
+                "undone_at": m.undone_at,
+            })
+        })
+        .collect();
+    Ok((page.wrap(data), Cache::Private))
+}
+
+// Stats lookup for a bot command: the linked player's profile.
+fn discord_player(conn: &mut PooledConn, discord_id: &str) -> ApiResult {
+    let player_id = discord::linked_player(conn, discord_id)?
+        .ok_or_else(|| ApiError::not_found("Discord user is not linked"))?;
+    let (profile, _) = player(conn, player_id, None)?;
+    Ok((profile, Cache::Private))
+}
+
+fn parse_id(value: &str) -> Result<u64, ApiError> {
+    value.parse().map_err(|_| ApiError::bad_request(format!("invalid player id '{}'", value)))
+}
+
+fn require_admin(request: &Request, config: &ApiConfig) -> Result<(), ApiError> {
+    let Some(ref token) = config.admin_token else {
+        return Err(ApiError::not_found("admin endpoints are disabled (API_ADMIN_TOKEN is not set)"));
+    };
+    let authorized = request
+        .headers()
+        .iter()
+        .filter(|h| h.field.equiv("Authorization"))
+        .filter_map(|h| h.value.as_str().strip_prefix("Bearer "))
+        .any(|given| token_matches(given, token));
+    if authorized { Ok(()) } else { Err(ApiError::new(401, "missing or invalid bearer token")) }
+}
+
+// Compares a presented secret with the configured one in constant time, so response times do not
+// reveal how much of it was right. Hashing first makes the length irrelevant too.
+pub fn token_matches(given: &str, expected: &str) -> bool {
+    Sha256::digest(given).ct_eq(&Sha256::digest(expected)).into()
+}
+
+// Most recently used name for a player id column.
+fn name_sql(column: &str) -> String {
+    format!(
+        "(SELECT n.username FROM PlayerNames n WHERE n.player_id = {} ORDER BY n.last_used DESC LIMIT 1)",
+        column
+    )
+}
+
+// kills, deaths, team_kills, kd_ratio, longest_kill, favorite_weapon
+type StatsRow = (Option<u32>, Option<u32>, Option<u32>, Option<f64>, Option<f64>, Option<String>);
+// current_streak, best_streak, best_multi_kill, double_kills, triple_kills, first_bloods, weapon_records
+type FeatsRow = (u32, u32, u32, u32, u32, u32, u32);
+// weapon, kills, team_kills, longest_kill
+type PlayerWeaponRow = (String, Option<u32>, Option<u32>, Option<f64>);
+
+// Stats are network-wide unless `server` picks one server; names, achievements and rating always are.
+fn player(conn: &mut PooledConn, player_id: u64, server: Option<&str>) -> ApiResult {
+    let player: Option<(u64, String, String, String)> = conn.exec_first(
+        format!(
+            "SELECT player_id, reforger_id, DATE_FORMAT(first_seen, '{iso}'), DATE_FORMAT(last_seen, '{iso}')
+            FROM Players WHERE player_id = :pid",
+            iso = SQL_ISO_FORMAT
+        ),
+        params! { "pid" => player_id },
+    )?;
+    let (player_id, reforger_id, first_seen, last_seen) =
+        player.ok_or_else(|| ApiError::not_found("player not found"))?;
+
+    let names: Vec<(String, String, String)> = conn.exec(
+        format!(
+            "SELECT username, DATE_FORMAT(first_used, '{iso}'), DATE_FORMAT(last_used, '{iso}')
+            FROM PlayerNames WHERE player_id = :pid ORDER BY last_used DESC",
+            iso = SQL_ISO_FORMAT
+        ),
+        params! { "pid" => player_id },
+    )?;
+
+    let stats: Option<StatsRow> = conn.exec_first(
+        r"SELECT SUM(s.total_kills), SUM(s.total_deaths), SUM(s.total_team_kills),
+            CASE WHEN SUM(s.total_deaths) = 0 THEN SUM(s.total_kills) ELSE SUM(s.total_kills) / SUM(s.total_deaths) END,
+            MAX(s.longest_kill),
+            COALESCE(MAX(s.favorite_weapon), (SELECT w.weapon FROM PlayerWeaponStats w
+                WHERE w.player_id = :pid AND (:server IS NULL OR w.server_id = :server)
+                GROUP BY w.weapon ORDER BY SUM(w.total_kills) DESC LIMIT 1))
+        FROM PlayerStats s
+        WHERE s.player_id = :pid AND (:server IS NULL OR s.server_id = :server)
+        HAVING COUNT(*) > 0",
+        params! { "pid" => player_id, "server" => server },
+    )?;
+    let stats = stats.map(|(kills, deaths, team_kills, kd, longest, favorite)| {
+        json!({
+            "kills": kills,
+            "deaths": deaths,
+            "team_kills": team_kills,
+            "kd_ratio": kd,
+            "longest_kill": longest,
+            "favorite_weapon": favorite,
+        })
+    });
+
+    let feats: Option<FeatsRow> = conn.exec_first(
+        r"SELECT MAX(current_streak), MAX(best_streak), MAX(best_multi_kill), SUM(double_kills), SUM(triple_kills),
+            SUM(first_bloods), SUM(weapon_records)
+        FROM PlayerStats
+        WHERE player_id = :pid AND (:server IS NULL OR server_id = :server)
+        HAVING COUNT(*) > 0",
+        params! { "pid" => player_id, "server" => server },
+    )?;
+    let feats = feats.map(|(current, best, multi, doubles, triples, first_bloods, records)| {
+        json!({
+            "current_streak": current,
+            "best_streak": best,
+            "best_multi_kill": multi,
+            "double_kills": doubles,
+            "triple_kills": triples,
+            "first_bloods": first_bloods,
+            "weapon_records": records,
+        })
+    });
+
+    let unlocked: Vec<(String, String, String)> = conn.exec(
+        format!(
+            "SELECT achievement_key, name, DATE_FORMAT(unlocked_at, '{iso}')
+            FROM PlayerAchievements WHERE player_id = :pid ORDER BY unlocked_at",
+            iso = SQL_ISO_FORMAT
+        ),
+        params! { "pid" => player_id },
+    )?;
+
+    let rating: Option<(f64, f64, u32)> = conn.exec_first(
+        format!(
+            "SELECT r.rating, {deviation}, r.games FROM PlayerRatings r WHERE r.player_id = :pid",
+            deviation = rating::current_deviation_sql("r")
+        ),
+        params! { "pid" => player_id },
+    )?;
+    let rating = rating.map(|(rating, deviation, games)| {
+        json!({
+            "rating": rating,
+            "deviation": deviation,
+            "games": games,
+        })
+    });
+
+    let weapons: Vec<PlayerWeaponRow> = conn.exec(
+        r"SELECT weapon, SUM(total_kills), SUM(total_team_kills), MAX(longest_kill)
+        FROM PlayerWeaponStats
+        WHERE player_id = :pid AND (:server IS NULL OR server_id = :server)
+        GROUP BY weapon
+        ORDER BY SUM(total_kills) DESC LIMIT 10",
+        params! { "pid" => player_id, "server" => server },
+    )?;
+
+    Ok((
+        json!({
+            "player_id": player_id,
+            "server": server,
+            "reforger_id": reforger_id,
+            "name": names.first().map(|(n, _, _)| n.clone()),
+            "first_seen": first_seen,
+            "last_seen": last_seen,
+            "names": names.iter().map(|(name, first, last)| json!({
+                "name": name,
+                "first_used": first,
+                "last_used": last,
+            })).collect::<Vec<_>>(),
+            "stats": stats,
+            "feats": feats,
+            "achievements": unlocked.iter().map(|(key, name, at)| json!({
+                "key": key,
+                "name": name,
+                "unlocked_at": at,
+            })).collect::<Vec<_>>(),
+            "rating": rating,
+            "top_weapons": weapons.iter().map(|(weapon, kills, team_kills, longest)| json!({
+                "weapon": weapon,
+                "kills": kills,
+                "team_kills": team_kills,
+                "longest_kill": longest,
+            })).collect::<Vec<_>>(),
+        }),
+        Cache::Public,
+    ))
+}
+
+fn search_players(conn: &mut PooledConn, query: &Query) -> ApiResult {
+    let name = query.get("name").ok_or_else(|| ApiError::bad_request("'name' is required"))?;
+    let page = Page::from_query(query)?;
+
+    // One row per player, using the best-matching name they have used most recently.
+    let rows: Vec<(u64, String, Option<String>, String)> = conn.exec(
+        format!(
+            "SELECT n.player_id, MAX(n.username), {current}, DATE_FORMAT(MAX(n.last_used), '{iso}')
+            FROM PlayerNames n
+            WHERE n.username LIKE :pattern
+            GROUP BY n.player_id
+            ORDER BY MAX(n.last_used) DESC
+            LIMIT :limit OFFSET :offset",
+            current = name_sql("n.player_id"),
+            iso = SQL_ISO_FORMAT
+        ),
+        params! {
+            "pattern" => format!("%{}%", escape_like(name)),
+            "limit" => page.limit(),
+            "offset" => page.offset(),
+        },
+    )?;
+
+    let data = rows
+        .into_iter()
+        .map(|(player_id, matched, current, last_used)| {
+            json!({
+                "player_id": player_id,
+                "name": current,
+                "matched_name": matched,
+                "last_used": last_used,
+            })
+        })
+        .collect();
+    Ok((page.wrap(data), Cache::Public))
+}
+
+// Player totals in SeasonPlayerStats and PeriodPlayerStats, summed over the selected servers.
+const PERIOD_COUNTERS: &str =
+    "SUM(kills) AS kills, SUM(deaths) AS deaths, SUM(team_kills) AS team_kills, MAX(longest_kill) AS longest_kill";
+
+// player_id, name, kills, deaths, team_kills, kd_ratio, longest_kill, rating, rating_deviation
+type LeaderboardRow =
+    (u64, Option<String>, Option<u64>, Option<u64>, Option<u64>, Option<f64>, Option<f64>, Option<f64>, Option<f64>);
+
+fn leaderboard(conn: &mut PooledConn, query: &Query) -> ApiResult {
+    let page = Page::from_query(query)?;
+    let deviation = rating::current_deviation_sql("r");
+    let order = match query.get("metric").unwrap_or("kills") {
+        "kills" => "l.kills".to_string(),
+        "deaths" => "l.deaths".to_string(),
+        "kd" | "kd_ratio" => "kd_ratio".to_string(),
+        "longest_kill" => "l.longest_kill".to_string(),
+        "team_kills" => "l.team_kills".to_string(),
+        // Conservative skill estimate, so players with few rated kills don't top the board on luck.
+        "rating" => format!("r.rating - 2 * {}", deviation),
+        other => return Err(ApiError::bad_request(format!("unknown metric '{}'", other))),
+    };
+
+    // Lifetime totals come from PlayerStats; calendar periods and seasons from their own tables.
+    // Every source has a row per player and server, summed over all servers unless `server` picks one.
+    let mut season_id = None;
+    let (counters, table, filter) = match query.get("period").unwrap_or("all") {
+        "all" => (
+            "SUM(total_kills) AS kills, SUM(total_deaths) AS deaths, SUM(total_team_kills) AS team_kills,
+                MAX(longest_kill) AS longest_kill",
+            "PlayerStats",
+            "TRUE".to_string(),
+        ),
+        "season" => {
+            season_id = match query.get("season") {
+                Some(id) => Some(parse_id(id)?),
+                None => Some(seasons::current_season(conn)?.ok_or_else(|| ApiError::not_found("no running season"))?),
+            };
+            (PERIOD_COUNTERS, "SeasonPlayerStats", "season_id = :season".to_string())
+        }
+        period @ ("day" | "week" | "month") => (
+            PERIOD_COUNTERS,
+            "PeriodPlayerStats",
+            format!(
+                "period_type = '{period}' AND period_start = {start}",
+                period = period.to_uppercase(),
+                start = seasons::period_start_sql(&format!("'{}'", period.to_uppercase()), "COALESCE(:date, NOW())")
+            ),
+        ),
+        other => return Err(ApiError::bad_request(format!("unknown period '{}'", other))),
+    };
+
+    let rows: Vec<LeaderboardRow> = conn.exec(
+        format!(
+            "SELECT l.player_id, {name}, l.kills, l.deaths, l.team_kills,
+                CASE WHEN l.deaths = 0 THEN l.kills ELSE l.kills / l.deaths END AS kd_ratio, l.longest_kill,
+                r.rating, {deviation}
+            FROM (
+                SELECT player_id, {counters} FROM {table}
+                WHERE {filter} AND (:server IS NULL OR server_id = :server)
+                GROUP BY player_id
+            ) l
+            LEFT JOIN PlayerRatings r ON r.player_id = l.player_id
+            ORDER BY {order} DESC, l.player_id
+            LIMIT :limit OFFSET :offset",
+            name = name_sql("l.player_id"),
+            deviation = deviation,
+            counters = counters,
+            table = table,
+            filter = filter,
+            order = order
+        ),
+        params! {
+            "date" => query.datetime("date")?,
+            "season" => season_id,
+            "server" => query.get("server"),
+            "limit" => page.limit(),
+            "offset" => page.offset(),
+        },
+    )?;
+
+    let data = rows
+        .into_iter()
+        .enumerate()
+        .map(|(i, (player_id, name, kills, deaths, team_kills, kd, longest, rating, rating_deviation))| {
+            json!({
+                "rank": page.offset() + i as u64 + 1,
+                "player_id": player_id,
+                "name": name,
+                "kills": kills.unwrap_or(0),
+                "deaths": deaths.unwrap_or(0),
+                "team_kills": team_kills.unwrap_or(0),
+                "kd_ratio": kd.unwrap_or(0.0),
+                "longest_kill": longest.unwrap_or(0.0),
+                "rating": rating,
+                "rating_deviation": rating_deviation,
+            })
+        })
+        .collect();
+    Ok((page.wrap(data), Cache::Public))
+}
+
+fn achievement_list(conn: &mut PooledConn) -> ApiResult {
+    let counts: Vec<(String, u64)> =
+        conn.query("SELECT achievement_key, COUNT(*) FROM PlayerAchievements GROUP BY achievement_key")?;
+
+    let data: Vec<Value> = achievements::definitions()
+        .iter()
+        .map(|a| {
+            json!({
+                "key": a.key,
+                "name": a.name,
+                "description": a.description,
+                "stat": a.stat.column(),
+                "threshold": a.threshold,
+                "players": counts.iter().find(|(key, _)| *key == a.key).map(|(_, n)| *n).unwrap_or(0),
+            })
+        })
+        .collect();
+    Ok((json!({ "data": data }), Cache::Public))
+}
+
+// weapon, server_id, player_id, name, kill_id, distance, set_at
+type RecordRow = (String, String, u64, Option<String>, u64, f64, String);
+
+// Records are kept per server; without `server` each weapon's best record across all servers is listed
+// (the earlier one on a tie).
+fn weapon_records(conn: &mut PooledConn, server: Option<&str>) -> ApiResult {
+    let rows: Vec<RecordRow> = conn.exec(
+        format!(
+            "SELECT r.weapon, r.server_id, r.player_id, {name}, r.kill_id, r.distance, DATE_FORMAT(r.set_at, '{iso}')
+            FROM WeaponRecords r
+            WHERE (:server IS NULL OR r.server_id = :server)
+                AND NOT EXISTS (SELECT 1 FROM WeaponRecords o
+                    WHERE o.weapon = r.weapon AND (:server IS NULL OR o.server_id = :server)
+                        AND (o.distance > r.distance OR (o.distance = r.distance AND o.set_at < r.set_at)))
+            ORDER BY r.distance DESC",
+            name = name_sql("r.player_id"),
+            iso = SQL_ISO_FORMAT
+        ),
+        params! { "server" => server },
+    )?;
+
+    let data: Vec<Value> = rows
+        .into_iter()
+        .map(|(weapon, server_id, player_id, name, kill_id, distance, set_at)| {
+            json!({
+                "weapon": weapon,
+                "server": server_id,
+                "player_id": player_id,
+                "name": name,
+                "kill_id": kill_id,
+                "distance": distance,
+                "set_at": set_at,
+            })
+        })
+        .collect();
+    Ok((json!({ "data": data }), Cache::Public))
+}
+
+// server_id, name, first_started, last_started, kills, players, last_kill
+type ServerRow = (String, String, String, String, u64, u64, Option<String>);
+
+fn server_list(conn: &mut PooledConn) -> ApiResult {
+    let rows: Vec<ServerRow> = conn.query(format!(
+        "SELECT s.server_id, s.name, DATE_FORMAT(s.first_started, '{iso}'), DATE_FORMAT(s.last_started, '{iso}'),
+            (SELECT COUNT(*) FROM PlayerKills k WHERE k.server_id = s.server_id),
+            (SELECT COUNT(DISTINCT c.player_id) FROM ConnectionHistory c WHERE c.server_id = s.server_id),
+            (SELECT DATE_FORMAT(MAX(k.killed_at), '{iso}') FROM PlayerKills k WHERE k.server_id = s.server_id)
+        FROM Servers s
+        ORDER BY s.server_id",
+        iso = SQL_ISO_FORMAT
+    ))?;
+
+    let data: Vec<Value> = rows
+        .into_iter()
+        .map(|(server_id, name, first_started, last_started, kills, players, last_kill)| {
+            json!({
+                "server": server_id,
+                "name": name,
+                "first_started": first_started,
+                "last_started": last_started,
+                "kills": kills,
+                "players": players,
+                "last_kill": last_kill,
+            })
+        })
+        .collect();
+    Ok((json!({ "data": data }), Cache::Public))
+}
+
+// season_id, name, starts_at, ends_at, archived_at, players
+type SeasonRow = (u64, String, String, Option<String>, Option<String>, u64);
+
+fn season_list(conn: &mut PooledConn) -> ApiResult {
+    let rows: Vec<SeasonRow> = conn.query(format!(
+        "SELECT s.season_id, s.name, DATE_FORMAT(s.starts_at, '{iso}'), DATE_FORMAT(s.ends_at, '{iso}'),
+            DATE_FORMAT(s.archived_at, '{iso}'),
+            (SELECT COUNT(DISTINCT sp.player_id) FROM SeasonPlayerStats sp WHERE sp.season_id = s.season_id)
+        FROM Seasons s
+        ORDER BY s.starts_at DESC",
+        iso = SQL_ISO_FORMAT
+    ))?;
+
+    let data: Vec<Value> = rows
+        .into_iter()
+        .map(|(season_id, name, starts_at, ends_at, archived_at, players)| {
+            json!({
+                "season_id": season_id,
+                "name": name,
+                "starts_at": starts_at,
+                "ends_at": ends_at,
+                "archived": archived_at.is_some(),
+                "archived_at": archived_at,
+                "players": players,
+            })
+        })
+        .collect();
+    Ok((json!({ "data": data }), Cache::Public))
+}
+
+// weapon, kills, team_kills, average_distance, longest_kill, players
+type WeaponRow = (String, u64, u64, Option<f64>, Option<f64>, u64);
+
+fn weapons(conn: &mut PooledConn, query: &Query) -> ApiResult {
+    let page = Page::from_query(query)?;
+    let rows: Vec<WeaponRow> = conn.exec(
+        r"SELECT weapon, SUM(total_kills), SUM(total_team_kills),
+            SUM(total_distance) / NULLIF(SUM(total_kills), 0), MAX(longest_kill), COUNT(DISTINCT player_id)
+        FROM PlayerWeaponStats
+        WHERE weapon <> '' AND (:server IS NULL OR server_id = :server)
+        GROUP BY weapon
+        ORDER BY SUM(total_kills) DESC, weapon
+        LIMIT :limit OFFSET :offset",
+        params! { "server" => query.get("server"), "limit" => page.limit(), "offset" => page.offset() },
+    )?;
+
+    let data = rows
+        .into_iter()
+        .map(|(weapon, kills, team_kills, average, longest, players)| {
+            json!({
+                "weapon": weapon,
+                "kills": kills,
+                "team_kills": team_kills,
+                "average_distance": average,
+                "longest_kill": longest,
+                "players": players,
+            })
+        })
+        .collect();
+    Ok((page.wrap(data), Cache::Public))
+}
+
+fn kills(conn: &mut PooledConn, query: &Query) -> ApiResult {
+    let page = Page::from_query(query)?;
+    let since = query.datetime("since")?;
+
+    let rows: Vec<Row> = conn.exec(
+        format!(
+            "SELECT k.kill_id, k.server_id, k.killer_id, {killer} AS killer_name, k.victim_id, {victim} AS victim_name,
+                k.weapon, k.distance, k.is_team_kill, k.killer_faction, k.victim_faction, k.match_id,
+                DATE_FORMAT(k.killed_at, '{iso}') AS killed_at
+            FROM PlayerKills k
+            WHERE (:since IS NULL OR k.killed_at >= :since)
+                AND (:server IS NULL OR k.server_id = :server)
+            ORDER BY k.killed_at DESC, k.kill_id DESC
+            LIMIT :limit OFFSET :offset",
+            killer = name_sql("k.killer_id"),
+            victim = name_sql("k.victim_id"),
+            iso = SQL_ISO_FORMAT
+        ),
+        params! {
+            "since" => since,
+            "server" => query.get("server"),
+            "limit" => page.limit(),
+            "offset" => page.offset(),
+        },
+    )?;
+
+    let data = rows
+        .into_iter()
+        .map(|mut row| {
+            json!({
+                "kill_id": row.take::<u64, _>("kill_id"),
+                "server": row.take::<String, _>("server_id"),
+                "killer_id": row.take::<u64, _>("killer_id"),
+                "killer_name": row.take::<Option<String>, _>("killer_name").flatten(),
+                "victim_id": row.take::<u64, _>("victim_id"),
+                "victim_name": row.take::<Option<String>, _>("victim_name").flatten(),
+                "weapon": row.take::<String, _>("weapon").filter(|w| !w.is_empty()),
+                "distance": row.take::<Option<f64>, _>("distance").flatten(),
+                "is_team_kill": row.take::<bool, _>("is_team_kill").unwrap_or(false),
+                "killer_faction": row.take::<Option<String>, _>("killer_faction").flatten().filter(|f| !f.is_empty()),
+                "victim_faction": row.take::<Option<String>, _>("victim_faction").flatten().filter(|f| !f.is_empty()),
+                "match_id": row.take::<Option<u64>, _>("match_id").flatten(),
+                "killed_at": row.take::<String, _>("killed_at"),
+            })
+        })
+        .collect();
+    Ok((page.wrap(data), Cache::Public))
+}
+
+fn head_to_head(conn: &mut PooledConn, a: u64, b: u64, server: Option<&str>) -> ApiResult {
+    let players: Vec<(u64, Option<String>)> = conn.exec(
+        format!(
+            "SELECT p.player_id, {name} FROM Players p WHERE p.player_id IN (:a, :b)",
+            name = name_sql("p.player_id")
+        ),
+        params! { "a" => a, "b" => b },
+    )?;
+    let name_of = |id: u64| players.iter().find(|(pid, _)| *pid == id).map(|(_, name)| name.clone());
+    let (Some(name_a), Some(name_b)) = (name_of(a), name_of(b)) else {
+        return Err(ApiError::not_found("player not found"));
+    };
+
+    let totals: Vec<(u64, u32, Option<String>)> = conn.exec(
+        format!(
+            "SELECT killer_id, SUM(total_kills), DATE_FORMAT(MAX(last_kill), '{iso}')
+            FROM PlayerVsPlayerStats
+            WHERE ((killer_id = :a AND victim_id = :b) OR (killer_id = :b AND victim_id = :a))
+                AND (:server IS NULL OR server_id = :server)
+            GROUP BY killer_id",
+            iso = SQL_ISO_FORMAT
+        ),
+        params! { "a" => a, "b" => b, "server" => server },
+    )?;
+    let weapons: Vec<(u64, String, u64)> = conn.exec(
+        r"SELECT killer_id, weapon, COUNT(*)
+        FROM PlayerKills
+        WHERE ((killer_id = :a AND victim_id = :b) OR (killer_id = :b AND victim_id = :a))
+            AND (:server IS NULL OR server_id = :server)
+        GROUP BY killer_id, weapon
+        ORDER BY COUNT(*) DESC",
+        params! { "a" => a, "b" => b, "server" => server },
+    )?;
+
+    let side = |id: u64, name: Option<String>| {
+        let (kills, last_kill) = totals
+            .iter()
+            .find(|(killer, _, _)| *killer == id)
+            .map(|(_, kills, last)| (*kills, last.clone()))
+            .unwrap_or((0, None));
+        json!({
+            "player_id": id,
+            "name": name,
+            "kills": kills,
+            "last_kill": last_kill,
+            "weapons": weapons.iter().filter(|(killer, _, _)| *killer == id).map(|(_, weapon, count)| json!({
+                "weapon": weapon,
+                "kills": count,
+            })).collect::<Vec<_>>(),
+        })
+    };
+
+    Ok((json!({ "server": server, "player_a": side(a, name_a), "player_b": side(b, name_b) }), Cache::Public))
+}
+
+fn chat(conn: &mut PooledConn, query: &Query) -> ApiResult {
+    let page = Page::from_query(query)?;
+    let search = ChatSearch {
+        player: query.get("player").map(str::to_string),
+        server: query.get("server").map(str::to_string),
+        since: query.datetime("since")?,
+        until: query.datetime("until")?,
+        keyword: query.get("keyword").map(str::to_string),
+        limit: page.limit(),
+        offset: page.offset(),
+    };
+
+    let data = chat_log::search_chat(conn, &search)?
+        .into_iter()
+        .map(|m| {
+            json!({
+                "message_id": m.message_id,
+                "player_id": m.player_id,
+                "name": m.username,
+                "server": m.server_id,
+                "channel": m.channel,
+                "message": m.message,
+                "sent_at": m.sent_at.replace(' ', "T"),
+            })
+        })
+        .collect();
+    Ok((page.wrap(data), Cache::Private))
+}
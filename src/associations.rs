@@ -0,0 +1,278 @@
+use mysql::{params, prelude::*, PooledConn, Transaction};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::sync::LazyLock;
+
+use crate::player_monitor::PlayerConnection;
+use crate::tail;
+
+struct Settings {
+    // Only connections this recent are compared for shared IPs.
+    lookback_days: u32,
+    // Two accounts used from the same IP within this many hours of each other are linked more strongly.
+    window_hours: u64,
+}
+
+static SETTINGS: LazyLock<Settings> = LazyLock::new(|| Settings {
+    lookback_days: env::var("ASSOCIATION_LOOKBACK_DAYS").ok().and_then(|v| v.parse().ok()).unwrap_or(90),
+    window_hours: env::var("ASSOCIATION_WINDOW_HOURS").ok().and_then(|v| v.parse().ok()).unwrap_or(24),
+});
+
+#[derive(Debug, Clone)]
+pub struct BanEvasionAlert {
+    pub server_id: String,
+    pub username: String,
+    pub reforger_id: String,
+    pub banned_player_id: u64,
+    pub banned_name: Option<String>,
+    pub reason: Option<String>,
+}
+
+// What storing a connection found out about the player.
+pub struct RecordedConnection {
+    // This is the Reforger ID's first stored connection
+    pub is_new_player: bool,
+    pub ban_evasion: Option<BanEvasionAlert>,
+}
+
+// Stores the connection, refreshes the player's association edges and, for a Reforger ID we have
+// never seen, checks whether it shares an IP or GUID with a banned player. None when the connection's
+// event id is already in ConnectionHistory; nothing is changed then.
+pub fn record_connection(
+    conn: &mut Transaction,
+    player_id: u64,
+    player: &PlayerConnection,
+) -> Result<Option<RecordedConnection>, mysql::Error> {
+    let guid = Some(player.battleye_guid.as_str()).filter(|g| !g.is_empty());
+    // connected_at is when the line was logged, so gaps between connections compare like kill times.
+    conn.exec_drop(
+        format!(
+            r"INSERT IGNORE INTO ConnectionHistory (player_id, ip_address, battleye_guid, username, server_id, connected_at, event_id)
+            VALUES (:pid, :ip, :guid, :uname, :server, {logged_at}, :event_id)",
+            logged_at = tail::LOGGED_AT_SQL
+        ),
+        params! {
+            "server" => player.server_id.as_str(),
+            "event_id" => player.event_id.as_str(),
+            "pid" => player_id,
+            "ip" => player.ip_address.as_str(),
+            "guid" => guid,
+            "uname" => player.username.as_str(),
+            "log_date" => player.logged.date.as_deref(),
+            "log_time" => player.logged.time.as_deref(),
+        },
+    )?;
+    // uq_event_id: already stored by an earlier read of the same line
+    if conn.affected_rows() == 0 {
+        return Ok(None);
+    }
+
+    // Counted here rather than from the Players upsert, which a connection read again no longer inserts.
+    let connections: Option<u64> = conn.exec_first(
+        "SELECT COUNT(*) FROM ConnectionHistory WHERE player_id = :pid",
+        params! { "pid" => player_id },
+    )?;
+    let is_new_player = connections == Some(1);
+
+    update_associations(conn, player_id)?;
+
+    let ban_evasion = if is_new_player { check_ban_evasion(conn, player_id, player, guid)? } else { None };
+    Ok(Some(RecordedConnection { is_new_player, ban_evasion }))
+}
+
+#[derive(Default)]
+struct Edge {
+    // Closest pair of connections (seconds apart) for each shared IP
+    ip_gaps: Vec<u64>,
+    shared_guid: bool,
+}
+
+// Rough likelihood that two accounts belong to the same person. A shared BattlEye GUID means the same
+// game licence; a shared IP alone may be a household or carrier NAT unless the accounts were used close together.
+fn alt_score(edge: &Edge, window_seconds: u64) -> f64 {
+    let mut unrelated: f64 = if edge.shared_guid { 0.1 } else { 1.0 };
+    for gap in &edge.ip_gaps {
+        unrelated *= if *gap <= window_seconds { 0.5 } else { 0.7 };
+    }
+    1.0 - unrelated
+}
+
+fn update_associations<C: Queryable>(conn: &mut C, player_id: u64) -> Result<(), mysql::Error> {
+    let ip_links: Vec<(u64, u64)> = conn.exec(
+        r"SELECT o.player_id, MIN(ABS(TIMESTAMPDIFF(SECOND, h.connected_at, o.connected_at)))
+        FROM ConnectionHistory h
+        JOIN ConnectionHistory o ON o.ip_address = h.ip_address AND o.player_id <> h.player_id
+        WHERE h.player_id = :pid
+            AND h.connected_at >= NOW() - INTERVAL :days DAY
+            AND o.connected_at >= NOW() - INTERVAL :days DAY
+        GROUP BY o.player_id, h.ip_address",
+        params! { "pid" => player_id, "days" => SETTINGS.lookback_days },
+    )?;
+    // GUIDs are tied to the game licence, so they link accounts regardless of when they were used.
+    let guid_links: Vec<u64> = conn.exec(
+        r"SELECT DISTINCT o.player_id
+        FROM ConnectionHistory h
+        JOIN ConnectionHistory o ON o.battleye_guid = h.battleye_guid AND o.player_id <> h.player_id
+        WHERE h.player_id = :pid AND h.battleye_guid IS NOT NULL",
+        params! { "pid" => player_id },
+    )?;
+
+    let mut edges: BTreeMap<u64, Edge> = BTreeMap::new();
+    for (other, gap) in ip_links {
+        edges.entry(other).or_default().ip_gaps.push(gap);
+    }
+    for other in guid_links {
+        edges.entry(other).or_default().shared_guid = true;
+    }
+
+    let window_seconds = SETTINGS.window_hours * 3600;
+    conn.exec_batch(
+        r"INSERT INTO PlayerAssociations (player_a, player_b, shared_ips, shared_guid, closest_seconds, score)
+        VALUES (LEAST(:pid, :other), GREATEST(:pid, :other), :ips, :guid, :closest, :score)
+        ON DUPLICATE KEY UPDATE
+            shared_ips = VALUES(shared_ips),
+            shared_guid = VALUES(shared_guid),
+            closest_seconds = VALUES(closest_seconds),
+            score = VALUES(score)",
+        edges.iter().map(|(other, edge)| {
+            params! {
+                "pid" => player_id,
+                "other" => other,
+                "ips" => edge.ip_gaps.len(),
+                "guid" => edge.shared_guid,
+                "closest" => edge.ip_gaps.iter().min().copied(),
+                "score" => alt_score(edge, window_seconds),
+            }
+        }),
+    )
+}
+
+fn check_ban_evasion<C: Queryable>(
+    conn: &mut C,
+    player_id: u64,
+    player: &PlayerConnection,
+    guid: Option<&str>,
+) -> Result<Option<BanEvasionAlert>, mysql::Error> {
+    let banned: Option<(u64, Option<String>, Option<String>)> = conn.exec_first(
+        r"SELECT a.player_id,
+            (SELECT n.username FROM PlayerNames n WHERE n.player_id = a.player_id ORDER BY n.last_used DESC LIMIT 1),
+            a.reason
+        FROM ModerationActions a
+        WHERE a.player_id IS NOT NULL AND a.player_id <> :pid
+            AND (a.action_type = 'BAN' OR (a.action_type = 'TK_BAN' AND a.status = 'ENFORCED'))
+            AND (a.duration_minutes IS NULL OR a.created_at + INTERVAL a.duration_minutes MINUTE > NOW())
+            AND (a.player_id IN (SELECT player_id FROM ConnectionHistory WHERE ip_address = :ip)
+                OR (:guid IS NOT NULL AND a.battleye_guid = :guid))
+        ORDER BY a.created_at DESC
+        LIMIT 1",
+        params! { "pid" => player_id, "ip" => player.ip_address.as_str(), "guid" => guid },
+    )?;
+    let Some((banned_player_id, banned_name, reason)) = banned else {
+        return Ok(None);
+    };
+
+    let alert = BanEvasionAlert {
+        server_id: player.server_id.clone(),
+        username: player.username.clone(),
+        reforger_id: player.reforger_id.clone(),
+        banned_player_id,
+        banned_name,
+        reason,
+    };
+    conn.exec_drop(
+        r"INSERT INTO ModerationActions
+        (player_id, battleye_guid, username, server_id, action_type, reason, issued_by, status)
+        VALUES (:pid, :guid, :uname, :server, 'BAN_EVASION_ALERT', :reason, 'DF_Backend', 'RECOMMENDED')",
+        params! {
+            "server" => player.server_id.as_str(),
+            "pid" => player_id,
+            "guid" => guid,
+            "uname" => player.username.as_str(),
+            "reason" => format!(
+                "New account shares an IP or GUID with banned player #{} ({})",
+                banned_player_id,
+                alert.banned_name.as_deref().unwrap_or("unknown")
+            ),
+        },
+    )?;
+    Ok(Some(alert))
+}
+
+#[derive(Debug)]
+pub struct AssociateNode {
+    pub player_id: u64,
+    pub name: Option<String>,
+    pub banned: bool,
+    // Hops from the player the graph was built for
+    pub depth: u32,
+}
+
+#[derive(Debug)]
+pub struct AssociationEdge {
+    pub player_a: u64,
+    pub player_b: u64,
+    pub score: f64,
+    pub shared_ips: u32,
+    pub shared_guid: bool,
+    pub closest_seconds: Option<u64>,
+}
+
+// player_a, player_b, score, shared_ips, shared_guid, closest_seconds
+type AssociationRow = (u64, u64, f64, u32, bool, Option<u64>);
+
+// Breadth-first walk over PlayerAssociations from one player, following edges with at least `min_score`.
+pub fn association_graph(
+    conn: &mut PooledConn,
+    player_id: u64,
+    depth: u32,
+    min_score: f64,
+) -> Result<(Vec<AssociateNode>, Vec<AssociationEdge>), mysql::Error> {
+    let mut depths: HashMap<u64, u32> = HashMap::from([(player_id, 0)]);
+    let mut seen_edges: HashSet<(u64, u64)> = HashSet::new();
+    let mut edges = Vec::new();
+    let mut frontier = vec![player_id];
+
+    for hop in 1..=depth {
+        let mut next = Vec::new();
+        for pid in frontier {
+            let rows: Vec<AssociationRow> = conn.exec(
+                r"SELECT player_a, player_b, score, shared_ips, shared_guid, closest_seconds
+                FROM PlayerAssociations
+                WHERE (player_a = :pid OR player_b = :pid) AND score >= :min
+                ORDER BY score DESC",
+                params! { "pid" => pid, "min" => min_score },
+            )?;
+            for (player_a, player_b, score, shared_ips, shared_guid, closest_seconds) in rows {
+                if !seen_edges.insert((player_a, player_b)) {
+                    continue;
+                }
+                let other = if player_a == pid { player_b } else { player_a };
+                if let Entry::Vacant(entry) = depths.entry(other) {
+                    entry.insert(hop);
+                    next.push(other);
+                }
+                edges.push(AssociationEdge { player_a, player_b, score, shared_ips, shared_guid, closest_seconds });
+            }
+        }
+        frontier = next;
+    }
+
+    let mut nodes = Vec::with_capacity(depths.len());
+    for (pid, depth) in depths {
+        let (name, banned): (Option<String>, bool) = conn
+            .exec_first(
+                r"SELECT
+                    (SELECT username FROM PlayerNames WHERE player_id = :pid ORDER BY last_used DESC LIMIT 1),
+                    EXISTS (SELECT 1 FROM ModerationActions
+                        WHERE player_id = :pid
+                            AND (action_type = 'BAN' OR (action_type = 'TK_BAN' AND status = 'ENFORCED'))
+                            AND (duration_minutes IS NULL OR created_at + INTERVAL duration_minutes MINUTE > NOW()))",
+                params! { "pid" => pid },
+            )?
+            .unwrap_or((None, false));
+        nodes.push(AssociateNode { player_id: pid, name, banned, depth });
+    }
+    nodes.sort_by_key(|n| (n.depth, n.player_id));
+    Ok((nodes, edges))
+}
@@ -0,0 +1,250 @@
+use log::info;
+use mysql::{params, prelude::*, PooledConn};
+use regex::Regex;
+use std::sync::LazyLock;
+
+use crate::tail::{self, LogTime};
+
+// BattlEye writes moderation activity to console.log alongside the "BE GUID:" lines, e.g.
+//   Player #3 Name (0123456789abcdef0123456789abcdef) has been kicked by BattlEye: Admin Kick (Spamming)
+//   Player #3 Name (0123456789abcdef0123456789abcdef) has been kicked by BattlEye: Admin Ban (Teamkilling 60 min)
+//   RCon admin #0: (Global) Server restart in 5 minutes
+static KICK_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"Player #(\d+) (.+) \(([0-9a-fA-F]{32})\) has been kicked by BattlEye: (.+?)'?\s*$").unwrap()
+});
+static ADMIN_MESSAGE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(RCon admin #\d+): \(([^)]+)\) (.+?)'?\s*$").unwrap());
+static ADMIN_REASON_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^Admin (Kick|Ban)(?: \((.*)\))?$").unwrap());
+static DURATION_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(\d+)\s*(m|min|mins|minutes?|h|hrs?|hours?|d|days?)\b").unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationKind {
+    Kick,
+    Ban,
+    AdminMessage,
+}
+
+impl ModerationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationKind::Kick => "KICK",
+            ModerationKind::Ban => "BAN",
+            ModerationKind::AdminMessage => "ADMIN_MESSAGE",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ModerationEvent {
+    pub kind: ModerationKind,
+    pub player_number: Option<u32>,
+    pub username: Option<String>,
+    pub battleye_guid: Option<String>,
+    pub reason: Option<String>,
+    // None for kicks, admin messages and permanent bans
+    pub duration_minutes: Option<u32>,
+    pub issued_by: String,
+    // When the line was logged, so a ban read late (or again) runs from when it was issued
+    pub logged: LogTime,
+    // Deterministic id of the log line (see `tail::event_id`), so a line read twice is stored once
+    pub event_id: String,
+}
+
+pub fn parse_moderation_line(line: &str, event_id: String) -> Option<ModerationEvent> {
+    if let Some(caps) = KICK_REGEX.captures(line) {
+        let message = caps[4].trim();
+        let (kind, reason, issued_by) = match ADMIN_REASON_REGEX.captures(message) {
+            Some(admin) => {
+                let kind = if &admin[1] == "Ban" { ModerationKind::Ban } else { ModerationKind::Kick };
+                let reason = admin.get(2).map(|m| m.as_str().trim().to_string()).filter(|r| !r.is_empty());
+                (kind, reason, "Admin")
+            }
+            // Kicks that BattlEye issues on its own (e.g. "Client not responding", "Global Ban #...")
+            None => (ModerationKind::Kick, Some(message.to_string()), "BattlEye"),
+        };
+        let duration_minutes = match kind {
+            ModerationKind::Ban => reason.as_deref().and_then(parse_ban_duration),
+            _ => None,
+        };
+
+        return Some(ModerationEvent {
+            kind,
+            player_number: caps[1].parse().ok(),
+            username: Some(caps[2].to_string()),
+            battleye_guid: Some(caps[3].to_lowercase()),
+            reason,
+            duration_minutes,
+            issued_by: issued_by.to_string(),
+            logged: tail::line_time(line),
+            event_id,
+        });
+    }
+
+    if let Some(caps) = ADMIN_MESSAGE_REGEX.captures(line) {
+        // "(Global)" is a broadcast; "(To Name)" is a direct message to one player
+        let target = caps[2].strip_prefix("To ").map(|name| name.to_string());
+        return Some(ModerationEvent {
+            kind: ModerationKind::AdminMessage,
+            player_number: None,
+            username: target,
+            battleye_guid: None,
+            reason: Some(caps[3].trim().to_string()),
+            duration_minutes: None,
+            issued_by: caps[1].to_string(),
+            logged: tail::line_time(line),
+            event_id,
+        });
+    }
+
+    None
+}
+
+// Ban durations are not a separate field in the log; admins put them in the reason
+// ("Teamkilling 60 min", "Cheating - 7 days"). No duration means a permanent ban.
+fn parse_ban_duration(reason: &str) -> Option<u32> {
+    let caps = DURATION_REGEX.captures(reason)?;
+    let amount: u32 = caps[1].parse().ok()?;
+    let unit = caps[2].to_lowercase();
+    let minutes = match unit.chars().next()? {
+        'h' => amount.checked_mul(60)?,
+        'd' => amount.checked_mul(60 * 24)?,
+        _ => amount,
+    };
+    Some(minutes)
+}
+
+pub fn log_moderation_event(server_id: &str, e: &ModerationEvent) {
+    info!(
+        server = server_id,
+        kind = e.kind.as_str(),
+        player_number = e.player_number,
+        username = e.username,
+        battleye_guid = e.battleye_guid,
+        reason = e.reason,
+        duration_minutes = e.duration_minutes,
+        issued_by = e.issued_by;
+        "Moderation {}", e.kind.as_str()
+    );
+}
+
+// Returns false for an event that was already stored by an earlier read of the same line.
+pub fn persist_moderation_event(conn: &mut PooledConn, server_id: &str, e: &ModerationEvent) -> Result<bool, mysql::Error> {
+    // Link to Players by BattlEye GUID; the player may not have been seen yet, so this is optional
+    let player_id: Option<u64> = match e.battleye_guid {
+        Some(ref guid) => conn.exec_first(
+            "SELECT player_id FROM Players WHERE battleye_guid = :guid ORDER BY last_seen DESC LIMIT 1",
+            params! { "guid" => guid },
+        )?,
+        None => None,
+    };
+
+    // created_at is when the line was logged: bans run from then (see `rcon::active_ban`).
+    conn.exec_drop(
+        format!(
+            r"INSERT IGNORE INTO ModerationActions
+            (player_id, battleye_guid, username, server_id, action_type, reason, duration_minutes, issued_by, created_at, event_id)
+            VALUES (:pid, :guid, :uname, :server, :kind, :reason, :duration, :issued_by, {logged_at}, :event_id)",
+            logged_at = tail::LOGGED_AT_SQL
+        ),
+        params! {
+            "pid" => player_id,
+            "server" => server_id,
+            "guid" => e.battleye_guid.as_deref(),
+            "uname" => e.username.as_deref(),
+            "kind" => e.kind.as_str(),
+            "reason" => e.reason.as_deref(),
+            "duration" => e.duration_minutes,
+            "issued_by" => e.issued_by.as_str(),
+            "log_date" => e.logged.date.as_deref(),
+            "log_time" => e.logged.time.as_deref(),
+            "event_id" => e.event_id.as_str(),
+        },
+    )?;
+    Ok(conn.affected_rows() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUID: &str = "0123456789ABCDEF0123456789abcdef";
+
+    fn parse(line: &str) -> ModerationEvent {
+        parse_moderation_line(line, "id".to_string()).expect("moderation line")
+    }
+
+    #[test]
+    fn admin_ban_with_duration() {
+        let event = parse(&format!(
+            "12:00:01.500  BattlEye Server: 'Player #3 Some Name ({}) has been kicked by BattlEye: Admin Ban (Teamkilling 60 min)'",
+            GUID
+        ));
+        assert_eq!(event.kind, ModerationKind::Ban);
+        assert_eq!(event.player_number, Some(3));
+        assert_eq!(event.username.as_deref(), Some("Some Name"));
+        assert_eq!(event.battleye_guid.as_deref(), Some("0123456789abcdef0123456789abcdef"));
+        assert_eq!(event.reason.as_deref(), Some("Teamkilling 60 min"));
+        assert_eq!(event.duration_minutes, Some(60));
+        assert_eq!(event.issued_by, "Admin");
+        assert_eq!(event.logged.time.as_deref(), Some("12:00:01.500"));
+        assert_eq!(event.event_id, "id");
+    }
+
+    #[test]
+    fn ban_without_duration_is_permanent() {
+        let event = parse(&format!("Player #1 Cheater ({}) has been kicked by BattlEye: Admin Ban (Cheating)", GUID));
+        assert_eq!(event.kind, ModerationKind::Ban);
+        assert_eq!(event.duration_minutes, None);
+
+        let event = parse(&format!("Player #1 Cheater ({}) has been kicked by BattlEye: Admin Ban", GUID));
+        assert_eq!((event.kind, event.reason), (ModerationKind::Ban, None));
+    }
+
+    #[test]
+    fn admin_and_battleye_kicks() {
+        let event = parse(&format!("Player #7 Talker ({}) has been kicked by BattlEye: Admin Kick (Spamming)", GUID));
+        assert_eq!(event.kind, ModerationKind::Kick);
+        assert_eq!((event.reason.as_deref(), event.issued_by.as_str()), (Some("Spamming"), "Admin"));
+
+        let event = parse(&format!("Player #7 Laggy ({}) has been kicked by BattlEye: Client not responding", GUID));
+        assert_eq!(event.kind, ModerationKind::Kick);
+        assert_eq!((event.reason.as_deref(), event.issued_by.as_str()), (Some("Client not responding"), "BattlEye"));
+        assert_eq!(event.duration_minutes, None);
+    }
+
+    #[test]
+    fn admin_messages() {
+        let event = parse("RCon admin #0: (Global) Server restart in 5 minutes");
+        assert_eq!(event.kind, ModerationKind::AdminMessage);
+        assert_eq!(event.username, None);
+        assert_eq!(event.reason.as_deref(), Some("Server restart in 5 minutes"));
+        assert_eq!(event.issued_by, "RCon admin #0");
+
+        let event = parse("RCon admin #1: (To Some Name) Please stop");
+        assert_eq!(event.username.as_deref(), Some("Some Name"));
+    }
+
+    #[test]
+    fn other_lines_are_not_moderation_events() {
+        assert!(parse_moderation_line("Player #3 Name - BE GUID: 0123456789abcdef0123456789abcdef", String::new()).is_none());
+        assert!(parse_moderation_line("Player #3 Name (not-a-guid) has been kicked by BattlEye: Admin Kick", String::new()).is_none());
+    }
+
+    #[test]
+    fn ban_durations() {
+        assert_eq!(parse_ban_duration("Teamkilling 60 min"), Some(60));
+        assert_eq!(parse_ban_duration("Toxic 30m"), Some(30));
+        assert_eq!(parse_ban_duration("Cheating - 2 hours"), Some(120));
+        assert_eq!(parse_ban_duration("Griefing 12h"), Some(720));
+        assert_eq!(parse_ban_duration("Cheating - 7 days"), Some(7 * 24 * 60));
+        assert_eq!(parse_ban_duration("1 Day"), Some(24 * 60));
+        assert_eq!(parse_ban_duration("Cheating"), None);
+        // "5 players" is not a duration.
+        assert_eq!(parse_ban_duration("Killed 5 players"), None);
+        // Too long to count in minutes.
+        assert_eq!(parse_ban_duration("4000000 days"), None);
+    }
+}
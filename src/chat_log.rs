@@ -0,0 +1,187 @@
+use mysql::{params, prelude::*, PooledConn};
+use regex::Regex;
+use std::sync::LazyLock;
+
+use crate::tail::{self, LogTime};
+
+// In-game chat as written to console.log, e.g.
+//   BattlEye Server: '(Global) Name: hello everyone'
+//   BattlEye Server: '(Side) Name With Spaces: need a medic at the church'
+static CHAT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\((Global|Side|Faction|Group|Squad|Vehicle|Direct|Command)\) (.+?): (.*?)'?\s*$").unwrap()
+});
+
+#[derive(Debug)]
+pub struct ChatMessage {
+    pub channel: String,
+    pub username: String,
+    pub message: String,
+    // When the line was logged; stored as sent_at
+    pub logged: LogTime,
+    // Deterministic id of the log line (see `tail::event_id`), so a line read twice is stored once
+    pub event_id: String,
+}
+
+// Whether a line is in-game chat. Players can type anything, so the other parsers skip these lines
+// instead of picking up a marker someone typed into chat.
+pub fn is_chat_line(line: &str) -> bool {
+    // Admin broadcasts look like chat ("RCon admin #0: (Global) ...") but are moderation events; in a
+    // player's message "RCon admin" can only come after the channel.
+    CHAT_REGEX.find(line).is_some_and(|m| !line[..m.start()].contains("RCon admin"))
+}
+
+pub fn parse_chat_line(line: &str, event_id: String) -> Option<ChatMessage> {
+    if !is_chat_line(line) {
+        return None;
+    }
+
+    let caps = CHAT_REGEX.captures(line)?;
+    Some(ChatMessage {
+        channel: caps[1].to_string(),
+        username: caps[2].to_string(),
+        message: caps[3].to_string(),
+        logged: tail::line_time(line),
+        event_id,
+    })
+}
+
+// Returns false for a message that was already stored by an earlier read of the same line.
+pub fn persist_chat_message(conn: &mut PooledConn, server_id: &str, m: &ChatMessage) -> Result<bool, mysql::Error> {
+    // Chat only carries the display name; link to the player who used it most recently.
+    let player_id: Option<u64> = conn.exec_first(
+        "SELECT player_id FROM PlayerNames WHERE username = :u ORDER BY last_used DESC LIMIT 1",
+        params! { "u" => m.username.as_str() },
+    )?;
+
+    conn.exec_drop(
+        format!(
+            r"INSERT IGNORE INTO ChatMessages (player_id, username, server_id, channel, message, sent_at, event_id)
+            VALUES (:pid, :uname, :server, :channel, :message, {logged_at}, :event_id)",
+            logged_at = tail::LOGGED_AT_SQL
+        ),
+        params! {
+            "pid" => player_id,
+            "server" => server_id,
+            "uname" => m.username.as_str(),
+            "channel" => m.channel.as_str(),
+            "message" => m.message.as_str(),
+            "log_date" => m.logged.date.as_deref(),
+            "log_time" => m.logged.time.as_deref(),
+            "event_id" => m.event_id.as_str(),
+        },
+    )?;
+    Ok(conn.affected_rows() > 0)
+}
+
+// Filters for search_chat; every field is optional. Times use MySQL's "YYYY-MM-DD HH:MM:SS" format.
+#[derive(Debug, Default)]
+pub struct ChatSearch {
+    pub player: Option<String>,
+    pub server: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub keyword: Option<String>,
+    pub limit: u32,
+    pub offset: u64,
+}
+
+#[derive(Debug)]
+pub struct ChatRecord {
+    pub message_id: u64,
+    pub player_id: Option<u64>,
+    pub username: String,
+    pub server_id: String,
+    pub channel: String,
+    pub message: String,
+    pub sent_at: String,
+}
+
+// message_id, player_id, username, server_id, channel, message, sent_at
+type ChatRow = (u64, Option<u64>, String, String, String, String, String);
+
+pub fn search_chat(conn: &mut PooledConn, search: &ChatSearch) -> Result<Vec<ChatRecord>, mysql::Error> {
+    // A player filter matches any name that player has used, not only the one in the message.
+    let keyword = search.keyword.as_ref().map(|k| format!("%{}%", escape_like(k)));
+    let rows: Vec<ChatRow> = conn.exec(
+        r"SELECT message_id, player_id, username, server_id, channel, message,
+            DATE_FORMAT(sent_at, '%Y-%m-%d %H:%i:%s')
+        FROM ChatMessages
+        WHERE (:player IS NULL
+                OR username = :player
+                OR player_id IN (SELECT player_id FROM PlayerNames WHERE username = :player))
+            AND (:server IS NULL OR server_id = :server)
+            AND (:since IS NULL OR sent_at >= :since)
+            AND (:until IS NULL OR sent_at <= :until)
+            AND (:keyword IS NULL OR message LIKE :keyword)
+        ORDER BY sent_at DESC, message_id DESC
+        LIMIT :limit OFFSET :offset",
+        params! {
+            "player" => search.player.as_deref(),
+            "server" => search.server.as_deref(),
+            "since" => search.since.as_deref(),
+            "until" => search.until.as_deref(),
+            "keyword" => keyword,
+            "limit" => search.limit,
+            "offset" => search.offset,
+        },
+    )?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(message_id, player_id, username, server_id, channel, message, sent_at)| ChatRecord {
+            message_id,
+            player_id,
+            username,
+            server_id,
+            channel,
+            message,
+            sent_at,
+        })
+        .collect())
+}
+
+pub fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_channel_name_and_message() {
+        let m = parse_chat_line("12:00:00.000  BattlEye Server: '(Side) Name With Spaces: need a medic: now'", "id".to_string()).unwrap();
+        assert_eq!(m.channel, "Side");
+        assert_eq!(m.username, "Name With Spaces");
+        assert_eq!(m.message, "need a medic: now");
+        assert_eq!(m.logged.time.as_deref(), Some("12:00:00.000"));
+        assert_eq!(m.event_id, "id");
+
+        let m = parse_chat_line("BattlEye Server: '(Global) Name: '", String::new()).unwrap();
+        assert_eq!(m.message, "");
+    }
+
+    #[test]
+    fn markers_typed_into_chat_are_chat() {
+        let line = "BattlEye Server: '(Global) Troll: PLAYER_KILLED: killerName='A', victimName='B''";
+        assert!(is_chat_line(line));
+        assert_eq!(parse_chat_line(line, String::new()).unwrap().message, "PLAYER_KILLED: killerName='A', victimName='B'");
+        assert!(is_chat_line("BattlEye Server: '(Global) Troll: RCon admin #0: (Global) fake'"));
+    }
+
+    #[test]
+    fn admin_broadcasts_and_other_lines_are_not_chat() {
+        assert!(!is_chat_line("RCon admin #0: (Global) Server restart in 5 minutes"));
+        assert!(parse_chat_line("RCon admin #0: (Global) Server restart in 5 minutes", String::new()).is_none());
+        assert!(!is_chat_line("Player #3 Name - BE GUID: 0123456789abcdef0123456789abcdef"));
+        assert!(!is_chat_line("(Unknown) Name: hello"));
+    }
+
+    #[test]
+    fn escape_like_escapes_wildcards() {
+        assert_eq!(escape_like("100%_sure"), "100\\%\\_sure");
+        assert_eq!(escape_like("back\\slash"), "back\\\\slash");
+        assert_eq!(escape_like("\\%"), "\\\\\\%");
+        assert_eq!(escape_like("plain"), "plain");
+    }
+}
@@ -0,0 +1,54 @@
+use log::warn;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// How far a reader got in a server's console.log, kept as `<server>.<reader>.offset` in CHECKPOINT_DIR
+// (default `checkpoints`), so a restart resumes there instead of skipping or re-reading lines. The
+// offset is stored with the identity of the log it belongs to (see `tail::log_identity`), so a log that
+// was replaced while DF_Backend was down is read from the start rather than from the old offset.
+pub struct Checkpoint {
+    path: PathBuf,
+    saved: Option<(u64, String)>,
+}
+
+impl Checkpoint {
+    pub fn open(server_id: &str, reader: &str) -> Self {
+        let dir = PathBuf::from(env::var("CHECKPOINT_DIR").unwrap_or_else(|_| "checkpoints".to_string()));
+        let path = dir.join(format!("{}.{}.offset", server_id, reader));
+        // "<offset> <identity>"; files written before identities were recorded hold only the offset.
+        let saved = fs::read_to_string(&path).ok().and_then(|s| {
+            let mut parts = s.split_whitespace();
+            let position = parts.next()?.parse().ok()?;
+            Some((position, parts.next().unwrap_or_default().to_string()))
+        });
+        Self { path, saved }
+    }
+
+    pub fn position(&self) -> Option<u64> {
+        self.saved.as_ref().map(|(position, _)| *position)
+    }
+
+    // The identity of the log the position belongs to, empty when it is not known.
+    pub fn identity(&self) -> &str {
+        self.saved.as_ref().map_or("", |(_, identity)| identity)
+    }
+
+    // Written to a temporary file and renamed over the old one, so a crash never leaves half an offset.
+    pub fn save(&mut self, position: u64, identity: &str) {
+        if self.saved.as_ref().is_some_and(|(p, i)| *p == position && i == identity) {
+            return;
+        }
+        let temp = self.path.with_extension("offset.tmp");
+        let result = self
+            .path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&temp, format!("{} {}", position, identity).trim_end()))
+            .and_then(|_| fs::rename(&temp, &self.path));
+        match result {
+            Ok(()) => self.saved = Some((position, identity.to_string())),
+            Err(e) => warn!("Failed to save checkpoint {}: {}", self.path.display(), e),
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use mysql::prelude::*;
+use std::error::Error;
+
+use crate::associations;
+use crate::chat_log::{self, ChatSearch};
+use crate::database_setup::init_db_pool;
+use crate::discord;
+use crate::factions;
+use crate::ingest::{self, AgentConfig};
+use crate::match_tracker;
+use crate::player_merge;
+use crate::rating;
+use crate::seasons;
+use crate::suspicion;
+
+const USAGE: &str = r"Usage: DF_backend [COMMAND]
+
+Without a command DF_backend runs the log monitors.
+
+Commands:
+  agent
+      Ship this host's console.log to a collector (AGENT_COLLECTOR, AGENT_TOKEN, SERVER_ID, SERVER_PATH). Runs until stopped.
+  associates PLAYER [--depth N] [--min-score X]
+      Show accounts linked to a player (name or id) by shared IPs or BattlEye GUIDs.
+  chat-search [--player NAME] [--server ID] [--since TIME] [--until TIME] [--keyword TEXT] [--limit N] [--offset N]
+      Search logged in-game chat. TIME is 'YYYY-MM-DD HH:MM:SS'.
+  discord-link-code DISCORD_ID
+      Issue a one-time code the player types in game chat as '!link CODE' to link their Discord account.
+  faction-stats [--player NAME]
+      Show faction win/loss and matchup weapon stats, or one player's per-faction record.
+  incidents [--status STATUS] [--limit N]
+      List suspicious-performance incidents, highest score first (default status OPEN).
+  match-summary [MATCH_ID]
+      Show the scoreboard of a match (defaults to the most recent one).
+  merge-players SOURCE_ID TARGET_ID [--by NAME]
+      Fold a duplicate player into another: names, IPs, Discord link and kills move over and stats are added up.
+  player-merges [--player ID] [--limit N]
+      List player merges, most recent first.
+  rebuild-periods
+      Rebuild the daily, weekly and monthly leaderboard totals from the stored kills.
+  recompute-ratings
+      Rebuild every player's skill rating from the stored kills.
+  review-incident INCIDENT_ID STATUS [--by NAME]
+      Mark an incident CONFIRMED, DISMISSED or OPEN again.
+  season-create NAME --start TIME [--end TIME]
+      Start a season; kills already stored since its start count towards it.
+  season-end SEASON_ID [--at TIME]
+      End a season now (or at TIME) and archive its results once the end has passed.
+  seasons
+      List seasons with their state and leader.
+  unmerge-players MERGE_ID [--by NAME]
+      Undo a merge, splitting the source player off again with its kills and stats.
+  help
+      Show this message.";
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (command, rest) = args.split_first().ok_or(USAGE)?;
+    match command.as_str() {
+        "agent" => agent(rest),
+        "associates" => associates(rest),
+        "chat-search" => chat_search(rest),
+        "discord-link-code" => discord_link_code(rest),
+        "faction-stats" => faction_stats(rest),
+        "incidents" => incidents(rest),
+        "match-summary" => match_summary(rest),
+        "merge-players" => merge_players(rest),
+        "player-merges" => player_merges(rest),
+        "rebuild-periods" => rebuild_periods(rest),
+        "recompute-ratings" => recompute_ratings(rest),
+        "review-incident" => review_incident(rest),
+        "season-create" => season_create(rest),
+        "season-end" => season_end(rest),
+        "seasons" => season_list(rest),
+        "unmerge-players" => unmerge_players(rest),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(format!("Unknown command '{}'\n\n{}", other, USAGE).into()),
+    }
+}
+
+// Splits "--flag value" pairs; every flag takes exactly one value.
+fn parse_flags<'a>(args: &'a [String], allowed: &[&str]) -> Result<Vec<(&'a str, &'a str)>, String> {
+    let mut flags = Vec::new();
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        if !allowed.contains(&flag.as_str()) {
+            return Err(format!("Unknown option '{}'\n\n{}", flag, USAGE));
+        }
+        let value = iter.next().ok_or_else(|| format!("Missing value for '{}'", flag))?;
+        flags.push((flag.as_str(), value.as_str()));
+    }
+    Ok(flags)
+}
+
+fn chat_search(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut search = ChatSearch { limit: 50, ..Default::default() };
+    for (flag, value) in parse_flags(args, &["--player", "--server", "--since", "--until", "--keyword", "--limit", "--offset"])? {
+        match flag {
+            "--player" => search.player = Some(value.to_string()),
+            "--server" => search.server = Some(value.to_string()),
+            "--since" => search.since = Some(value.to_string()),
+            "--until" => search.until = Some(value.to_string()),
+            "--keyword" => search.keyword = Some(value.to_string()),
+            "--limit" => search.limit = value.parse()?,
+            "--offset" => search.offset = value.parse()?,
+            _ => unreachable!(),
+        }
+    }
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    let messages = chat_log::search_chat(&mut conn, &search)?;
+
+    for m in &messages {
+        let player = m.player_id.map(|id| format!(" [player {}]", id)).unwrap_or_default();
+        println!(
+            "#{} [{}] {} ({}) {}{}: {}",
+            m.message_id, m.sent_at, m.server_id, m.channel, m.username, player, m.message
+        );
+    }
+    println!("{} message(s)", messages.len());
+    Ok(())
+}
+
+fn match_summary(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+
+    let match_id: u64 = match args {
+        [] => conn
+            .query_first("SELECT match_id FROM Matches ORDER BY started_at DESC LIMIT 1")?
+            .ok_or("No matches recorded yet")?,
+        [id] => id.parse()?,
+        _ => return Err(USAGE.into()),
+    };
+
+    match_tracker::print_match_summary(&mut conn, match_id)?;
+    Ok(())
+}
+
+fn faction_stats(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let flags = parse_flags(args, &["--player"])?;
+    let player = flags.first().map(|(_, value)| *value);
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    factions::print_faction_stats(&mut conn, player)?;
+    Ok(())
+}
+
+fn discord_link_code(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [discord_id] = args else {
+        return Err(USAGE.into());
+    };
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    let code = discord::issue_link_code(&mut conn, discord_id)?;
+    println!(
+        "Link code for Discord user {}: {} (type '!link {}' in game chat within {} minutes)",
+        discord_id,
+        code,
+        code,
+        discord::LINK_CODE_TTL_MINUTES
+    );
+    Ok(())
+}
+
+fn associates(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (player, rest) = args.split_first().ok_or(USAGE)?;
+    let mut depth = 2;
+    let mut min_score = 0.3;
+    for (flag, value) in parse_flags(rest, &["--depth", "--min-score"])? {
+        match flag {
+            "--depth" => depth = value.parse()?,
+            "--min-score" => min_score = value.parse()?,
+            _ => unreachable!(),
+        }
+    }
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    let player_id: u64 = match player.parse() {
+        Ok(id) => id,
+        Err(_) => conn
+            .exec_first(
+                "SELECT player_id FROM PlayerNames WHERE username = ? ORDER BY last_used DESC LIMIT 1",
+                (player,),
+            )?
+            .ok_or_else(|| format!("No player named '{}'", player))?,
+    };
+
+    let (nodes, edges) = associations::association_graph(&mut conn, player_id, depth, min_score)?;
+    println!("=== KNOWN ASSOCIATES OF #{} ===", player_id);
+    for node in nodes.iter().filter(|n| n.depth > 0) {
+        println!(
+            "  {}#{} {}{}",
+            "  ".repeat(node.depth as usize - 1),
+            node.player_id,
+            node.name.as_deref().unwrap_or("unknown"),
+            if node.banned { " [BANNED]" } else { "" }
+        );
+    }
+    println!("Links:");
+    for edge in &edges {
+        println!(
+            "  #{} - #{}: score {:.2}, {} shared IP(s){}{}",
+            edge.player_a,
+            edge.player_b,
+            edge.score,
+            edge.shared_ips,
+            if edge.shared_guid { ", same BattlEye GUID" } else { "" },
+            edge.closest_seconds.map(|s| format!(", closest {}h apart", s / 3600)).unwrap_or_default()
+        );
+    }
+    if edges.is_empty() {
+        println!("  none");
+    }
+    Ok(())
+}
+
+fn incidents(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut status = "OPEN".to_string();
+    let mut limit = 50;
+    for (flag, value) in parse_flags(args, &["--status", "--limit"])? {
+        match flag {
+            "--status" => status = value.to_uppercase(),
+            "--limit" => limit = value.parse()?,
+            _ => unreachable!(),
+        }
+    }
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    suspicion::print_incidents(&mut conn, &status, limit)?;
+    Ok(())
+}
+
+fn review_incident(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [incident_id, status, rest @ ..] = args else {
+        return Err(USAGE.into());
+    };
+    let incident_id: u64 = incident_id.parse()?;
+    let status = status.to_uppercase();
+    if !suspicion::REVIEW_STATUSES.contains(&status.as_str()) {
+        return Err(format!("Status must be one of {}", suspicion::REVIEW_STATUSES.join(", ")).into());
+    }
+    let flags = parse_flags(rest, &["--by"])?;
+    let reviewer = flags.first().map(|(_, value)| *value).unwrap_or("admin");
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    if !suspicion::review_incident(&mut conn, incident_id, &status, reviewer)? {
+        return Err(format!("No incident #{}", incident_id).into());
+    }
+    println!("Incident #{} marked {} by {}", incident_id, status, reviewer);
+    Ok(())
+}
+
+fn recompute_ratings(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if !args.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    let (players, kills) = rating::recompute_ratings(&mut conn)?;
+    println!("Rated {} player(s) from {} kill(s)", players, kills);
+    Ok(())
+}
+
+fn agent(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if !args.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    ingest::run_agent(AgentConfig::from_env()?);
+    Ok(())
+}
+
+fn rebuild_periods(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if !args.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    let kills = seasons::rebuild_periods(&mut conn)?;
+    println!("Rebuilt daily, weekly and monthly totals from {} kill(s)", kills);
+    Ok(())
+}
+
+fn season_create(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (name, rest) = args.split_first().ok_or(USAGE)?;
+    let mut start = None;
+    let mut end = None;
+    for (flag, value) in parse_flags(rest, &["--start", "--end"])? {
+        match flag {
+            "--start" => start = Some(value),
+            "--end" => end = Some(value),
+            _ => unreachable!(),
+        }
+    }
+    let start = start.ok_or("Missing --start")?;
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    let season_id = seasons::create_season(&mut conn, name, start, end)?;
+    println!("Created season #{} '{}'", season_id, name);
+    Ok(())
+}
+
+fn season_end(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (season_id, rest) = args.split_first().ok_or(USAGE)?;
+    let season_id: u64 = season_id.parse()?;
+    let flags = parse_flags(rest, &["--at"])?;
+    let at = flags.first().map(|(_, value)| *value);
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    if !seasons::end_season(&mut conn, season_id, at)? {
+        return Err(format!("No running season #{}", season_id).into());
+    }
+    println!("Season #{} ends {}", season_id, at.unwrap_or("now"));
+    Ok(())
+}
+
+fn season_list(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if !args.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    seasons::print_seasons(&mut conn)?;
+    Ok(())
+}
+
+fn merge_players(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [source, target, rest @ ..] = args else {
+        return Err(USAGE.into());
+    };
+    let (source, target): (u64, u64) = (source.parse()?, target.parse()?);
+    let flags = parse_flags(rest, &["--by"])?;
+    let merged_by = flags.first().map(|(_, value)| *value).unwrap_or("admin");
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    let report = player_merge::merge_players(&mut conn, source, target, merged_by)?;
+    println!(
+        "Merged player #{} into #{} as merge #{}: {} kill(s) moved, names moved: {}",
+        source,
+        target,
+        report.merge_id,
+        report.kills,
+        if report.names.is_empty() { "none".to_string() } else { report.names.join(", ") }
+    );
+    Ok(())
+}
+
+fn unmerge_players(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [merge_id, rest @ ..] = args else {
+        return Err(USAGE.into());
+    };
+    let merge_id: u64 = merge_id.parse()?;
+    let flags = parse_flags(rest, &["--by"])?;
+    let undone_by = flags.first().map(|(_, value)| *value).unwrap_or("admin");
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    let kills = player_merge::undo_merge(&mut conn, merge_id, undone_by)?;
+    println!("Undid merge #{}: {} kill(s) moved back", merge_id, kills);
+    Ok(())
+}
+
+fn player_merges(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut player_id = None;
+    let mut limit = 50;
+    for (flag, value) in parse_flags(args, &["--player", "--limit"])? {
+        match flag {
+            "--player" => player_id = Some(value.parse()?),
+            "--limit" => limit = value.parse()?,
+            _ => unreachable!(),
+        }
+    }
+
+    let pool = init_db_pool().ok_or("Database is not configured")?;
+    let mut conn = pool.get_conn()?;
+    let merges = player_merge::list_merges(&mut conn, player_id, limit, 0)?;
+    println!("=== PLAYER MERGES ===");
+    for m in &merges {
+        let undone = match (&m.undone_at, &m.undone_by) {
+            (Some(at), Some(by)) => format!(" [undone {} by {}]", at, by),
+            (Some(at), None) => format!(" [undone {}]", at),
+            _ => String::new(),
+        };
+        println!("  #{} #{} -> #{} at {} by {}{}", m.merge_id, m.source_id, m.target_id, m.merged_at, m.merged_by, undone);
+    }
+    if merges.is_empty() {
+        println!("  none");
+    }
+    Ok(())
+}
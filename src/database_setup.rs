@@ -1,3 +1,4 @@
+use log::{error, info};
 use mysql::*;
 use mysql::prelude::*;
 use std::env;
@@ -11,7 +12,7 @@ pub fn setup_database(
     database_user: &str,
     database_password: &str,
 ) {
-    println!("Setting up the database...");
+    info!("Setting up the database...");
 
     let url = format!(
         "mysql://{}:{}@{}:{}/{}",
@@ -21,17 +22,51 @@ pub fn setup_database(
     match Pool::new(url.as_str()) {
         Ok(pool) => match pool.get_conn() {
             Ok(mut conn) => {
-                println!("Connected to database {}", database_name);
+                info!("Connected to database {}", database_name);
 
                 // Drop tables if they exist (in correct order due to foreign keys)
+                let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerMergeKills");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerMerges");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS ChatMessages");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS SuspicionEvidence");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS SuspicionIncidents");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS ModerationEvidence");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS ModerationActions");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS MatchPlayerStats");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS MatchFactionResults");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerFactionStats");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS FactionWeaponStats");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerAchievements");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS WeaponRecords");
                 let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerKills");
                 let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerWeaponStats");
                 let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerVsPlayerStats");
                 let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerStats");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerRatings");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS PeriodWeaponStats");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS PeriodPlayerStats");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS SeasonPlayerStats");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS Seasons");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerAssociations");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS ConnectionHistory");
                 let _ = conn.query_drop("DROP TABLE IF EXISTS ConnectionLogs");
                 let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerNames");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS Matches");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS DiscordLinkCodes");
                 let _ = conn.query_drop("DROP TABLE IF EXISTS PlayerDiscordLink");
                 let _ = conn.query_drop("DROP TABLE IF EXISTS Players");
+                let _ = conn.query_drop("DROP TABLE IF EXISTS Servers");
+
+                // Create Servers table (monitored game servers, registered at startup from the server config)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE Servers (
+                        server_id VARCHAR(32) PRIMARY KEY,
+                        name VARCHAR(100) NOT NULL,
+                        log_path VARCHAR(512) NOT NULL,
+                        first_started DATETIME DEFAULT CURRENT_TIMESTAMP,
+                        last_started DATETIME DEFAULT CURRENT_TIMESTAMP
+                    )"
+                );
 
                 // Create Players table
                 let _ = conn.query_drop(
@@ -41,7 +76,8 @@ pub fn setup_database(
                         battleye_guid CHAR(32),
                         first_seen DATETIME DEFAULT CURRENT_TIMESTAMP,
                         last_seen TIMESTAMP DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
-                        INDEX idx_reforger_id (reforger_id)
+                        INDEX idx_reforger_id (reforger_id),
+                        INDEX idx_battleye_guid (battleye_guid)
                     )"
                 );
 
@@ -56,6 +92,17 @@ pub fn setup_database(
                     )"
                 );
 
+                // Create DiscordLinkCodes table (pending one-time codes for PlayerDiscordLink)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE DiscordLinkCodes (
+                        code CHAR(8) PRIMARY KEY,
+                        discord_id VARCHAR(64) NOT NULL,
+                        created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                        expires_at DATETIME NOT NULL,
+                        INDEX idx_discord_id (discord_id)
+                    )"
+                );
+
                 // Create PlayerNames table
                 let _ = conn.query_drop(
                     r"CREATE TABLE PlayerNames (
@@ -71,24 +118,86 @@ pub fn setup_database(
                     )"
                 );
 
+                // Create Matches table (one row per game from "Game successfully created." to its end)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE Matches (
+                        match_id INT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
+                        scenario VARCHAR(255),
+                        map VARCHAR(100),
+                        started_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                        ended_at DATETIME NULL,
+                        duration_seconds INT UNSIGNED NULL,
+                        winner_faction VARCHAR(50),
+                        end_reason VARCHAR(100),
+                        first_blood_kill_id INT UNSIGNED NULL,
+                        event_id CHAR(32) NULL,
+                        UNIQUE KEY uq_event_id (event_id),
+                        INDEX idx_server_started (server_id, started_at),
+                        INDEX idx_started_at (started_at),
+                        INDEX idx_map (map)
+                    )"
+                );
+
                 // Create ConnectionLogs table
                 let _ = conn.query_drop(
                     r"CREATE TABLE ConnectionLogs (
                         player_id INT UNSIGNED NOT NULL,
                         ip_address VARCHAR(45) NOT NULL,
                         username VARCHAR(255) NOT NULL,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
+                        match_id INT UNSIGNED NULL,
                         connected_at DATETIME DEFAULT CURRENT_TIMESTAMP,
                         PRIMARY KEY (player_id, ip_address),
                         FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE,
+                        FOREIGN KEY (match_id) REFERENCES Matches(match_id) ON DELETE SET NULL,
                         INDEX idx_ip_address (ip_address),
                         INDEX idx_connected_at (connected_at)
                     )"
                 );
 
+                // Create ConnectionHistory table (every connection, for cross-referencing accounts)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE ConnectionHistory (
+                        connection_id INT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+                        player_id INT UNSIGNED NOT NULL,
+                        ip_address VARCHAR(45) NOT NULL,
+                        battleye_guid CHAR(32),
+                        username VARCHAR(255) NOT NULL,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
+                        connected_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                        event_id CHAR(32) NULL,
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE,
+                        UNIQUE KEY uq_event_id (event_id),
+                        INDEX idx_player_connected (player_id, connected_at),
+                        INDEX idx_ip_address (ip_address),
+                        INDEX idx_battleye_guid (battleye_guid)
+                    )"
+                );
+
+                // Create PlayerAssociations table (alt-likelihood graph; player_a < player_b)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE PlayerAssociations (
+                        player_a INT UNSIGNED NOT NULL,
+                        player_b INT UNSIGNED NOT NULL,
+                        shared_ips INT UNSIGNED DEFAULT 0,
+                        shared_guid BOOLEAN DEFAULT FALSE,
+                        closest_seconds INT UNSIGNED NULL,
+                        score DOUBLE DEFAULT 0,
+                        updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+                        PRIMARY KEY (player_a, player_b),
+                        FOREIGN KEY (player_a) REFERENCES Players(player_id) ON DELETE CASCADE,
+                        FOREIGN KEY (player_b) REFERENCES Players(player_id) ON DELETE CASCADE,
+                        INDEX idx_player_b (player_b),
+                        INDEX idx_score (score)
+                    )"
+                );
+
                 // Create PlayerKills table
                 let _ = conn.query_drop(
                     r"CREATE TABLE PlayerKills (
                         kill_id INT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
                         killer_id INT UNSIGNED NOT NULL,
                         victim_id INT UNSIGNED NOT NULL,
                         weapon VARCHAR(100) NOT NULL,
@@ -96,9 +205,15 @@ pub fn setup_database(
                         is_team_kill BOOLEAN DEFAULT FALSE,
                         killer_faction VARCHAR(50),
                         victim_faction VARCHAR(50),
-                        killed_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                        match_id INT UNSIGNED NULL,
+                        killed_at DATETIME(3) DEFAULT CURRENT_TIMESTAMP(3),
+                        event_id CHAR(32) NULL,
                         FOREIGN KEY (killer_id) REFERENCES Players(player_id) ON DELETE CASCADE,
                         FOREIGN KEY (victim_id) REFERENCES Players(player_id) ON DELETE CASCADE,
+                        FOREIGN KEY (match_id) REFERENCES Matches(match_id) ON DELETE SET NULL,
+                        UNIQUE KEY uq_event_id (event_id),
+                        INDEX idx_server_killed_at (server_id, killed_at),
+                        INDEX idx_match_id (match_id),
                         INDEX idx_killer_id (killer_id),
                         INDEX idx_victim_id (victim_id),
                         INDEX idx_weapon (weapon),
@@ -110,13 +225,14 @@ pub fn setup_database(
                 let _ = conn.query_drop(
                     r"CREATE TABLE PlayerWeaponStats (
                         player_id INT UNSIGNED NOT NULL,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
                         weapon VARCHAR(100) NOT NULL,
                         total_kills INT UNSIGNED DEFAULT 0,
                         total_team_kills INT UNSIGNED DEFAULT 0,
                         total_distance DECIMAL(12,4) DEFAULT 0,
                         longest_kill DECIMAL(8,4) DEFAULT 0,
                         last_kill DATETIME,
-                        PRIMARY KEY (player_id, weapon),
+                        PRIMARY KEY (player_id, server_id, weapon),
                         FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE,
                         INDEX idx_weapon (weapon),
                         INDEX idx_total_kills (total_kills)
@@ -128,9 +244,10 @@ pub fn setup_database(
                     r"CREATE TABLE PlayerVsPlayerStats (
                         killer_id INT UNSIGNED NOT NULL,
                         victim_id INT UNSIGNED NOT NULL,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
                         total_kills INT UNSIGNED DEFAULT 0,
                         last_kill DATETIME,
-                        PRIMARY KEY (killer_id, victim_id),
+                        PRIMARY KEY (killer_id, victim_id, server_id),
                         FOREIGN KEY (killer_id) REFERENCES Players(player_id) ON DELETE CASCADE,
                         FOREIGN KEY (victim_id) REFERENCES Players(player_id) ON DELETE CASCADE,
                         INDEX idx_killer_id (killer_id),
@@ -141,31 +258,358 @@ pub fn setup_database(
                 // Create PlayerStats table
                 let _ = conn.query_drop(
                     r"CREATE TABLE PlayerStats (
-                        player_id INT UNSIGNED PRIMARY KEY,
+                        player_id INT UNSIGNED NOT NULL,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
                         total_kills INT UNSIGNED DEFAULT 0,
                         total_deaths INT UNSIGNED DEFAULT 0,
                         total_team_kills INT UNSIGNED DEFAULT 0,
                         kd_ratio DECIMAL(6,2) DEFAULT 0,
                         longest_kill DECIMAL(8,4) DEFAULT 0,
                         favorite_weapon VARCHAR(100),
+                        current_streak INT UNSIGNED DEFAULT 0,
+                        best_streak INT UNSIGNED DEFAULT 0,
+                        multi_kill INT UNSIGNED DEFAULT 0,
+                        best_multi_kill INT UNSIGNED DEFAULT 0,
+                        double_kills INT UNSIGNED DEFAULT 0,
+                        triple_kills INT UNSIGNED DEFAULT 0,
+                        first_bloods INT UNSIGNED DEFAULT 0,
+                        weapon_records INT UNSIGNED DEFAULT 0,
+                        last_kill_at DATETIME(3) NULL,
+                        PRIMARY KEY (player_id, server_id),
                         FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE,
                         INDEX idx_total_kills (total_kills),
                         INDEX idx_kd_ratio (kd_ratio)
                     )"
                 );
 
-                println!("Database tables created successfully");
+                // Create WeaponRecords table (longest kill per weapon and server, and who holds it)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE WeaponRecords (
+                        weapon VARCHAR(100) NOT NULL,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
+                        player_id INT UNSIGNED NOT NULL,
+                        kill_id INT UNSIGNED NOT NULL,
+                        distance DECIMAL(8,4) NOT NULL,
+                        set_at DATETIME(3) NOT NULL,
+                        PRIMARY KEY (weapon, server_id),
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE,
+                        FOREIGN KEY (kill_id) REFERENCES PlayerKills(kill_id) ON DELETE CASCADE
+                    )"
+                );
+
+                // Create PlayerAchievements table (unlocked achievements; definitions live in achievements.json)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE PlayerAchievements (
+                        player_id INT UNSIGNED NOT NULL,
+                        achievement_key VARCHAR(64) NOT NULL,
+                        name VARCHAR(100) NOT NULL,
+                        unlocked_at DATETIME(3) NOT NULL,
+                        kill_id INT UNSIGNED NULL,
+                        PRIMARY KEY (player_id, achievement_key),
+                        INDEX idx_unlocked_at (unlocked_at),
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE,
+                        FOREIGN KEY (kill_id) REFERENCES PlayerKills(kill_id) ON DELETE SET NULL
+                    )"
+                );
+
+                // Create PlayerRatings table (Glicko skill rating from PvP kills)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE PlayerRatings (
+                        player_id INT UNSIGNED PRIMARY KEY,
+                        rating DOUBLE NOT NULL DEFAULT 1500,
+                        deviation DOUBLE NOT NULL DEFAULT 350,
+                        games INT UNSIGNED NOT NULL DEFAULT 0,
+                        last_game DATETIME(3),
+                        INDEX idx_rating (rating),
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE
+                    )"
+                );
+
+                // Create PeriodPlayerStats table (player totals per calendar day, week and month)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE PeriodPlayerStats (
+                        period_type ENUM('DAY', 'WEEK', 'MONTH') NOT NULL,
+                        period_start DATE NOT NULL,
+                        player_id INT UNSIGNED NOT NULL,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
+                        kills INT UNSIGNED DEFAULT 0,
+                        deaths INT UNSIGNED DEFAULT 0,
+                        team_kills INT UNSIGNED DEFAULT 0,
+                        longest_kill DECIMAL(8,4) DEFAULT 0,
+                        PRIMARY KEY (period_type, period_start, player_id, server_id),
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE
+                    )"
+                );
+
+                // Create PeriodWeaponStats table (weapon usage per player per calendar day, week and month)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE PeriodWeaponStats (
+                        period_type ENUM('DAY', 'WEEK', 'MONTH') NOT NULL,
+                        period_start DATE NOT NULL,
+                        player_id INT UNSIGNED NOT NULL,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
+                        weapon VARCHAR(100) NOT NULL,
+                        kills INT UNSIGNED DEFAULT 0,
+                        team_kills INT UNSIGNED DEFAULT 0,
+                        total_distance DECIMAL(12,4) DEFAULT 0,
+                        longest_kill DECIMAL(8,4) DEFAULT 0,
+                        PRIMARY KEY (period_type, period_start, player_id, server_id, weapon),
+                        INDEX idx_period_weapon (period_type, period_start, weapon),
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE
+                    )"
+                );
+
+                // Create Seasons table (admin-defined competition periods)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE Seasons (
+                        season_id INT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+                        name VARCHAR(100) NOT NULL,
+                        starts_at DATETIME NOT NULL,
+                        ends_at DATETIME NULL,
+                        archived_at DATETIME NULL,
+                        INDEX idx_starts_at (starts_at)
+                    )"
+                );
+
+                // Create SeasonPlayerStats table (per-season totals; final_rank/final_rating set when archived)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE SeasonPlayerStats (
+                        season_id INT UNSIGNED NOT NULL,
+                        player_id INT UNSIGNED NOT NULL,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
+                        kills INT UNSIGNED DEFAULT 0,
+                        deaths INT UNSIGNED DEFAULT 0,
+                        team_kills INT UNSIGNED DEFAULT 0,
+                        longest_kill DECIMAL(8,4) DEFAULT 0,
+                        final_rank INT UNSIGNED NULL,
+                        final_rating DOUBLE NULL,
+                        PRIMARY KEY (season_id, player_id, server_id),
+                        FOREIGN KEY (season_id) REFERENCES Seasons(season_id) ON DELETE CASCADE,
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE
+                    )"
+                );
+
+                // Create PlayerFactionStats table (per player and faction they fought for)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE PlayerFactionStats (
+                        player_id INT UNSIGNED NOT NULL,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
+                        faction VARCHAR(50) NOT NULL,
+                        kills INT UNSIGNED DEFAULT 0,
+                        deaths INT UNSIGNED DEFAULT 0,
+                        team_kills INT UNSIGNED DEFAULT 0,
+                        wins INT UNSIGNED DEFAULT 0,
+                        losses INT UNSIGNED DEFAULT 0,
+                        PRIMARY KEY (player_id, server_id, faction),
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE,
+                        INDEX idx_faction (faction)
+                    )"
+                );
+
+                // Create FactionWeaponStats table (weapon usage per killer/victim faction pair)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE FactionWeaponStats (
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
+                        killer_faction VARCHAR(50) NOT NULL,
+                        victim_faction VARCHAR(50) NOT NULL,
+                        weapon VARCHAR(100) NOT NULL,
+                        total_kills INT UNSIGNED DEFAULT 0,
+                        total_team_kills INT UNSIGNED DEFAULT 0,
+                        total_distance DECIMAL(12,4) DEFAULT 0,
+                        longest_kill DECIMAL(8,4) DEFAULT 0,
+                        PRIMARY KEY (server_id, killer_faction, victim_faction, weapon),
+                        INDEX idx_weapon (weapon)
+                    )"
+                );
+
+                // Create MatchPlayerStats table (per-match scoreboard)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE MatchPlayerStats (
+                        match_id INT UNSIGNED NOT NULL,
+                        player_id INT UNSIGNED NOT NULL,
+                        kills INT UNSIGNED DEFAULT 0,
+                        deaths INT UNSIGNED DEFAULT 0,
+                        team_kills INT UNSIGNED DEFAULT 0,
+                        faction VARCHAR(50),
+                        longest_kill DECIMAL(8,4) DEFAULT 0,
+                        PRIMARY KEY (match_id, player_id),
+                        FOREIGN KEY (match_id) REFERENCES Matches(match_id) ON DELETE CASCADE,
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE,
+                        INDEX idx_player_id (player_id)
+                    )"
+                );
+
+                // Create MatchFactionResults table (per-match faction totals and WIN/LOSS)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE MatchFactionResults (
+                        match_id INT UNSIGNED NOT NULL,
+                        faction VARCHAR(50) NOT NULL,
+                        kills INT UNSIGNED DEFAULT 0,
+                        deaths INT UNSIGNED DEFAULT 0,
+                        team_kills INT UNSIGNED DEFAULT 0,
+                        result VARCHAR(8),
+                        PRIMARY KEY (match_id, faction),
+                        FOREIGN KEY (match_id) REFERENCES Matches(match_id) ON DELETE CASCADE
+                    )"
+                );
+
+                // Create ModerationActions table (BattlEye kicks/bans, RCon admin messages and team kill rules)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE ModerationActions (
+                        action_id INT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+                        player_id INT UNSIGNED NULL,
+                        battleye_guid CHAR(32),
+                        username VARCHAR(255),
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
+                        action_type VARCHAR(32) NOT NULL,
+                        reason VARCHAR(512),
+                        duration_minutes INT UNSIGNED,
+                        issued_by VARCHAR(100) NOT NULL,
+                        status VARCHAR(16),
+                        created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                        event_id CHAR(32) NULL,
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE SET NULL,
+                        UNIQUE KEY uq_event_id (event_id),
+                        INDEX idx_player_id (player_id),
+                        INDEX idx_battleye_guid (battleye_guid),
+                        INDEX idx_action_type (action_type),
+                        INDEX idx_created_at (created_at)
+                    )"
+                );
+
+                // Create ModerationEvidence table (kills that led to an automatic moderation action)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE ModerationEvidence (
+                        action_id INT UNSIGNED NOT NULL,
+                        kill_id INT UNSIGNED NOT NULL,
+                        PRIMARY KEY (action_id, kill_id),
+                        FOREIGN KEY (action_id) REFERENCES ModerationActions(action_id) ON DELETE CASCADE,
+                        FOREIGN KEY (kill_id) REFERENCES PlayerKills(kill_id) ON DELETE CASCADE
+                    )"
+                );
+
+                // Create SuspicionIncidents table (review queue for statistically implausible play)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE SuspicionIncidents (
+                        incident_id INT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+                        player_id INT UNSIGNED NOT NULL,
+                        kind VARCHAR(32) NOT NULL,
+                        score DOUBLE NOT NULL,
+                        details VARCHAR(255) NOT NULL,
+                        status VARCHAR(16) NOT NULL DEFAULT 'OPEN',
+                        created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                        reviewed_by VARCHAR(64),
+                        reviewed_at DATETIME,
+                        INDEX idx_status (status, score),
+                        INDEX idx_player_kind (player_id, kind),
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE CASCADE
+                    )"
+                );
+
+                // Create SuspicionEvidence table (kills supporting a suspicion incident)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE SuspicionEvidence (
+                        incident_id INT UNSIGNED NOT NULL,
+                        kill_id INT UNSIGNED NOT NULL,
+                        PRIMARY KEY (incident_id, kill_id),
+                        FOREIGN KEY (incident_id) REFERENCES SuspicionIncidents(incident_id) ON DELETE CASCADE,
+                        FOREIGN KEY (kill_id) REFERENCES PlayerKills(kill_id) ON DELETE CASCADE
+                    )"
+                );
+
+                // Create ChatMessages table (in-game chat; player_id is NULL for unknown names)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE ChatMessages (
+                        message_id INT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+                        player_id INT UNSIGNED NULL,
+                        username VARCHAR(255) NOT NULL,
+                        server_id VARCHAR(32) NOT NULL DEFAULT 'default',
+                        channel VARCHAR(32) NOT NULL,
+                        message TEXT NOT NULL,
+                        sent_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                        event_id CHAR(32) NULL,
+                        FOREIGN KEY (player_id) REFERENCES Players(player_id) ON DELETE SET NULL,
+                        UNIQUE KEY uq_event_id (event_id),
+                        INDEX idx_player_id (player_id),
+                        INDEX idx_username (username),
+                        INDEX idx_sent_at (sent_at)
+                    )"
+                );
+
+                // Create PlayerMerges table (admin merges of duplicate players, with what is needed to undo them)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE PlayerMerges (
+                        merge_id INT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+                        source_id INT UNSIGNED NOT NULL,
+                        target_id INT UNSIGNED NOT NULL,
+                        merged_by VARCHAR(64) NOT NULL,
+                        merged_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                        undone_by VARCHAR(64) NULL,
+                        undone_at DATETIME NULL,
+                        snapshot MEDIUMTEXT NOT NULL,
+                        FOREIGN KEY (source_id) REFERENCES Players(player_id) ON DELETE CASCADE,
+                        FOREIGN KEY (target_id) REFERENCES Players(player_id) ON DELETE CASCADE,
+                        INDEX idx_source_id (source_id),
+                        INDEX idx_target_id (target_id)
+                    )"
+                );
+
+                // Create PlayerMergeKills table (the kills a merge moved from the source player)
+                let _ = conn.query_drop(
+                    r"CREATE TABLE PlayerMergeKills (
+                        merge_id INT UNSIGNED NOT NULL,
+                        kill_id INT UNSIGNED NOT NULL,
+                        role ENUM('killer', 'victim') NOT NULL,
+                        PRIMARY KEY (merge_id, kill_id, role),
+                        FOREIGN KEY (merge_id) REFERENCES PlayerMerges(merge_id) ON DELETE CASCADE,
+                        FOREIGN KEY (kill_id) REFERENCES PlayerKills(kill_id) ON DELETE CASCADE
+                    )"
+                );
+
+                info!("Database tables created successfully");
 
                 // On success, update .env to mark DATABASE_SETUP_COMPLETE=true
                 if let Err(e) = set_dotenv_key("DATABASE_SETUP_COMPLETE", "true") {
-                    eprintln!("Failed to update .env: {}", e);
+                    error!("Failed to update .env: {}", e);
                 } else {
-                    println!("Updated .env: DATABASE_SETUP_COMPLETE=true");
+                    info!("Updated .env: DATABASE_SETUP_COMPLETE=true");
                 }
             }
-            Err(e) => eprintln!("Failed to get connection: {}", e),
+            Err(e) => error!("Failed to get connection: {}", e),
         },
-        Err(e) => eprintln!("Failed to create pool: {}", e),
+        Err(e) => error!("Failed to create pool: {}", e),
+    }
+}
+
+// Whether a failed write may succeed when tried again: the database could not be reached or the
+// statement lost a lock. An error the server raises for the statement itself would only repeat.
+pub fn is_transient(e: &mysql::Error) -> bool {
+    match e {
+        // Too many connections, shutting down, lock wait timeout, deadlock
+        mysql::Error::MySqlError(e) => matches!(e.code, 1040 | 1053 | 1205 | 1213),
+        _ => true,
+    }
+}
+
+// Shared pool for the watcher threads; None when DB env vars are missing or the pool can't be created.
+pub fn init_db_pool() -> Option<Pool> {
+    let database_user = env::var("DATABASE_USER").ok()?;
+    let database_password = env::var("DATABASE_PASSWORD").ok()?;
+    let database_ip = env::var("DATABASE_IP").ok()?;
+    let database_port = env::var("DATABASE_PORT").ok()?;
+    let database_name = env::var("DATABASE_NAME").ok()?;
+
+    let url = format!(
+        "mysql://{}:{}@{}:{}/{}",
+        database_user, database_password, database_ip, database_port, database_name
+    );
+
+    match Pool::new(url.as_str()) {
+        Ok(p) => Some(p),
+        Err(e) => {
+            error!("Failed to create DB pool: {}", e);
+            None
+        }
     }
 }
 
@@ -0,0 +1,293 @@
+use log::{error, info, warn};
+use mysql::{params, prelude::*, Pool, PooledConn, TxOpts};
+use serde_json::{json, Value};
+use std::env;
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::event_bus::{BusEvent, EventBus};
+use crate::kill_watcher::KillEvent;
+
+// Link codes avoid characters that are easy to confuse when typed in game chat (0/O, 1/I).
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 8;
+pub const LINK_CODE_TTL_MINUTES: u32 = 15;
+const LINK_COMMAND: &str = "!link";
+
+#[derive(Clone)]
+pub struct DiscordConfig {
+    pub webhook_url: String,
+}
+
+impl DiscordConfig {
+    // Kill notifications are optional; they are only sent when DISCORD_WEBHOOK_URL is set.
+    pub fn from_env() -> Option<Self> {
+        let webhook_url = env::var("DISCORD_WEBHOOK_URL").ok().filter(|u| !u.is_empty())?;
+        Some(Self { webhook_url })
+    }
+}
+
+// Issues a one-time code for a Discord user. Any earlier code for that user stops working.
+pub fn issue_link_code(conn: &mut PooledConn, discord_id: &str) -> Result<String, mysql::Error> {
+    conn.exec_drop(
+        "DELETE FROM DiscordLinkCodes WHERE discord_id = :did OR expires_at <= NOW()",
+        params! { "did" => discord_id },
+    )?;
+
+    let code: String = Uuid::new_v4()
+        .as_bytes()
+        .iter()
+        .take(CODE_LENGTH)
+        .map(|b| CODE_ALPHABET[*b as usize % CODE_ALPHABET.len()] as char)
+        .collect();
+    conn.exec_drop(
+        r"INSERT INTO DiscordLinkCodes (code, discord_id, expires_at)
+        VALUES (:code, :did, NOW() + INTERVAL :ttl MINUTE)",
+        params! { "code" => &code, "did" => discord_id, "ttl" => LINK_CODE_TTL_MINUTES },
+    )?;
+    Ok(code)
+}
+
+// Binds the code's Discord user to a player, replacing any previous link on either side.
+// Returns the Discord id, or None if the code is unknown or expired.
+pub fn redeem_link_code(conn: &mut PooledConn, code: &str, player_id: u64) -> Result<Option<String>, mysql::Error> {
+    let code = code.trim().to_uppercase();
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    let discord_id: Option<String> = tx.exec_first(
+        "SELECT discord_id FROM DiscordLinkCodes WHERE code = :code AND expires_at > NOW() FOR UPDATE",
+        params! { "code" => &code },
+    )?;
+    let Some(discord_id) = discord_id else {
+        return Ok(None);
+    };
+
+    tx.exec_drop("DELETE FROM DiscordLinkCodes WHERE code = :code", params! { "code" => &code })?;
+    tx.exec_drop(
+        "DELETE FROM PlayerDiscordLink WHERE player_id = :pid OR discord_id = :did",
+        params! { "pid" => player_id, "did" => &discord_id },
+    )?;
+    tx.exec_drop(
+        "INSERT INTO PlayerDiscordLink (player_id, discord_id) VALUES (:pid, :did)",
+        params! { "pid" => player_id, "did" => &discord_id },
+    )?;
+    tx.commit()?;
+    Ok(Some(discord_id))
+}
+
+pub fn unlink_discord(conn: &mut PooledConn, discord_id: &str) -> Result<bool, mysql::Error> {
+    conn.exec_drop(
+        "DELETE FROM PlayerDiscordLink WHERE discord_id = :did",
+        params! { "did" => discord_id },
+    )?;
+    Ok(conn.affected_rows() > 0)
+}
+
+pub fn linked_player(conn: &mut PooledConn, discord_id: &str) -> Result<Option<u64>, mysql::Error> {
+    conn.exec_first(
+        "SELECT player_id FROM PlayerDiscordLink WHERE discord_id = :did",
+        params! { "did" => discord_id },
+    )
+}
+
+// "!link ABCD2345" typed in any chat channel.
+pub fn parse_link_command(message: &str) -> Option<&str> {
+    let mut words = message.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case(LINK_COMMAND) {
+        return None;
+    }
+    words.next()
+}
+
+// Chat only carries the display name, so the code is redeemed for the player who used it most recently.
+pub fn redeem_from_chat(conn: &mut PooledConn, code: &str, username: &str) -> Result<(), mysql::Error> {
+    let player_id: Option<u64> = conn.exec_first(
+        "SELECT player_id FROM PlayerNames WHERE username = :u ORDER BY last_used DESC LIMIT 1",
+        params! { "u" => username },
+    )?;
+    let Some(player_id) = player_id else {
+        warn!(username; "Discord link code from unknown player {}", username);
+        return Ok(());
+    };
+
+    match redeem_link_code(conn, code, player_id)? {
+        Some(discord_id) => info!(player_id; "Linked {} (player {}) to Discord user {}", username, player_id, discord_id),
+        None => warn!(player_id; "{} used an unknown or expired Discord link code", username),
+    }
+    Ok(())
+}
+
+// Posts a webhook message for every kill involving a linked player, mentioning them.
+pub fn run_discord_notifier(config: DiscordConfig, pool: Pool, bus: EventBus) {
+    let events = bus.subscribe("discord");
+    info!("Discord notifications enabled");
+
+    for event in events {
+        let BusEvent::Kill { kill, .. } = event else { continue };
+
+        let mut conn = match pool.get_conn() {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Failed to get DB connection from pool: {}", e);
+                continue;
+            }
+        };
+        let killer = discord_for_name(&mut conn, &kill.killer_name);
+        let victim = discord_for_name(&mut conn, &kill.victim_name);
+        let (killer, victim) = match (killer, victim) {
+            (Ok(k), Ok(v)) => (k, v),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Failed to look up Discord links: {}", e);
+                continue;
+            }
+        };
+        if killer.is_none() && victim.is_none() {
+            continue;
+        }
+
+        let body = kill_message(&kill, killer.as_deref(), victim.as_deref());
+        if let Err(e) = post_webhook(&config.webhook_url, &body) {
+            error!("Failed to send Discord notification: {}", e);
+        }
+    }
+}
+
+fn discord_for_name(conn: &mut PooledConn, username: &str) -> Result<Option<String>, mysql::Error> {
+    conn.exec_first(
+        r"SELECT d.discord_id FROM PlayerDiscordLink d
+        WHERE d.player_id = (SELECT player_id FROM PlayerNames WHERE username = :u ORDER BY last_used DESC LIMIT 1)",
+        params! { "u" => username },
+    )
+}
+
+fn kill_message(kill: &KillEvent, killer: Option<&str>, victim: Option<&str>) -> Value {
+    let mention = |name: &str, discord_id: Option<&str>| match discord_id {
+        Some(id) => format!("<@{}>", id),
+        None => format!("**{}**", name),
+    };
+
+    let mut content = format!(
+        "{} {} {}",
+        mention(&kill.killer_name, killer),
+        if kill.is_team_kill { "team killed" } else { "killed" },
+        mention(&kill.victim_name, victim)
+    );
+    if let Some(weapon) = &kill.weapon {
+        content.push_str(&format!(" with {}", weapon));
+    }
+    if let Some(distance) = kill.distance {
+        content.push_str(&format!(" ({:.1} m)", distance));
+    }
+
+    // Only the linked players themselves may be pinged, whatever the names contain.
+    let users: Vec<&str> = [killer, victim].into_iter().flatten().collect();
+    json!({
+        "content": content,
+        "allowed_mentions": { "parse": [], "users": users },
+    })
+}
+
+fn post_webhook(url: &str, body: &Value) -> Result<(), Box<ureq::Error>> {
+    let send = || {
+        ureq::post(url)
+            .timeout(Duration::from_secs(10))
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())
+            .map(|_| ())
+            .map_err(Box::new)
+    };
+
+    match send().map_err(|e| *e) {
+        // Discord answers 429 with the number of seconds to wait; retry once after that.
+        Err(ureq::Error::Status(429, response)) => {
+            let retry_after = response
+                .into_string()
+                .ok()
+                .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+                .and_then(|v| v["retry_after"].as_f64())
+                .unwrap_or(1.0);
+            thread::sleep(Duration::from_secs_f64(retry_after.clamp(0.0, 60.0)));
+            send()
+        }
+        result => result.map_err(Box::new),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tail::LogTime;
+    use std::thread::JoinHandle;
+    use tiny_http::{Response, Server};
+
+    // A webhook endpoint on 127.0.0.1 that answers with `statuses` in turn and returns the bodies it got.
+    fn mock_webhook(statuses: &'static [u16]) -> (DiscordConfig, JoinHandle<Vec<Value>>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let webhook_url = format!("http://{}/api/webhooks/1/token", server.server_addr().to_ip().unwrap());
+        let handle = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for &status in statuses {
+                let mut request = server.recv_timeout(Duration::from_secs(5)).unwrap().expect("no webhook request");
+                assert_eq!(request.url(), "/api/webhooks/1/token");
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                bodies.push(serde_json::from_str(&body).unwrap());
+                let response = match status {
+                    429 => Response::from_string(r#"{"message": "You are being rate limited.", "retry_after": 0.05}"#),
+                    _ => Response::from_string(""),
+                };
+                request.respond(response.with_status_code(status)).unwrap();
+            }
+            // Nothing is sent after the answers run out.
+            assert!(server.recv_timeout(Duration::from_millis(200)).unwrap().is_none());
+            bodies
+        });
+        (DiscordConfig { webhook_url }, handle)
+    }
+
+    fn kill() -> KillEvent {
+        KillEvent {
+            server_id: "default".to_string(),
+            logged: LogTime::default(),
+            killer_name: "@everyone".to_string(),
+            victim_name: "Bob".to_string(),
+            weapon: Some("M16A2".to_string()),
+            distance: Some(123.45),
+            is_team_kill: false,
+            killer_faction: None,
+            victim_faction: None,
+            event_id: String::new(),
+        }
+    }
+
+    #[test]
+    fn kill_is_posted_with_only_the_linked_player_mentionable() {
+        let (config, webhook) = mock_webhook(&[204]);
+        post_webhook(&config.webhook_url, &kill_message(&kill(), None, Some("123456789012345678"))).unwrap();
+
+        let bodies = webhook.join().unwrap();
+        assert_eq!(
+            bodies,
+            vec![json!({
+                "content": "**@everyone** killed <@123456789012345678> with M16A2 (123.5 m)",
+                "allowed_mentions": { "parse": [], "users": ["123456789012345678"] },
+            })]
+        );
+    }
+
+    #[test]
+    fn rate_limited_post_is_retried_once() {
+        let (config, webhook) = mock_webhook(&[429, 204]);
+        let body = kill_message(&kill(), None, Some("123456789012345678"));
+        post_webhook(&config.webhook_url, &body).unwrap();
+        assert_eq!(webhook.join().unwrap(), vec![body.clone(), body]);
+    }
+
+    #[test]
+    fn rate_limited_retry_is_not_retried_again() {
+        let (config, webhook) = mock_webhook(&[429, 429]);
+        let result = post_webhook(&config.webhook_url, &kill_message(&kill(), None, None));
+        assert!(matches!(result.map_err(|e| *e), Err(ureq::Error::Status(429, _))));
+        assert_eq!(webhook.join().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,266 @@
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::associations::BanEvasionAlert;
+use crate::kill_watcher::KillEvent;
+use crate::metrics;
+use crate::player_monitor::{PlayerConnection, PlayerDisconnect};
+
+// Events buffered per subscriber; a consumer that falls this far behind starts missing events
+// instead of holding up the watchers.
+pub const SUBSCRIBER_BUFFER: usize = 256;
+
+// Every value BusEvent::event_type can return, for validating stream filters and webhook rules.
+pub const EVENT_TYPES: &[&str] = &["kill", "connect", "disconnect", "new_player", "new_ip", "milestone", "achievement", "ban_evasion"];
+
+#[derive(Debug, Clone)]
+pub enum BusEvent {
+    // kill_id is the PlayerKills row, when the kill could be stored.
+    Kill { kill: KillEvent, match_id: Option<u64>, kill_id: Option<u64> },
+    PlayerConnected(PlayerConnection),
+    PlayerDisconnected(PlayerDisconnect),
+    // First time this Reforger ID was seen.
+    NewPlayer(PlayerConnection),
+    // A known player connected from an address not in their ConnectionLogs.
+    NewIp(PlayerConnection),
+    // The killer's lifetime kill count reached one of kill_watcher::KILL_MILESTONES.
+    Milestone { server_id: String, username: String, kills: u32 },
+    // The killer unlocked an achievement (key and name from the achievement definitions).
+    Achievement { server_id: String, username: String, key: String, name: String },
+    // A new Reforger ID shares an IP or GUID with a banned player.
+    BanEvasion(BanEvasionAlert),
+}
+
+impl BusEvent {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            BusEvent::Kill { .. } => "kill",
+            BusEvent::PlayerConnected(_) => "connect",
+            BusEvent::PlayerDisconnected(_) => "disconnect",
+            BusEvent::NewPlayer(_) => "new_player",
+            BusEvent::NewIp(_) => "new_ip",
+            BusEvent::Milestone { .. } => "milestone",
+            BusEvent::Achievement { .. } => "achievement",
+            BusEvent::BanEvasion(_) => "ban_evasion",
+        }
+    }
+
+    // Id of the server the event happened on.
+    pub fn server_id(&self) -> &str {
+        match self {
+            BusEvent::Kill { kill, .. } => &kill.server_id,
+            BusEvent::PlayerConnected(p) | BusEvent::NewPlayer(p) | BusEvent::NewIp(p) => &p.server_id,
+            BusEvent::PlayerDisconnected(p) => &p.server_id,
+            BusEvent::Milestone { server_id, .. } | BusEvent::Achievement { server_id, .. } => server_id,
+            BusEvent::BanEvasion(a) => &a.server_id,
+        }
+    }
+
+    // Case-insensitive match on any player name in the event.
+    pub fn involves(&self, name: &str) -> bool {
+        match self {
+            BusEvent::Kill { kill, .. } => {
+                kill.killer_name.eq_ignore_ascii_case(name) || kill.victim_name.eq_ignore_ascii_case(name)
+            }
+            BusEvent::PlayerConnected(p) | BusEvent::NewPlayer(p) | BusEvent::NewIp(p) => {
+                p.username.eq_ignore_ascii_case(name)
+            }
+            BusEvent::PlayerDisconnected(p) => p.username.eq_ignore_ascii_case(name),
+            BusEvent::Milestone { username, .. } | BusEvent::Achievement { username, .. } => {
+                username.eq_ignore_ascii_case(name)
+            }
+            BusEvent::BanEvasion(a) => {
+                a.username.eq_ignore_ascii_case(name)
+                    || a.banned_name.as_ref().is_some_and(|b| b.eq_ignore_ascii_case(name))
+            }
+        }
+    }
+
+    // Public representation for the live streams; IP addresses and GUIDs are left out on purpose.
+    pub fn to_json(&self) -> Value {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut value = match self {
+            BusEvent::Kill { kill, match_id, kill_id } => json!({
+                "type": self.event_type(),
+                "timestamp": timestamp,
+                "kill_id": kill_id,
+                "event_id": kill.event_id,
+                "killer": kill.killer_name,
+                "victim": kill.victim_name,
+                "weapon": kill.weapon,
+                "distance": kill.distance,
+                "is_team_kill": kill.is_team_kill,
+                "killer_faction": kill.killer_faction,
+                "victim_faction": kill.victim_faction,
+                "match_id": match_id,
+            }),
+            BusEvent::PlayerConnected(p) | BusEvent::NewPlayer(p) | BusEvent::NewIp(p) => json!({
+                "type": self.event_type(),
+                "timestamp": timestamp,
+                "name": p.username,
+                "reforger_id": p.reforger_id,
+            }),
+            BusEvent::PlayerDisconnected(p) => json!({
+                "type": self.event_type(),
+                "timestamp": timestamp,
+                "name": p.username,
+            }),
+            BusEvent::Milestone { username, kills, .. } => json!({
+                "type": self.event_type(),
+                "timestamp": timestamp,
+                "name": username,
+                "kills": kills,
+            }),
+            BusEvent::Achievement { username, key, name, .. } => json!({
+                "type": self.event_type(),
+                "timestamp": timestamp,
+                "name": username,
+                "achievement": key,
+                "achievement_name": name,
+            }),
+            BusEvent::BanEvasion(a) => json!({
+                "type": self.event_type(),
+                "timestamp": timestamp,
+                "name": a.username,
+                "reforger_id": a.reforger_id,
+                "banned_player_id": a.banned_player_id,
+                "banned_name": a.banned_name,
+                "ban_reason": a.reason,
+            }),
+        };
+        value["server"] = json!(self.server_id());
+        value
+    }
+}
+
+struct Subscriber {
+    // Kind of consumer ("webhooks", "sse", ...), for the queue metrics.
+    name: &'static str,
+    events: SyncSender<BusEvent>,
+    pending: Arc<AtomicUsize>,
+}
+
+// The receiving end of a subscription; it keeps count of the events still queued for it.
+pub struct Subscription {
+    events: Receiver<BusEvent>,
+    pending: Arc<AtomicUsize>,
+}
+
+impl Subscription {
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<BusEvent, RecvTimeoutError> {
+        let event = self.events.recv_timeout(timeout)?;
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+        Ok(event)
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = BusEvent;
+
+    fn next(&mut self) -> Option<BusEvent> {
+        let event = self.events.recv().ok()?;
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+        Some(event)
+    }
+}
+
+// In-process fan-out of live events from the watchers to any number of subscribers
+// (SSE/WebSocket clients, notifiers). Publishing never blocks.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self, name: &'static str) -> Subscription {
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_BUFFER);
+        let pending = Arc::new(AtomicUsize::new(0));
+        self.subscribers.lock().unwrap().push(Subscriber { name, events: tx, pending: Arc::clone(&pending) });
+        Subscription { events: rx, pending }
+    }
+
+    pub fn publish(&self, event: BusEvent) {
+        // Subscribers that went away are dropped here; slow ones just miss this event.
+        let mut dropped = Vec::new();
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            // Counted before sending so the subscriber can never take the event first.
+            subscriber.pending.fetch_add(1, Ordering::Relaxed);
+            match subscriber.events.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    subscriber.pending.fetch_sub(1, Ordering::Relaxed);
+                    dropped.push(subscriber.name);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+        // Counted after the lock is released, so the bus and the metrics registry are never locked together.
+        for name in dropped {
+            metrics::inc("df_backend_event_queue_dropped_total", &[("subscriber", name)]);
+        }
+    }
+
+    // Waits until the subscribers have taken every queued event, or the deadline passes (returning false).
+    pub fn drain(&self, deadline: Instant) -> bool {
+        while self.queue_depths().values().any(|depth| *depth > 0) {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        true
+    }
+
+    // Events waiting per kind of subscriber, summed over its subscribers.
+    pub fn queue_depths(&self) -> BTreeMap<&'static str, usize> {
+        let mut depths = BTreeMap::new();
+        for subscriber in self.subscribers.lock().unwrap().iter() {
+            *depths.entry(subscriber.name).or_default() += subscriber.pending.load(Ordering::Relaxed);
+        }
+        depths
+    }
+
+    // Fullest queue per kind of subscriber; each subscriber has its own SUBSCRIBER_BUFFER.
+    pub fn max_queue_depths(&self) -> BTreeMap<&'static str, usize> {
+        let mut depths: BTreeMap<&'static str, usize> = BTreeMap::new();
+        for subscriber in self.subscribers.lock().unwrap().iter() {
+            let depth = depths.entry(subscriber.name).or_default();
+            *depth = (*depth).max(subscriber.pending.load(Ordering::Relaxed));
+        }
+        depths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn milestone() -> BusEvent {
+        BusEvent::Milestone { server_id: "default".to_string(), username: "Alpha".to_string(), kills: 100 }
+    }
+
+    #[test]
+    fn queue_depths_per_subscriber_kind() {
+        let bus = EventBus::new();
+        let mut first = bus.subscribe("sse");
+        let _second = bus.subscribe("sse");
+        let _discord = bus.subscribe("discord");
+        for _ in 0..3 {
+            bus.publish(milestone());
+        }
+        first.next().unwrap();
+
+        assert_eq!(bus.queue_depths(), BTreeMap::from([("discord", 3), ("sse", 5)]));
+        assert_eq!(bus.max_queue_depths(), BTreeMap::from([("discord", 3), ("sse", 3)]));
+    }
+}
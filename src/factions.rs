@@ -0,0 +1,141 @@
+use log::{info, warn};
+use mysql::{params, prelude::*, PooledConn};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::LazyLock;
+
+// Built-in names for the factions we know about. FACTION_MAP_PATH (default `factions.json`)
+// can add or override entries, e.g. { "#MyMod-Faction_PMC": "PMC" }.
+const DEFAULT_FACTIONS: &[(&str, &str)] = &[
+    ("#WCS-Faction_NATO", "NATO"),
+    ("#WCS-Faction_RU", "RU"),
+    ("#AR-Faction_US", "US"),
+    ("#AR-Faction_USSR", "USSR"),
+    ("#AR-Faction_FIA", "FIA"),
+    ("#AR-Faction_Civilians", "CIV"),
+];
+
+static FACTION_MAP: LazyLock<HashMap<String, String>> = LazyLock::new(load_faction_map);
+
+fn load_faction_map() -> HashMap<String, String> {
+    let mut map: HashMap<String, String> = DEFAULT_FACTIONS
+        .iter()
+        .map(|(raw, name)| (raw.to_string(), name.to_string()))
+        .collect();
+
+    let explicit_path = env::var("FACTION_MAP_PATH").ok();
+    let path = explicit_path.clone().unwrap_or_else(|| "factions.json".to_string());
+    match fs::read_to_string(&path) {
+        Ok(content) => match serde_json::from_str::<HashMap<String, String>>(&content) {
+            Ok(custom) => {
+                info!("Loaded {} faction mapping(s) from {}", custom.len(), path);
+                map.extend(custom);
+            }
+            Err(e) => warn!("Ignoring faction map {}: {}", path, e),
+        },
+        // The file is optional unless it was configured explicitly.
+        Err(e) if explicit_path.is_some() => warn!("Failed to read faction map {}: {}", path, e),
+        Err(_) => {}
+    }
+
+    map
+}
+
+// Maps the localisation key the game logs ("#WCS-Faction_NATO") to a short faction name.
+// Unknown keys following the usual "#<Prefix>-Faction_<Name>" pattern fall back to <Name>,
+// so modded factions still group cleanly before they are added to the map.
+pub fn normalize_faction(value: &str) -> String {
+    if let Some(name) = FACTION_MAP.get(value) {
+        return name.clone();
+    }
+    match value.strip_prefix('#').and_then(|v| v.split_once("Faction_")) {
+        Some((_, name)) if !name.is_empty() => name.to_string(),
+        _ => value.to_string(),
+    }
+}
+
+pub fn print_faction_stats(conn: &mut PooledConn, player: Option<&str>) -> Result<(), mysql::Error> {
+    if let Some(name) = player {
+        let rows: Vec<(String, u32, u32, u32, u32, u32)> = conn.exec(
+            r"SELECT faction, SUM(kills), SUM(deaths), SUM(team_kills), SUM(wins), SUM(losses)
+            FROM PlayerFactionStats
+            WHERE player_id IN (SELECT player_id FROM PlayerNames WHERE username = :name)
+            GROUP BY faction
+            ORDER BY SUM(kills) DESC",
+            params! { "name" => name },
+        )?;
+        println!("=== FACTION STATS FOR {} ===", name);
+        if rows.is_empty() {
+            println!("No faction stats recorded");
+        }
+        for (faction, kills, deaths, team_kills, wins, losses) in rows {
+            println!(
+                "{}: {} kills, {} deaths, {} team kills, {} wins, {} losses",
+                faction, kills, deaths, team_kills, wins, losses
+            );
+        }
+        println!();
+        return Ok(());
+    }
+
+    let overview: Vec<(String, u32, u32, u32, u32, u32)> = conn.query(
+        r"SELECT faction, COUNT(*), COALESCE(SUM(result = 'WIN'), 0) AS wins,
+            COALESCE(SUM(result = 'LOSS'), 0), SUM(kills), SUM(deaths)
+        FROM MatchFactionResults
+        GROUP BY faction
+        ORDER BY wins DESC, SUM(kills) DESC",
+    )?;
+    println!("=== FACTIONS ===");
+    for (faction, matches, wins, losses, kills, deaths) in overview {
+        println!(
+            "{}: {} matches, {} wins, {} losses, {} kills, {} deaths",
+            faction, matches, wins, losses, kills, deaths
+        );
+    }
+
+    let weapons: Vec<(String, String, String, u32, f64)> = conn.query(
+        r"SELECT killer_faction, victim_faction, weapon, SUM(total_kills), MAX(longest_kill)
+        FROM FactionWeaponStats
+        GROUP BY killer_faction, victim_faction, weapon
+        ORDER BY SUM(total_kills) DESC
+        LIMIT 15",
+    )?;
+    println!("Top weapons by matchup:");
+    for (killer, victim, weapon, kills, longest) in weapons {
+        println!(
+            "  {} vs {} with {}: {} kills (longest {:.1} m)",
+            display_or_unknown(&killer),
+            display_or_unknown(&victim),
+            display_or_unknown(&weapon),
+            kills,
+            longest
+        );
+    }
+    println!();
+    Ok(())
+}
+
+fn display_or_unknown(value: &str) -> &str {
+    if value.is_empty() { "unknown" } else { value }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_factions_use_their_short_name() {
+        assert_eq!(normalize_faction("#WCS-Faction_NATO"), "NATO");
+        assert_eq!(normalize_faction("#AR-Faction_Civilians"), "CIV");
+    }
+
+    #[test]
+    fn unknown_factions_fall_back_to_the_key_suffix() {
+        assert_eq!(normalize_faction("#MyMod-Faction_PMC"), "PMC");
+        // Anything that does not follow the pattern is kept as logged.
+        assert_eq!(normalize_faction("#MyMod-Faction_"), "#MyMod-Faction_");
+        assert_eq!(normalize_faction("MyMod-Faction_PMC"), "MyMod-Faction_PMC");
+        assert_eq!(normalize_faction("Insurgents"), "Insurgents");
+    }
+}
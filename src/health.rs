@@ -0,0 +1,265 @@
+use log::{error, info};
+use mysql::{prelude::*, Pool};
+use serde_json::{Value, json};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::database_setup::init_db_pool;
+use crate::event_bus::{EventBus, SUBSCRIBER_BUFFER};
+use crate::metrics;
+use crate::shutdown;
+
+// Restart delays double from the first to the last; a worker that ran this long without failing starts over.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const HEALTHY_RUN: Duration = Duration::from_secs(300);
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+static WORKERS: LazyLock<Mutex<BTreeMap<String, Worker>>> = LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WorkerState {
+    Running,
+    // Waiting out the backoff after a panic or an unexpected return.
+    Restarting,
+    // Gave up after too many failures in a row.
+    Failed,
+    // Returned after a shutdown request.
+    Stopped,
+}
+
+impl WorkerState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            WorkerState::Running => "running",
+            WorkerState::Restarting => "restarting",
+            WorkerState::Failed => "failed",
+            WorkerState::Stopped => "stopped",
+        }
+    }
+}
+
+struct Worker {
+    critical: bool,
+    state: WorkerState,
+    restarts: u32,
+    last_error: Option<String>,
+}
+
+fn set_state(name: &str, state: WorkerState, error: Option<String>) {
+    let mut workers = WORKERS.lock().unwrap();
+    let Some(worker) = workers.get_mut(name) else { return };
+    if state == WorkerState::Restarting {
+        worker.restarts += 1;
+    }
+    worker.state = state;
+    if error.is_some() {
+        worker.last_error = error;
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+// Why `Supervisor::wait` returned.
+pub enum Stop {
+    Shutdown,
+    WorkerFailed(String),
+}
+
+// Runs the long-lived worker threads, restarting them with backoff when they panic or return.
+pub struct Supervisor {
+    max_restarts: u32,
+    failures: Sender<String>,
+    failed: Receiver<String>,
+}
+
+impl Supervisor {
+    // SUPERVISOR_MAX_RESTARTS failures in a row make a worker give up (0 restarts forever).
+    pub fn from_env() -> Self {
+        let (failures, failed) = mpsc::channel();
+        Self {
+            max_restarts: env::var("SUPERVISOR_MAX_RESTARTS").ok().and_then(|v| v.parse().ok()).unwrap_or(10),
+            failures,
+            failed,
+        }
+    }
+
+    // `run` is called again for every restart, until it gives up or returns after a shutdown request.
+    // When a critical worker gives up, `wait` returns.
+    pub fn spawn(&self, name: impl Into<String>, critical: bool, mut run: impl FnMut() + Send + 'static) {
+        let name = name.into();
+        WORKERS.lock().unwrap().insert(
+            name.clone(),
+            Worker { critical, state: WorkerState::Running, restarts: 0, last_error: None },
+        );
+        let max_restarts = self.max_restarts;
+        let failures = self.failures.clone();
+
+        // Named after the worker so panics logged from it say which one it was.
+        let builder = thread::Builder::new().name(name.clone());
+        builder.spawn(move || {
+            let mut backoff = FIRST_BACKOFF;
+            let mut failures_in_row = 0;
+            loop {
+                let started = Instant::now();
+                let reason = match panic::catch_unwind(AssertUnwindSafe(&mut run)) {
+                    Ok(()) => "stopped unexpectedly".to_string(),
+                    Err(payload) => format!("panicked: {}", panic_message(payload.as_ref())),
+                };
+                if shutdown::requested() {
+                    set_state(&name, WorkerState::Stopped, None);
+                    return;
+                }
+                if started.elapsed() >= HEALTHY_RUN {
+                    backoff = FIRST_BACKOFF;
+                    failures_in_row = 0;
+                }
+                failures_in_row += 1;
+
+                if max_restarts > 0 && failures_in_row > max_restarts {
+                    error!(worker = name; "Worker {} {}; giving up after {} restarts", name, reason, max_restarts);
+                    set_state(&name, WorkerState::Failed, Some(reason));
+                    if critical {
+                        let _ = failures.send(name);
+                    }
+                    return;
+                }
+                error!(worker = name; "Worker {} {}; restarting in {}s", name, reason, backoff.as_secs());
+                set_state(&name, WorkerState::Restarting, Some(reason));
+                if shutdown::sleep(backoff) {
+                    set_state(&name, WorkerState::Stopped, None);
+                    return;
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                info!(worker = name; "Restarting worker {}", name);
+                set_state(&name, WorkerState::Running, None);
+            }
+        })
+        .expect("Failed to spawn worker thread");
+    }
+
+    // Blocks until shutdown is requested or a critical worker gives up.
+    pub fn wait(&self) -> Stop {
+        loop {
+            if let Ok(worker) = self.failed.recv_timeout(SHUTDOWN_POLL) {
+                return Stop::WorkerFailed(worker);
+            }
+            if shutdown::requested() {
+                return Stop::Shutdown;
+            }
+        }
+    }
+
+    // After a shutdown request: waits for the critical workers to finish and checkpoint, returning
+    // false if the deadline passed first. The others are left to end with the process.
+    pub fn wait_stopped(&self, deadline: Instant) -> bool {
+        loop {
+            let stopped = WORKERS
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|w| w.critical)
+                .all(|w| matches!(w.state, WorkerState::Stopped | WorkerState::Failed));
+            if stopped {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(SHUTDOWN_POLL);
+        }
+    }
+}
+
+pub struct HealthConfig {
+    // Readiness fails when a log has had unread writes for longer than this.
+    pub max_tail_lag: Duration,
+}
+
+impl HealthConfig {
+    pub fn from_env() -> Self {
+        let seconds = env::var("HEALTH_MAX_TAIL_LAG").ok().and_then(|v| v.parse().ok()).unwrap_or(60);
+        Self { max_tail_lag: Duration::from_secs(seconds) }
+    }
+}
+
+fn workers_report() -> (bool, bool, Value) {
+    let workers = WORKERS.lock().unwrap();
+    let all_running = workers.values().all(|w| w.state == WorkerState::Running);
+    let none_failed = workers.values().all(|w| w.state != WorkerState::Failed);
+    let report = workers
+        .iter()
+        .map(|(name, w)| {
+            let value = json!({
+                "state": w.state.as_str(),
+                "critical": w.critical,
+                "restarts": w.restarts,
+                "last_error": w.last_error,
+            });
+            (name.clone(), value)
+        })
+        .collect();
+    (all_running, none_failed, Value::Object(report))
+}
+
+// Liveness: every worker is running or about to be restarted.
+pub fn liveness() -> (bool, Value) {
+    let (_, none_failed, workers) = workers_report();
+    let status = if none_failed { "ok" } else { "failed" };
+    (none_failed, json!({ "status": status, "workers": workers }))
+}
+
+// Readiness: every worker is running, the database answers, the logs are being kept up with and no
+// event queue is close to full.
+pub fn readiness(config: &HealthConfig, bus: &EventBus, pool: &Mutex<Option<Pool>>) -> (bool, Value) {
+    let (workers_ok, _, workers) = workers_report();
+
+    let database = {
+        let mut pool = pool.lock().unwrap();
+        if pool.is_none() {
+            *pool = init_db_pool();
+        }
+        match pool.as_ref().map(|p| p.get_conn().and_then(|mut conn| conn.query_drop("SELECT 1"))) {
+            Some(Ok(())) => Ok(()),
+            Some(Err(e)) => Err(e.to_string()),
+            None => Err("database is not available".to_string()),
+        }
+    };
+    let lags = metrics::tail_lags();
+    let tailers_ok = lags.iter().all(|(_, _, lag)| *lag <= config.max_tail_lag.as_secs_f64());
+    let tailers: Vec<Value> = lags
+        .iter()
+        .map(|(server, reader, lag)| json!({ "server": server, "reader": reader, "lag_seconds": lag }))
+        .collect();
+
+    let depths = bus.queue_depths();
+    let max_depths = bus.max_queue_depths();
+    // A queue that is nearly full starts dropping events. Every subscriber has its own queue, so many
+    // streaming clients with a few events each are fine.
+    let queue_ok = max_depths.values().all(|depth| *depth * 10 < SUBSCRIBER_BUFFER * 9);
+
+    let ready = workers_ok && database.is_ok() && tailers_ok && queue_ok;
+    let check = |ok: bool| if ok { "ok" } else { "failed" };
+    let report = json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "checks": {
+            "workers": { "status": check(workers_ok), "workers": workers },
+            "database": { "status": check(database.is_ok()), "error": database.err() },
+            "tailers": { "status": check(tailers_ok), "max_lag_seconds": config.max_tail_lag.as_secs(), "tailers": tailers },
+            "queue": { "status": check(queue_ok), "capacity": SUBSCRIBER_BUFFER, "depths": depths, "max_depths": max_depths },
+        },
+    });
+    (ready, report)
+}
@@ -0,0 +1,508 @@
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::api;
+use crate::metrics;
+use crate::servers::ServerConfig;
+use crate::shutdown;
+use crate::tail;
+
+// Most lines an agent sends at once; it waits for each batch to be acknowledged before the next.
+const MAX_BATCH_LINES: usize = 500;
+// Longest protocol message accepted, so a peer cannot make the other side buffer without bound.
+const MAX_MESSAGE_BYTES: u64 = 16 * 1024 * 1024;
+const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+// How long a new connection has to send its hello, and how many may be waiting to do so at once, so
+// unauthenticated peers cannot tie up threads.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_PENDING_HELLOS: usize = 16;
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+const ACCEPT_POLL: Duration = Duration::from_millis(200);
+
+// Agent <-> collector protocol: one JSON object per line over TCP. Offsets are byte positions in the
+// agent's console.log, so a reconnecting agent resumes exactly where the collector's copy ends.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    // Agent -> collector, first message on a connection.
+    Hello { server: String, token: String },
+    // Collector -> agent: how much of the log the collector already has, and the identity of the log
+    // (see `tail::log_identity`) it was copied from, empty if not known.
+    Welcome {
+        offset: u64,
+        #[serde(default)]
+        identity: String,
+    },
+    // Agent -> collector: the complete lines between `offset` and `end` of the log with `identity`.
+    // `reset` means the log was rotated, truncated or replaced, so the copy starts over.
+    Lines {
+        offset: u64,
+        end: u64,
+        reset: bool,
+        #[serde(default)]
+        identity: String,
+        lines: Vec<String>,
+    },
+    // Collector -> agent: everything before `offset` is on disk.
+    Ack { offset: u64 },
+    // Collector -> agent, just before it closes the connection.
+    Error { message: String },
+}
+
+fn send(stream: &mut TcpStream, message: &Message) -> io::Result<()> {
+    let mut line = serde_json::to_string(message).map_err(io::Error::other)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+fn receive(reader: &mut BufReader<TcpStream>) -> io::Result<Message> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_MESSAGE_BYTES).read_line(&mut line)?;
+    if line.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+    }
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message too long"));
+    }
+    serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn unexpected(message: Message) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unexpected message {:?}", message))
+}
+
+// Directory holding the collector's copies of the remote servers' logs (COLLECTOR_SPOOL_DIR, default `spool`).
+fn spool_dir() -> PathBuf {
+    PathBuf::from(env::var("COLLECTOR_SPOOL_DIR").unwrap_or_else(|_| "spool".to_string()))
+}
+
+// The copy of a remote server's console.log, which its kill watcher and player monitor tail.
+pub fn spool_log(server_id: &str) -> PathBuf {
+    spool_dir().join(server_id).join("console.log")
+}
+
+// The collector's copy of one remote server's console.log. `console.offset` next to it records how far
+// into the agent's log the copy reaches, and which log that is; it is written after the lines, so a
+// crash in between makes the agent send them again rather than lose them.
+struct Spool {
+    log: File,
+    offset_path: PathBuf,
+    offset: u64,
+    identity: String,
+}
+
+impl Spool {
+    fn open(server_id: &str) -> io::Result<Self> {
+        let log_path = spool_log(server_id);
+        let dir = spool_dir().join(server_id);
+        fs::create_dir_all(&dir)?;
+        let offset_path = dir.join("console.offset");
+        // "<offset> <identity>", like a checkpoint.
+        let saved = fs::read_to_string(&offset_path).ok().and_then(|s| {
+            let mut parts = s.split_whitespace();
+            let offset: u64 = parts.next()?.parse().ok()?;
+            Some((offset, parts.next().unwrap_or_default().to_string()))
+        });
+        let log = OpenOptions::new().create(true).append(true).open(&log_path)?;
+        // Without a recorded offset the copy cannot be resumed, so the agent starts it over.
+        if saved.is_none() {
+            log.set_len(0)?;
+        }
+        let (offset, identity) = saved.unwrap_or_default();
+        Ok(Self { log, offset_path, offset, identity })
+    }
+
+    // Appends a batch and returns the offset to acknowledge.
+    fn append(&mut self, offset: u64, end: u64, reset: bool, identity: &str, lines: &[String]) -> Result<u64, String> {
+        if reset {
+            self.log.set_len(0).map_err(|e| e.to_string())?;
+            self.offset = 0;
+        }
+        // A batch sent again because its acknowledgement was lost.
+        if end <= self.offset {
+            return Ok(self.offset);
+        }
+        if offset != self.offset {
+            return Err(format!("expected lines from byte {}, got {}", self.offset, offset));
+        }
+
+        if !lines.is_empty() {
+            let mut data = lines.join("\n");
+            data.push('\n');
+            self.log.write_all(data.as_bytes()).map_err(|e| e.to_string())?;
+            self.log.sync_data().map_err(|e| e.to_string())?;
+        }
+        fs::write(&self.offset_path, format!("{} {}", end, identity).trim_end()).map_err(|e| e.to_string())?;
+        self.offset = end;
+        self.identity = identity.to_string();
+        Ok(end)
+    }
+}
+
+#[derive(Clone)]
+pub struct CollectorConfig {
+    pub bind: String,
+}
+
+impl CollectorConfig {
+    // The collector is only started when COLLECTOR_BIND is set (e.g. "0.0.0.0:7878").
+    pub fn from_env() -> Option<Self> {
+        let bind = env::var("COLLECTOR_BIND").ok().filter(|b| !b.is_empty())?;
+        Some(Self { bind })
+    }
+}
+
+struct RemoteServer {
+    token: String,
+    spool: Mutex<Spool>,
+}
+
+// Receives the logs of the servers that have an `agent_token`.
+pub struct Collector {
+    servers: HashMap<String, RemoteServer>,
+}
+
+impl Collector {
+    // Opens the copies of the remote servers' logs, so their monitors have a file to watch from the start.
+    pub fn open(servers: &[ServerConfig]) -> io::Result<Self> {
+        let mut remote = HashMap::new();
+        for server in servers {
+            if let Some(token) = &server.agent_token {
+                let spool = Spool::open(&server.id)?;
+                remote.insert(server.id.clone(), RemoteServer { token: token.clone(), spool: Mutex::new(spool) });
+            }
+        }
+        Ok(Self { servers: remote })
+    }
+
+    fn serve_agent(&self, mut stream: TcpStream, peer: &str, pending: PendingHello) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        // Agents must say who they are promptly; after that they only send when there are new lines.
+        stream.set_read_timeout(Some(HELLO_TIMEOUT))?;
+        let (server_id, remote) = match receive(&mut reader)? {
+            Message::Hello { server, token } => match self.servers.get(&server) {
+                Some(remote) if api::token_matches(&token, &remote.token) => (server, remote),
+                _ => return reject(&mut stream, "unknown server or wrong token"),
+            },
+            _ => return reject(&mut stream, "expected hello"),
+        };
+        drop(pending);
+        stream.set_read_timeout(None)?;
+
+        let (offset, identity) = {
+            let spool = remote.spool.lock().unwrap();
+            (spool.offset, spool.identity.clone())
+        };
+        info!(server = server_id, peer; "Agent {} connected, resuming at byte {}", peer, offset);
+        send(&mut stream, &Message::Welcome { offset, identity })?;
+
+        loop {
+            match receive(&mut reader)? {
+                Message::Lines { offset, end, reset, identity, lines } => {
+                    if reset {
+                        info!(server = server_id; "Agent log was rotated; starting the copy over");
+                    }
+                    let result = remote.spool.lock().unwrap().append(offset, end, reset, &identity, &lines);
+                    if result.is_ok() {
+                        metrics::add("df_backend_collector_lines_received_total", &[("server", &server_id)], lines.len() as u64);
+                    }
+                    match result {
+                        Ok(offset) => send(&mut stream, &Message::Ack { offset })?,
+                        Err(e) => return reject(&mut stream, &e),
+                    }
+                }
+                _ => return reject(&mut stream, "expected lines"),
+            }
+        }
+    }
+}
+
+// A connection that has not said hello yet; counted in `run_collector` until it does or goes away.
+struct PendingHello(Arc<AtomicUsize>);
+
+impl PendingHello {
+    fn start(count: &Arc<AtomicUsize>) -> Option<Self> {
+        count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < MAX_PENDING_HELLOS).then_some(n + 1))
+            .ok()
+            .map(|_| Self(Arc::clone(count)))
+    }
+}
+
+impl Drop for PendingHello {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn reject(stream: &mut TcpStream, message: &str) -> io::Result<()> {
+    let _ = send(stream, &Message::Error { message: message.to_string() });
+    Err(io::Error::other(message.to_string()))
+}
+
+pub fn run_collector(config: CollectorConfig, collector: Arc<Collector>) {
+    let listener = match TcpListener::bind(&config.bind) {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Failed to start collector on {}: {}", config.bind, e);
+            return;
+        }
+    };
+    info!("Collector listening on {}", config.bind);
+    // Accepting without blocking lets the collector notice a shutdown request. A batch already being
+    // received is either on disk with its offset or sent again by the agent.
+    if let Err(e) = listener.set_nonblocking(true) {
+        error!("Failed to configure collector listener: {}", e);
+        return;
+    }
+
+    // One thread per agent; there is one agent per remote server.
+    let pending_hellos = Arc::new(AtomicUsize::new(0));
+    while !shutdown::requested() {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL);
+                continue;
+            }
+            Err(e) => {
+                warn!("Failed to accept agent connection: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = stream.set_nonblocking(false) {
+            warn!("Failed to configure agent connection: {}", e);
+            continue;
+        }
+        let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_else(|_| "unknown".to_string());
+        let Some(pending) = PendingHello::start(&pending_hellos) else {
+            warn!(peer; "Dropping connection from {}: too many connections waiting to say hello", peer);
+            continue;
+        };
+        let collector = Arc::clone(&collector);
+        thread::spawn(move || {
+            if let Err(e) = collector.serve_agent(stream, &peer, pending) {
+                warn!(peer; "Agent {} disconnected: {}", peer, e);
+            }
+        });
+    }
+    info!("Collector stopped");
+}
+
+pub struct AgentConfig {
+    pub collector: String,
+    pub server_id: String,
+    pub token: String,
+    pub log_path: PathBuf,
+    pub poll_interval: Duration,
+}
+
+impl AgentConfig {
+    // The agent ships SERVER_PATH's console.log as server SERVER_ID to the collector at AGENT_COLLECTOR.
+    pub fn from_env() -> Result<Self, String> {
+        let collector = env::var("AGENT_COLLECTOR").ok().filter(|c| !c.is_empty()).ok_or("AGENT_COLLECTOR is not set")?;
+        let token = env::var("AGENT_TOKEN").ok().filter(|t| !t.is_empty()).ok_or("AGENT_TOKEN is not set")?;
+        let server_path = env::var("SERVER_PATH").map_err(|_| "SERVER_PATH is not set")?;
+        Ok(Self {
+            collector,
+            server_id: env::var("SERVER_ID").ok().filter(|id| !id.is_empty()).unwrap_or_else(|| "default".to_string()),
+            token,
+            log_path: PathBuf::from(server_path).join("console.log"),
+            poll_interval: Duration::from_secs(
+                env::var("AGENT_POLL_INTERVAL").ok().and_then(|v| v.parse().ok()).unwrap_or(2).max(1),
+            ),
+        })
+    }
+}
+
+// Runs until shutdown is requested, reconnecting whenever the collector goes away.
+pub fn run_agent(config: AgentConfig) {
+    info!(
+        server = config.server_id;
+        "Shipping {} to collector {} as server {}",
+        config.log_path.display(),
+        config.collector,
+        config.server_id
+    );
+    loop {
+        if let Err(e) = ship(&config) {
+            warn!("Collector connection lost: {}", e);
+        }
+        if shutdown::sleep(RECONNECT_DELAY) {
+            break;
+        }
+    }
+    info!(server = config.server_id; "Agent stopped");
+}
+
+fn ship(config: &AgentConfig) -> io::Result<()> {
+    let mut stream = TcpStream::connect(&config.collector)?;
+    stream.set_read_timeout(Some(ACK_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    send(&mut stream, &Message::Hello { server: config.server_id.clone(), token: config.token.clone() })?;
+    let (mut position, mut identity) = match receive(&mut reader)? {
+        Message::Welcome { offset, identity } => (offset, identity),
+        Message::Error { message } => return Err(io::Error::other(format!("collector refused: {}", message))),
+        other => return Err(unexpected(other)),
+    };
+    info!("Connected to collector {}, resuming at byte {}", config.collector, position);
+
+    // Stops between batches, once the last one sent has been acknowledged.
+    let mut reset = false;
+    while !shutdown::requested() {
+        let mut file = match File::open(&config.log_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                shutdown::sleep(config.poll_interval);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        // Same rotation checks as the local tailers (see `tail::LogTail`): a shorter file, or one whose
+        // first line changed, is a new log.
+        let file_len = file.metadata()?.len();
+        let current = tail::log_identity(&mut file)?;
+        if file_len < position {
+            info!("Log file was truncated or rotated (len {} < position {}); sending it from the start", file_len, position);
+            position = 0;
+            reset = true;
+        } else if position > 0 && (current.is_empty() || (!identity.is_empty() && current != identity)) {
+            info!("Log file was replaced (its first line changed); sending it from the start");
+            position = 0;
+            reset = true;
+        }
+        identity = current;
+
+        let (lines, end) = read_lines(file, position)?;
+        if lines.is_empty() && !reset {
+            shutdown::sleep(config.poll_interval);
+            continue;
+        }
+
+        send(&mut stream, &Message::Lines { offset: position, end, reset, identity: identity.clone(), lines })?;
+        match receive(&mut reader)? {
+            Message::Ack { offset } => {
+                position = offset;
+                reset = false;
+            }
+            Message::Error { message } => return Err(io::Error::other(format!("collector rejected lines: {}", message))),
+            other => return Err(unexpected(other)),
+        }
+    }
+    Ok(())
+}
+
+// Complete lines from `position`, up to MAX_BATCH_LINES, and the offset after the last one. A line
+// still being written (no newline yet) is left for the next read.
+fn read_lines(mut file: File, position: u64) -> io::Result<(Vec<String>, u64)> {
+    file.seek(SeekFrom::Start(position))?;
+    let (lines, end) = tail::read_complete_lines(&mut BufReader::new(file), position, MAX_BATCH_LINES)?;
+    Ok((lines.into_iter().map(|l| l.text).collect(), end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestSpool {
+        spool: Spool,
+        dir: PathBuf,
+    }
+
+    impl TestSpool {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("df_backend_spool_{}_{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let log = OpenOptions::new().create(true).append(true).open(dir.join("console.log")).unwrap();
+            let spool = Spool { log, offset_path: dir.join("console.offset"), offset: 0, identity: String::new() };
+            Self { spool, dir }
+        }
+
+        fn log(&self) -> String {
+            fs::read_to_string(self.dir.join("console.log")).unwrap()
+        }
+
+        fn offset_file(&self) -> String {
+            fs::read_to_string(self.dir.join("console.offset")).unwrap()
+        }
+    }
+
+    impl Drop for TestSpool {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn appends_batches_in_order() {
+        let mut t = TestSpool::new("in_order");
+        assert_eq!(t.spool.append(0, 6, false, "abc", &lines(&["first"])), Ok(6));
+        assert_eq!(t.spool.append(6, 19, false, "abc", &lines(&["second", "third"])), Ok(19));
+        assert_eq!(t.log(), "first\nsecond\nthird\n");
+        assert_eq!(t.offset_file(), "19 abc");
+        assert_eq!((t.spool.offset, t.spool.identity.as_str()), (19, "abc"));
+    }
+
+    #[test]
+    fn a_batch_sent_again_is_acknowledged_without_writing_it_twice() {
+        let mut t = TestSpool::new("resent");
+        t.spool.append(0, 6, false, "abc", &lines(&["first"])).unwrap();
+        assert_eq!(t.spool.append(0, 6, false, "abc", &lines(&["first"])), Ok(6));
+        assert_eq!(t.log(), "first\n");
+    }
+
+    #[test]
+    fn a_gap_is_rejected() {
+        let mut t = TestSpool::new("gap");
+        t.spool.append(0, 6, false, "abc", &lines(&["first"])).unwrap();
+        assert_eq!(t.spool.append(10, 16, false, "abc", &lines(&["later"])), Err("expected lines from byte 6, got 10".to_string()));
+        assert_eq!(t.log(), "first\n");
+        assert_eq!(t.offset_file(), "6 abc");
+    }
+
+    #[test]
+    fn reset_starts_the_copy_over() {
+        let mut t = TestSpool::new("reset");
+        t.spool.append(0, 12, false, "abc", &lines(&["old", "lines"])).unwrap();
+        assert_eq!(t.spool.append(0, 4, true, "def", &lines(&["new"])), Ok(4));
+        assert_eq!(t.log(), "new\n");
+        assert_eq!(t.offset_file(), "4 def");
+    }
+
+    #[test]
+    fn an_empty_batch_only_moves_the_offset() {
+        let mut t = TestSpool::new("empty");
+        assert_eq!(t.spool.append(0, 6, false, "", &[]), Ok(6));
+        assert_eq!(t.log(), "");
+        // Without a known identity only the offset is recorded.
+        assert_eq!(t.offset_file(), "6");
+    }
+
+    #[test]
+    fn pending_hellos_are_capped() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut pending: Vec<PendingHello> = (0..MAX_PENDING_HELLOS).map_while(|_| PendingHello::start(&count)).collect();
+        assert_eq!(pending.len(), MAX_PENDING_HELLOS);
+        assert!(PendingHello::start(&count).is_none());
+
+        pending.pop();
+        assert!(PendingHello::start(&count).is_some());
+        assert_eq!(count.load(Ordering::SeqCst), MAX_PENDING_HELLOS - 1);
+    }
+}
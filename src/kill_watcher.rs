@@ -1,98 +1,164 @@
 // rust
 // File: `src/kill_watcher.rs`
-use mysql::{params, prelude::*, Pool};
-use std::env;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
-use std::path::PathBuf;
-use std::thread;
+use mysql::{params, prelude::*, Pool, Transaction, TxOpts};
+use log::{debug, error, info, warn};
+use std::fs;
 use std::time::Duration;
 use uuid::Uuid;
 
-pub fn watch_console_log(file_path: PathBuf, timeout: u64) {
-    println!("Watching file: {}", file_path.display());
-    println!("Checking for PLAYER_KILLED events every {} seconds...\n", timeout);
+use crate::achievements::{self, KillFeats};
+use crate::chat_log;
+use crate::checkpoint::Checkpoint;
+use crate::database_setup::{self, init_db_pool};
+use crate::event_bus::{BusEvent, EventBus};
+use crate::factions::normalize_faction;
+use crate::match_tracker::{self, MatchTracker};
+use crate::metrics;
+use crate::rating;
+use crate::seasons;
+use crate::servers::ServerConfig;
+use crate::shutdown;
+use crate::tail::{self, LogTail, LogTime};
+
+const ER_DUP_ENTRY: u16 = 1062;
+const ER_LOCK_DEADLOCK: u16 = 1213;
+// Tries at writing a kill that keeps running into deadlocks
+const MAX_ATTEMPTS: u32 = 3;
+
+// Lifetime kill counts that are announced as milestones on the event bus.
+pub const KILL_MILESTONES: &[u32] = &[100, 250, 500, 1000, 2500, 5000, 10000];
+
+pub fn watch_console_log(server: ServerConfig, timeout: u64, tracker: MatchTracker, bus: EventBus) {
+    let file_path = server.console_log();
+    info!(server = server.id; "Watching file {} for PLAYER_KILLED events every {} seconds", file_path.display(), timeout);
 
     let pool = init_db_pool();
+    tracker.resume(pool.as_ref());
+
+    // Resume where the last run stopped; a log that was replaced in the meantime is read from the start
+    // (see `LogTail`). Without a checkpoint only new lines are read, or the whole log once it appears if
+    // it does not exist yet.
+    let mut checkpoint = Checkpoint::open(&server.id, "kill_watcher");
+    let file_end = fs::metadata(&file_path).map(|m| m.len()).ok();
+    let start = match (checkpoint.position(), file_end) {
+        (Some(position), _) => {
+            info!(server = server.id; "Resuming at byte {}", position);
+            position
+        }
+        (None, Some(end)) => end,
+        (None, None) => 0,
+    };
+    let mut tail = LogTail::new(&server.id, "kill_watcher", file_path.clone(), start, checkpoint.identity());
+
+    // A pass that is under way when shutdown is requested is finished and checkpointed first.
+    while !shutdown::sleep(Duration::from_secs(timeout)) {
+        // Reopened every pass to follow rotation and truncation.
+        let lines = match tail.read_lines() {
+            Ok(lines) => lines,
+            Err(e) => {
+                tail.report(&e);
+                continue;
+            }
+        };
 
-    let mut file = File::open(&file_path).expect("Failed to open file");
-    let mut last_position = file.seek(SeekFrom::End(0)).expect("Failed to seek");
+        for line in lines {
+            // Chat can contain anything, including "PLAYER_KILLED:" or "GAME_ENDED:" typed by a player.
+            if chat_log::is_chat_line(&line.text) {
+                continue;
+            }
 
-    loop {
-        thread::sleep(Duration::from_secs(timeout));
-
-        // Reopen file to handle rotation/truncation
-        file = File::open(&file_path).expect("Failed to reopen file");
-
-        // If file was truncated or recreated with smaller length, reset position
-        let file_len = file.metadata().map(|m| m.len()).unwrap_or(0);
-        if file_len < last_position {
-            println!(
-                "Log file was truncated or rotated (len {} < last_position {}). Resetting last_position to 0.",
-                file_len, last_position
-            );
-            last_position = 0;
-        }
+            // "Game successfully created." and friends drive the match lifecycle; they no longer reset the position.
+            if let Some(event) = match_tracker::parse_match_line(&line.text) {
+                metrics::event_parsed(&server.id, event.event_type());
+                tracker.handle_event(event, &tail::line_time(&line.text), &tail.event_id(&line), pool.as_ref());
+                continue;
+            }
 
-        file.seek(SeekFrom::Start(last_position)).expect("Failed to seek");
-        let reader = BufReader::new(&file);
-
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                // Do not reset position based on "Game successfully created." anymore.
-                if line.contains("PLAYER_KILLED:") {
-                    if let Some(kill) = parse_kill_line(&line) {
-                        print_kill(&kill);
-                        if let Some(ref pool) = pool {
-                            if let Err(e) = persist_kill(pool, &kill) {
-                                eprintln!("DB error persisting kill: {}", e);
-                            }
-                        } else {
-                            eprintln!("DB pool not initialized; skipping DB write.");
+            if line.text.contains("PLAYER_KILLED:")
+                && let Some(kill) = parse_kill_line(&line.text, &server.id, tail.event_id(&line))
+            {
+                metrics::event_parsed(&server.id, "kill");
+                let match_id = tracker.current_match();
+                let persisted = match pool {
+                    Some(ref pool) => match metrics::db_write("kill", || persist_kill(pool, &kill, match_id)) {
+                        Ok(Some(persisted)) => Some(persisted),
+                        // Already stored by an earlier read of the same line; it was published then.
+                        Ok(None) => {
+                            debug!(server = kill.server_id, event_id = kill.event_id; "Skipping duplicate kill {}", kill.event_id);
+                            metrics::duplicate_event(&server.id, "kill");
+                            continue;
                         }
+                        // Not read past: the kill and the lines after it are read again on the next pass.
+                        Err(e) if database_setup::is_transient(&e) => {
+                            error!(server = kill.server_id; "DB error persisting kill, retrying on the next pass: {}", e);
+                            tail.rewind(line.offset);
+                            break;
+                        }
+                        Err(e) => {
+                            error!(server = kill.server_id; "DB error persisting kill: {}", e);
+                            None
+                        }
+                    },
+                    None => {
+                        warn!("DB pool not initialized; skipping DB write.");
+                        None
                     }
+                };
+                log_kill(&kill);
+                metrics::kill_logged(&server.id);
+
+                // Published after the write so consumers can refer to the PlayerKills row.
+                let kill_id = persisted.as_ref().map(|p| p.kill_id);
+                bus.publish(BusEvent::Kill { kill: kill.clone(), match_id, kill_id });
+                let Some(p) = persisted else { continue };
+                if KILL_MILESTONES.contains(&p.killer_total_kills) {
+                    info!(
+                        server = kill.server_id, player = kill.killer_name, kills = p.killer_total_kills;
+                        "Milestone: {} reached {} kills", kill.killer_name, p.killer_total_kills
+                    );
+                    bus.publish(BusEvent::Milestone {
+                        server_id: kill.server_id.clone(),
+                        username: kill.killer_name.clone(),
+                        kills: p.killer_total_kills,
+                    });
+                }
+                log_feats(&kill, &p.feats);
+                for achievement in p.feats.unlocked {
+                    bus.publish(BusEvent::Achievement {
+                        server_id: kill.server_id.clone(),
+                        username: kill.killer_name.clone(),
+                        key: achievement.key,
+                        name: achievement.name,
+                    });
                 }
+            } else if line.text.contains("PLAYER_KILLED:") {
+                metrics::parse_failure(&server.id, "kill");
+                warn!(server = server.id; "Could not parse kill line: {}", line.text);
             }
         }
-
-        // Update last_position to current EOF so we only read new content next time
-        last_position = file.seek(SeekFrom::End(0)).expect("Failed to seek end");
+        checkpoint.save(tail.position(), tail.identity());
     }
+    info!(server = server.id; "Kill watcher stopped at byte {}", tail.position());
 }
 
-fn init_db_pool() -> Option<Pool> {
-    let database_user = env::var("DATABASE_USER").ok()?;
-    let database_password = env::var("DATABASE_PASSWORD").ok()?;
-    let database_ip = env::var("DATABASE_IP").ok()?;
-    let database_port = env::var("DATABASE_PORT").ok()?;
-    let database_name = env::var("DATABASE_NAME").ok()?;
-
-    let url = format!(
-        "mysql://{}:{}@{}:{}/{}",
-        database_user, database_password, database_ip, database_port, database_name
-    );
-
-    match Pool::new(url.as_str()) {
-        Ok(p) => Some(p),
-        Err(e) => {
-            eprintln!("Failed to create DB pool: {}", e);
-            None
-        }
-    }
-}
-
-#[derive(Debug)]
-struct KillEvent {
-    killer_name: String,
-    victim_name: String,
-    weapon: Option<String>,
-    distance: Option<f64>,
-    is_team_kill: bool,
-    killer_faction: Option<String>,
-    victim_faction: Option<String>,
+#[derive(Debug, Clone)]
+pub struct KillEvent {
+    // Id of the server whose log the kill came from
+    pub server_id: String,
+    // Date and time of day from the log line, when present
+    pub logged: LogTime,
+    pub killer_name: String,
+    pub victim_name: String,
+    pub weapon: Option<String>,
+    pub distance: Option<f64>,
+    pub is_team_kill: bool,
+    pub killer_faction: Option<String>,
+    pub victim_faction: Option<String>,
+    // Deterministic id of the log line (see `tail::event_id`), so a line read twice is stored once
+    pub event_id: String,
 }
 
-fn parse_kill_line(line: &str) -> Option<KillEvent> {
+fn parse_kill_line(line: &str, server_id: &str, event_id: String) -> Option<KillEvent> {
     // find data after "PLAYER_KILLED:"
     let marker = "PLAYER_KILLED:";
     let start = line.find(marker)? + marker.len();
@@ -118,14 +184,16 @@ fn parse_kill_line(line: &str) -> Option<KillEvent> {
                 "isTeamKill" => {
                     is_team_kill = matches!(val.to_lowercase().as_str(), "true" | "1")
                 }
-                "killerFaction" => killer_faction = Some(normalize_faction(val).to_string()),
-                "victimFaction" => victim_faction = Some(normalize_faction(val).to_string()),
+                "killerFaction" => killer_faction = Some(normalize_faction(val)),
+                "victimFaction" => victim_faction = Some(normalize_faction(val)),
                 _ => {}
             }
         }
     }
 
     Some(KillEvent {
+        server_id: server_id.to_string(),
+        logged: tail::line_time(line),
         killer_name: killer_name?,
         victim_name: victim_name?,
         weapon,
@@ -133,42 +201,102 @@ fn parse_kill_line(line: &str) -> Option<KillEvent> {
         is_team_kill,
         killer_faction,
         victim_faction,
+        event_id,
     })
 }
 
-fn print_kill(k: &KillEvent) {
-    println!("=== PLAYER KILLED ===");
-    println!("Killer: {}", k.killer_name);
-    println!("Victim: {}", k.victim_name);
-    if let Some(ref w) = k.weapon {
-        println!("Weapon: {}", w);
+fn log_kill(k: &KillEvent) {
+    debug!(
+        server = k.server_id,
+        killer = k.killer_name,
+        victim = k.victim_name,
+        weapon = k.weapon,
+        distance = k.distance,
+        team_kill = k.is_team_kill,
+        killer_faction = k.killer_faction,
+        victim_faction = k.victim_faction;
+        "Player killed"
+    );
+}
+
+fn log_feats(k: &KillEvent, feats: &KillFeats) {
+    let (server, killer) = (&k.server_id, &k.killer_name);
+    if feats.first_blood {
+        info!(server, player = killer; "First blood: {}", killer);
+    }
+    match feats.multi_kill {
+        2 => info!(server, player = killer; "Double kill: {}", killer),
+        3 => info!(server, player = killer; "Triple kill: {}", killer),
+        n if n > 3 => info!(server, player = killer; "Multi kill ({}): {}", n, killer),
+        _ => {}
     }
-    if let Some(d) = k.distance {
-        println!("Distance: {} meters", d);
+    if feats.streak >= 5 && feats.streak.is_multiple_of(5) {
+        info!(server, player = killer, streak = feats.streak; "Kill streak: {} has {} kills without dying", killer, feats.streak);
     }
-    println!("Team Kill: {}", k.is_team_kill);
-    if let Some(ref f) = k.killer_faction {
-        println!("Killer Faction: {}", f);
+    if feats.weapon_record {
+        info!(server, player = killer, weapon = k.weapon, distance = k.distance; "Weapon record: {}", killer);
     }
-    if let Some(ref f) = k.victim_faction {
-        println!("Victim Faction: {}", f);
+    for achievement in &feats.unlocked {
+        info!(server, player = killer, achievement = achievement.key; "Achievement unlocked: {} earned '{}'", killer, achievement.name);
     }
-    println!();
 }
 
-fn persist_kill(pool: &Pool, k: &KillEvent) -> Result<(), Box<dyn std::error::Error>> {
+struct PersistedKill {
+    kill_id: u64,
+    killer_total_kills: u32,
+    feats: KillFeats,
+}
+
+// Stores a kill and everything derived from it in one transaction, so a kill is either counted
+// everywhere or not at all. None when its event id is already in PlayerKills; nothing is changed then.
+fn persist_kill(pool: &Pool, k: &KillEvent, match_id: Option<u64>) -> Result<Option<PersistedKill>, mysql::Error> {
     let mut conn = pool.get_conn()?;
+    seasons::archive_ended_seasons(&mut conn)?;
 
-    // ensure killer player exists -> player_id
-    let killer_id = get_or_create_player(&mut conn, &k.killer_name)?;
-    let victim_id = get_or_create_player(&mut conn, &k.victim_name)?;
+    // Kill watchers of different servers update the same players' rows; InnoDB resolves a deadlock
+    // between them by rolling one back, which is simply tried again.
+    let mut attempt = 1;
+    loop {
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        match write_kill(&mut tx, k, match_id) {
+            Ok(Some(persisted)) => {
+                tx.commit()?;
+                return Ok(Some(persisted));
+            }
+            Ok(None) => {
+                tx.rollback()?;
+                return Ok(None);
+            }
+            Err(mysql::Error::MySqlError(ref e)) if e.code == ER_LOCK_DEADLOCK && attempt < MAX_ATTEMPTS => {
+                drop(tx);
+                debug!(server = k.server_id, attempt; "Deadlock persisting kill {}; retrying", k.event_id);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
 
-    // Insert into PlayerKills (killed_at uses NOW())
-    conn.exec_drop(
-        r"INSERT INTO PlayerKills
-        (killer_id, victim_id, weapon, distance, is_team_kill, killer_faction, victim_faction, killed_at)
-        VALUES (:killer, :victim, :weapon, :distance, :is_team_kill, :kf, :vf, NOW())",
+fn write_kill(tx: &mut Transaction, k: &KillEvent, match_id: Option<u64>) -> Result<Option<PersistedKill>, mysql::Error> {
+    // ensure killer player exists -> player_id
+    let killer_id = get_or_create_player(tx, &k.killer_name)?;
+    let victim_id = get_or_create_player(tx, &k.victim_name)?;
+
+    // Insert into PlayerKills. killed_at comes from the log line so kills read in one batch keep their
+    // spacing; a time of day later than now belongs to yesterday (the log crossed midnight).
+    let inserted = tx.exec_drop(
+        format!(
+            r"INSERT INTO PlayerKills
+            (server_id, killer_id, victim_id, weapon, distance, is_team_kill, killer_faction, victim_faction, match_id, killed_at, event_id)
+            VALUES (:server, :killer, :victim, :weapon, :distance, :is_team_kill, :kf, :vf, :match_id, {logged_at}, :event_id)",
+            logged_at = tail::LOGGED_AT_SQL
+        ),
         params! {
+            "server" => &k.server_id,
+            "event_id" => &k.event_id,
+            "log_date" => k.logged.date.as_deref(),
+            "log_time" => k.logged.time.as_deref(),
+            "match_id" => match_id,
             "killer" => killer_id,
             "victim" => victim_id,
             "weapon" => k.weapon.as_deref().unwrap_or(""),
@@ -177,14 +305,29 @@ fn persist_kill(pool: &Pool, k: &KillEvent) -> Result<(), Box<dyn std::error::Er
             "kf" => k.killer_faction.as_deref().unwrap_or(""),
             "vf" => k.victim_faction.as_deref().unwrap_or("")
         },
-    )?;
+    );
+    match inserted {
+        Ok(()) => {}
+        // uq_event_id: already stored by an earlier read of the same line
+        Err(mysql::Error::MySqlError(e)) if e.code == ER_DUP_ENTRY => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let kill_id = tx.last_insert_id().unwrap_or(0);
+
+    // Day/week/month buckets and running seasons
+    seasons::record_kill(tx, kill_id)?;
+
+    // Skill ratings only move on kills between opponents
+    if !k.is_team_kill && killer_id != victim_id {
+        rating::record_kill(tx, kill_id, killer_id, victim_id)?;
+    }
 
     // Update PlayerWeaponStats for killer
     let distance_val = k.distance.unwrap_or(0.0);
-    conn.exec_drop(
+    tx.exec_drop(
         r"INSERT INTO PlayerWeaponStats
-        (player_id, weapon, total_kills, total_team_kills, total_distance, longest_kill, last_kill)
-        VALUES (:pid, :weapon, 1, :tk, :dist, :dist, NOW())
+        (player_id, server_id, weapon, total_kills, total_team_kills, total_distance, longest_kill, last_kill)
+        VALUES (:pid, :server, :weapon, 1, :tk, :dist, :dist, NOW())
         ON DUPLICATE KEY UPDATE
             total_kills = total_kills + 1,
             total_team_kills = total_team_kills + VALUES(total_team_kills),
@@ -193,6 +336,7 @@ fn persist_kill(pool: &Pool, k: &KillEvent) -> Result<(), Box<dyn std::error::Er
             last_kill = VALUES(last_kill)",
         params! {
             "pid" => killer_id,
+            "server" => &k.server_id,
             "weapon" => k.weapon.as_deref().unwrap_or(""),
             "tk" => if k.is_team_kill { 1 } else { 0 },
             "dist" => distance_val
@@ -200,52 +344,160 @@ fn persist_kill(pool: &Pool, k: &KillEvent) -> Result<(), Box<dyn std::error::Er
     )?;
 
     // Update PlayerVsPlayerStats (killer -> victim)
-    conn.exec_drop(
+    tx.exec_drop(
         r"INSERT INTO PlayerVsPlayerStats
-        (killer_id, victim_id, total_kills, last_kill)
-        VALUES (:killer, :victim, 1, NOW())
+        (killer_id, victim_id, server_id, total_kills, last_kill)
+        VALUES (:killer, :victim, :server, 1, NOW())
         ON DUPLICATE KEY UPDATE
             total_kills = total_kills + 1,
             last_kill = VALUES(last_kill)",
-        params! { "killer" => killer_id, "victim" => victim_id },
+        params! { "killer" => killer_id, "victim" => victim_id, "server" => &k.server_id },
+    )?;
+
+    // Per-faction totals for killer and victim
+    if let Some(ref faction) = k.killer_faction {
+        tx.exec_drop(
+            r"INSERT INTO PlayerFactionStats (player_id, server_id, faction, kills, deaths, team_kills)
+            VALUES (:pid, :server, :faction, 1, 0, :tk)
+            ON DUPLICATE KEY UPDATE
+                kills = kills + 1,
+                team_kills = team_kills + VALUES(team_kills)",
+            params! {
+                "pid" => killer_id,
+                "server" => &k.server_id,
+                "faction" => faction,
+                "tk" => if k.is_team_kill { 1 } else { 0 }
+            },
+        )?;
+    }
+    if let Some(ref faction) = k.victim_faction {
+        tx.exec_drop(
+            r"INSERT INTO PlayerFactionStats (player_id, server_id, faction, kills, deaths, team_kills)
+            VALUES (:pid, :server, :faction, 0, 1, 0)
+            ON DUPLICATE KEY UPDATE deaths = deaths + 1",
+            params! { "pid" => victim_id, "server" => &k.server_id, "faction" => faction },
+        )?;
+    }
+
+    // Faction-vs-faction weapon usage (empty string when a side is unknown, to keep the key usable)
+    tx.exec_drop(
+        r"INSERT INTO FactionWeaponStats
+        (server_id, killer_faction, victim_faction, weapon, total_kills, total_team_kills, total_distance, longest_kill)
+        VALUES (:server, :kf, :vf, :weapon, 1, :tk, :dist, :dist)
+        ON DUPLICATE KEY UPDATE
+            total_kills = total_kills + 1,
+            total_team_kills = total_team_kills + VALUES(total_team_kills),
+            total_distance = total_distance + VALUES(total_distance),
+            longest_kill = GREATEST(longest_kill, VALUES(longest_kill))",
+        params! {
+            "server" => &k.server_id,
+            "kf" => k.killer_faction.as_deref().unwrap_or(""),
+            "vf" => k.victim_faction.as_deref().unwrap_or(""),
+            "weapon" => k.weapon.as_deref().unwrap_or(""),
+            "tk" => if k.is_team_kill { 1 } else { 0 },
+            "dist" => distance_val
+        },
     )?;
 
     // Update PlayerStats for killer (increment kills)
-    conn.exec_drop(
+    tx.exec_drop(
         r"INSERT INTO PlayerStats
-        (player_id, total_kills, total_deaths, total_team_kills, longest_kill)
-        VALUES (:pid, 1, 0, :tk, :dist)
+        (player_id, server_id, total_kills, total_deaths, total_team_kills, longest_kill)
+        VALUES (:pid, :server, 1, 0, :tk, :dist)
         ON DUPLICATE KEY UPDATE
             total_kills = total_kills + 1,
             total_team_kills = total_team_kills + VALUES(total_team_kills),
             longest_kill = GREATEST(longest_kill, VALUES(longest_kill))",
-        params! { "pid" => killer_id, "tk" => if k.is_team_kill { 1 } else { 0 }, "dist" => distance_val },
+        params! {
+            "pid" => killer_id,
+            "server" => &k.server_id,
+            "tk" => if k.is_team_kill { 1 } else { 0 },
+            "dist" => distance_val
+        },
     )?;
 
     // Update PlayerStats for victim (increment deaths)
-    conn.exec_drop(
+    tx.exec_drop(
         r"INSERT INTO PlayerStats
-        (player_id, total_kills, total_deaths, total_team_kills)
-        VALUES (:pid, 0, 1, 0)
+        (player_id, server_id, total_kills, total_deaths, total_team_kills)
+        VALUES (:pid, :server, 0, 1, 0)
         ON DUPLICATE KEY UPDATE
             total_deaths = total_deaths + 1",
-        params! { "pid" => victim_id },
+        params! { "pid" => victim_id, "server" => &k.server_id },
     )?;
 
+    // Per-match scoreboard (players and factions) when a match is running
+    if let Some(match_id) = match_id {
+        tx.exec_drop(
+            r"INSERT INTO MatchPlayerStats
+            (match_id, player_id, kills, deaths, team_kills, faction, longest_kill)
+            VALUES (:mid, :pid, 1, 0, :tk, :faction, :dist)
+            ON DUPLICATE KEY UPDATE
+                kills = kills + 1,
+                team_kills = team_kills + VALUES(team_kills),
+                faction = COALESCE(VALUES(faction), faction),
+                longest_kill = GREATEST(longest_kill, VALUES(longest_kill))",
+            params! {
+                "mid" => match_id,
+                "pid" => killer_id,
+                "tk" => if k.is_team_kill { 1 } else { 0 },
+                "faction" => k.killer_faction.as_deref(),
+                "dist" => distance_val
+            },
+        )?;
+        tx.exec_drop(
+            r"INSERT INTO MatchPlayerStats
+            (match_id, player_id, kills, deaths, team_kills, faction)
+            VALUES (:mid, :pid, 0, 1, 0, :faction)
+            ON DUPLICATE KEY UPDATE
+                deaths = deaths + 1,
+                faction = COALESCE(VALUES(faction), faction)",
+            params! { "mid" => match_id, "pid" => victim_id, "faction" => k.victim_faction.as_deref() },
+        )?;
+
+        if let Some(ref faction) = k.killer_faction {
+            tx.exec_drop(
+                r"INSERT INTO MatchFactionResults (match_id, faction, kills, deaths, team_kills)
+                VALUES (:mid, :faction, 1, 0, :tk)
+                ON DUPLICATE KEY UPDATE
+                    kills = kills + 1,
+                    team_kills = team_kills + VALUES(team_kills)",
+                params! { "mid" => match_id, "faction" => faction, "tk" => if k.is_team_kill { 1 } else { 0 } },
+            )?;
+        }
+        if let Some(ref faction) = k.victim_faction {
+            tx.exec_drop(
+                r"INSERT INTO MatchFactionResults (match_id, faction, kills, deaths, team_kills)
+                VALUES (:mid, :faction, 0, 1, 0)
+                ON DUPLICATE KEY UPDATE deaths = deaths + 1",
+                params! { "mid" => match_id, "faction" => faction },
+            )?;
+        }
+    }
+
     // Recompute kd_ratio for killer and victim
     for pid in &[killer_id, victim_id] {
-        conn.exec_drop(
+        tx.exec_drop(
             r"UPDATE PlayerStats
             SET kd_ratio = CASE WHEN total_deaths = 0 THEN total_kills ELSE total_kills / total_deaths END
-            WHERE player_id = :pid",
-            params! { "pid" => pid },
+            WHERE player_id = :pid AND server_id = :server",
+            params! { "pid" => pid, "server" => &k.server_id },
         )?;
     }
 
-    Ok(())
+    // Streaks, multi-kills, first blood, weapon records and achievements
+    let feats =
+        achievements::record_kill(tx, &k.server_id, kill_id, killer_id, victim_id, k.is_team_kill, match_id)?;
+
+    // Milestones count kills across all servers
+    let total_kills: Option<u32> = tx.exec_first(
+        "SELECT SUM(total_kills) FROM PlayerStats WHERE player_id = :pid",
+        params! { "pid" => killer_id },
+    )?;
+    Ok(Some(PersistedKill { kill_id, killer_total_kills: total_kills.unwrap_or(0), feats }))
 }
 
-fn get_or_create_player(conn: &mut mysql::PooledConn, username: &str) -> Result<u64, Box<dyn std::error::Error>> {
+fn get_or_create_player(conn: &mut Transaction, username: &str) -> Result<u64, mysql::Error> {
     // try find in PlayerNames
     if let Some(row) = conn.exec_first::<(u64,), _, _>(
         "SELECT player_id FROM PlayerNames WHERE username = :u LIMIT 1",
@@ -260,25 +512,52 @@ fn get_or_create_player(conn: &mut mysql::PooledConn, username: &str) -> Result<
         "INSERT INTO Players (reforger_id) VALUES (:rid)",
         params! { "rid" => &reforger_id },
     )?;
-    let player_id = conn.last_insert_id();
+    let player_id = conn.last_insert_id().unwrap_or(0);
     conn.exec_drop(
         "INSERT INTO PlayerNames (player_id, username) VALUES (:pid, :uname)",
         params! { "pid" => player_id, "uname" => username },
     )?;
 
-    // Also ensure PlayerStats row exists (so later updates work)
-    conn.exec_drop(
-        "INSERT INTO PlayerStats (player_id) VALUES (:pid) ON DUPLICATE KEY UPDATE player_id = player_id",
-        params! { "pid" => player_id },
-    )?;
-
     Ok(player_id)
 }
 
-fn normalize_faction(value: &str) -> &str {
-    match value {
-        "#WCS-Faction_NATO" => "NATO",
-        "#WCS-Faction_RU" => "RU",
-        other => other,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_kill() {
+        let line = "2026-10-19 12:00:00.250  SCRIPT       : PLAYER_KILLED: killerName='Alpha One', victimName='Bravo', \
+            weaponName='M16A2', killDistance='123.5', isTeamKill='false', killerFaction='#WCS-Faction_NATO', victimFaction='#WCS-Faction_RU'";
+        let kill = parse_kill_line(line, "eu1", "id".to_string()).unwrap();
+        assert_eq!(kill.server_id, "eu1");
+        assert_eq!(kill.logged.date.as_deref(), Some("2026-10-19"));
+        assert_eq!(kill.logged.time.as_deref(), Some("12:00:00.250"));
+        assert_eq!((kill.killer_name.as_str(), kill.victim_name.as_str()), ("Alpha One", "Bravo"));
+        assert_eq!(kill.weapon.as_deref(), Some("M16A2"));
+        assert_eq!(kill.distance, Some(123.5));
+        assert!(!kill.is_team_kill);
+        assert_eq!(kill.killer_faction.as_deref(), Some("NATO"));
+        assert_eq!(kill.victim_faction.as_deref(), Some("RU"));
+        assert_eq!(kill.event_id, "id");
+    }
+
+    #[test]
+    fn optional_fields_and_team_kills() {
+        let kill = parse_kill_line("PLAYER_KILLED: killerName='A', victimName='B', isTeamKill='1', killDistance='far'", "default", String::new()).unwrap();
+        assert!(kill.is_team_kill);
+        assert_eq!((kill.weapon, kill.distance), (None, None));
+        assert_eq!((kill.killer_faction, kill.victim_faction), (None, None));
+        assert_eq!(kill.logged, LogTime::default());
+
+        let kill = parse_kill_line("PLAYER_KILLED: killerName='A', victimName='B', isTeamKill='TRUE'", "default", String::new()).unwrap();
+        assert!(kill.is_team_kill);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn killer_and_victim_are_required() {
+        assert!(parse_kill_line("PLAYER_KILLED: killerName='A'", "default", String::new()).is_none());
+        assert!(parse_kill_line("PLAYER_KILLED: victimName='B'", "default", String::new()).is_none());
+        assert!(parse_kill_line("killerName='A', victimName='B'", "default", String::new()).is_none());
+    }
+}
@@ -0,0 +1,256 @@
+use log::kv::{self, Key, Value, VisitSource, VisitValue};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, Value as JsonValue};
+use std::env;
+use std::io::{self, Write};
+use std::panic;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy)]
+enum Format {
+    Human,
+    Json,
+}
+
+struct Logger {
+    default: LevelFilter,
+    // Per-module levels, longest module first so the most specific one wins.
+    modules: Vec<(String, LevelFilter)>,
+    format: Format,
+}
+
+impl Logger {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let target = short_target(target);
+        self.modules
+            .iter()
+            .find(|(module, _)| target == module || target.strip_prefix(module.as_str()).is_some_and(|rest| rest.starts_with("::")))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut fields = Fields(Vec::new());
+        let _ = record.key_values().visit(&mut fields);
+        // Missing optional values are left out rather than written as null.
+        fields.0.retain(|(_, value)| !value.is_null());
+        let line = match self.format {
+            Format::Human => human_line(record, &fields.0),
+            Format::Json => json_line(record, fields.0),
+        };
+        // Warnings and errors go to stderr, everything else to stdout.
+        if record.level() <= Level::Warn {
+            let _ = writeln!(io::stderr().lock(), "{}", line);
+        } else {
+            let _ = writeln!(io::stdout().lock(), "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+// Module paths without the crate name ("DF_backend::kill_watcher" -> "kill_watcher", "DF_backend" -> "main").
+fn short_target(target: &str) -> &str {
+    if target == env!("CARGO_CRATE_NAME") {
+        return "main";
+    }
+    target.strip_prefix(concat!(env!("CARGO_CRATE_NAME"), "::")).unwrap_or(target)
+}
+
+// Key-value pairs of a record, in the order they were given.
+struct Fields(Vec<(String, JsonValue)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let mut json = JsonValue::Null;
+        value.visit(ToJson(&mut json))?;
+        self.0.push((key.to_string(), json));
+        Ok(())
+    }
+}
+
+struct ToJson<'a>(&'a mut JsonValue);
+
+impl<'v> VisitValue<'v> for ToJson<'_> {
+    fn visit_any(&mut self, value: Value) -> Result<(), kv::Error> {
+        *self.0 = JsonValue::String(value.to_string());
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        *self.0 = JsonValue::Null;
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+        *self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+        *self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+        *self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+        *self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), kv::Error> {
+        *self.0 = value.into();
+        Ok(())
+    }
+}
+
+// 2026-05-01T12:00:00.000Z  INFO kill_watcher: Player killed server=eu1 killer=Alice weapon="M16 A2"
+fn human_line(record: &Record, fields: &[(String, JsonValue)]) -> String {
+    let mut line = format!(
+        "{} {:>5} {}: {}",
+        timestamp(),
+        record.level(),
+        short_target(record.target()),
+        record.args()
+    );
+    for (key, value) in fields {
+        match value {
+            JsonValue::String(s) if !s.is_empty() && !s.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') => {
+                line.push_str(&format!(" {}={}", key, s))
+            }
+            _ => line.push_str(&format!(" {}={}", key, value)),
+        }
+    }
+    line
+}
+
+// {"timestamp":"...","level":"INFO","target":"kill_watcher","message":"Player killed","server":"eu1",...}
+fn json_line(record: &Record, fields: Vec<(String, JsonValue)>) -> String {
+    let mut object: Map<String, JsonValue> = fields.into_iter().collect();
+    object.insert("timestamp".to_string(), timestamp().into());
+    object.insert("level".to_string(), record.level().as_str().into());
+    object.insert("target".to_string(), short_target(record.target()).into());
+    object.insert("message".to_string(), record.args().to_string().into());
+    JsonValue::Object(object).to_string()
+}
+
+// Current UTC time as RFC 3339 with milliseconds.
+fn timestamp() -> String {
+    format_timestamp(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+fn format_timestamp(since_epoch: Duration) -> String {
+    let secs = since_epoch.as_secs();
+    let (days, seconds) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's days_from_civil, inverted).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+// LOG_LEVEL is a default level followed by per-module levels, e.g. "info,kill_watcher=debug,mysql=warn";
+// LOG_FORMAT is `human` (default) or `json`, one object per line.
+pub fn init() {
+    let mut default = LevelFilter::Info;
+    let mut modules = Vec::new();
+    let mut invalid = Vec::new();
+    let spec = env::var("LOG_LEVEL").unwrap_or_default();
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        match directive.split_once('=') {
+            Some((module, level)) => match level.trim().parse() {
+                Ok(level) => modules.push((module.trim().to_string(), level)),
+                Err(_) => invalid.push(directive),
+            },
+            None => match directive.parse() {
+                Ok(level) => default = level,
+                Err(_) => invalid.push(directive),
+            },
+        }
+    }
+    modules.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+
+    let format_name = env::var("LOG_FORMAT").unwrap_or_default().to_lowercase();
+    let format = match format_name.as_str() {
+        "json" => Format::Json,
+        _ => Format::Human,
+    };
+
+    let max_level = modules.iter().map(|(_, level)| *level).fold(default, Ord::max);
+    if log::set_boxed_logger(Box::new(Logger { default, modules, format })).is_ok() {
+        log::set_max_level(max_level);
+    }
+    // Panics are logged like everything else (the supervisor restarts the worker) instead of as raw stderr text.
+    panic::set_hook(Box::new(|info| {
+        let location = info.location().map(|l| format!("{}:{}", l.file(), l.line()));
+        let thread = thread::current();
+        log::error!(location, thread = thread.name(); "Panic: {}", info.payload_as_str().unwrap_or("unknown panic"));
+    }));
+
+    for directive in invalid {
+        log::warn!("Ignoring invalid LOG_LEVEL directive '{}'", directive);
+    }
+    if !matches!(format_name.as_str(), "" | "human" | "json") {
+        log::warn!("Unknown LOG_FORMAT '{}'; using human", format_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64, millis: u64) -> String {
+        format_timestamp(Duration::from_secs(secs) + Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn timestamps_are_rfc3339_utc_with_milliseconds() {
+        assert_eq!(at(0, 0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(at(1_709_208_000, 7), "2024-02-29T12:00:00.007Z");
+        assert_eq!(at(951_782_400, 999), "2000-02-29T00:00:00.999Z");
+        // 2100 is not a leap year.
+        assert_eq!(at(4_107_542_399, 0), "2100-02-28T23:59:59.000Z");
+        assert_eq!(at(4_107_542_400, 0), "2100-03-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn current_timestamp_has_the_same_shape() {
+        let now = timestamp();
+        assert_eq!(now.len(), "1970-01-01T00:00:00.000Z".len());
+        assert!(now.ends_with('Z'));
+    }
+}
@@ -1,20 +1,89 @@
 use dotenv::dotenv;
+mod achievements;
+mod api;
+mod associations;
+mod battleye_events;
+mod chat_log;
+mod checkpoint;
+mod cli;
 mod kill_watcher;
+mod logging;
+mod metrics;
+mod match_tracker;
 mod database_setup;
+mod discord;
+mod event_bus;
+mod factions;
+mod health;
+mod ingest;
+mod player_merge;
 mod player_monitor;
+mod rating;
+mod rcon;
+mod seasons;
+mod servers;
+mod shutdown;
+mod tail;
+mod suspicion;
+mod teamkill;
+mod webhooks;
 
+use std::collections::HashMap;
 use std::env;
 use std::path::Path;
-use std::thread;
-use crate::database_setup::setup_database;
+use std::process;
+use std::sync::{Arc, mpsc};
+use std::time::Instant;
+use crate::database_setup::{init_db_pool, setup_database};
+use event_bus::EventBus;
+use health::{Stop, Supervisor};
+use log::{error, info, warn};
+use match_tracker::MatchTracker;
+use metrics::MetricsConfig;
 use player_monitor::PlayerMonitor;
+use api::ApiConfig;
+use discord::DiscordConfig;
+use ingest::CollectorConfig;
+use rcon::RconConfig;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+// Exit codes, so service managers can tell a broken configuration (don't restart, e.g. systemd's
+// RestartPreventExitStatus=78) from a worker that kept failing (restart).
+const EXIT_COMMAND_FAILED: i32 = 1;
+const EXIT_WORKER_FAILED: i32 = 70;
+const EXIT_CONFIG: i32 = 78;
+
+fn main() {
     dotenv().ok();
+    logging::init();
+    shutdown::install();
+
+    // Any arguments select a one-off admin command instead of the monitors.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = cli::run(&args) {
+            eprintln!("{}", e);
+            process::exit(EXIT_COMMAND_FAILED);
+        }
+        return;
+    }
 
-    let server_path = env::var("SERVER_PATH")?;
+    let code = match run() {
+        Ok(code) => code,
+        Err(e) => {
+            error!("Failed to start: {}", e);
+            EXIT_CONFIG
+        }
+    };
+    log::logger().flush();
+    process::exit(code);
+}
+
+// Starts every worker under the supervisor and returns the exit code once they are stopped by a signal
+// or one they depend on fails.
+fn run() -> Result<i32, Box<dyn std::error::Error>> {
+    let servers = servers::load_servers()?;
+    let webhook_rules = webhooks::load_rules()?;
     let timeout: u64 = env::var("PLAYER_KILL_CHECKER_TIMEOUT")?.parse()?;
-    let file_path = Path::new(&server_path).join("console.log");
     let database_setup = env::var("DATABASE_SETUP_COMPLETE")
         .unwrap_or_else(|_| "false".to_string())
         .to_lowercase() == "true";
@@ -26,36 +95,137 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let database_port = env::var("DATABASE_PORT")?;
     let database_name = env::var("DATABASE_NAME")?;
 
-    println!("Database setup complete: {}", database_setup);
+    info!("Database setup complete: {}", database_setup);
     if !database_setup {
-        println!("Warning: Database setup is not complete. Some features may not work as expected.");
+        warn!("Database setup is not complete. Some features may not work as expected.");
         setup_database(&database_ip, &database_port, &database_name, &database_user, &database_password);
     }
-    check_env(file_path.to_str().unwrap())?;
+    // Workers are restarted with backoff when they panic; the tailers and the collector are critical.
+    let supervisor = Supervisor::from_env();
+
+    // Remote servers' logs arrive through the collector, which keeps a local copy for the monitors to tail.
+    if servers.iter().any(|s| s.agent_token.is_some()) {
+        let config = CollectorConfig::from_env().ok_or("Servers with an agent_token need COLLECTOR_BIND")?;
+        let collector = Arc::new(ingest::Collector::open(&servers)?);
+        supervisor.spawn("collector", true, move || ingest::run_collector(config.clone(), Arc::clone(&collector)));
+    }
+    for server in &servers {
+        check_env(&server.console_log())?;
+    }
+    if let Some(pool) = init_db_pool()
+        && let Err(e) = pool.get_conn().and_then(|mut conn| servers::register_servers(&mut conn, &servers))
+    {
+        error!("Failed to register servers: {}", e);
+    }
+
+    // Live kills, connections and disconnections from every server for the streaming endpoints.
+    let bus = EventBus::new();
+    // Player actions for each server's RCon client, by server id.
+    let mut rcon_actions = HashMap::new();
+
+    // Each server gets its own player monitor and kill watcher, and an RCon client if configured.
+    for server in servers {
+        info!(server = server.id; "Monitoring server {} ({})", server.id, server.display_name());
+        // Match lifecycle is read by the kill watcher and shared with the monitor to stamp sessions.
+        let tracker = MatchTracker::new(&server.id);
+        let (monitor_server, monitor_tracker, monitor_bus) = (server.clone(), tracker.clone(), bus.clone());
+        supervisor.spawn(format!("player_monitor:{}", server.id), true, move || {
+            PlayerMonitor::new(&monitor_server, monitor_tracker.clone(), monitor_bus.clone()).start_monitoring();
+        });
+
+        // Optional BattlEye RCon client; other threads send it player actions.
+        if let Some(config) = RconConfig::for_server(&server) {
+            let (actions, receiver) = mpsc::channel();
+            rcon_actions.insert(server.id.clone(), actions);
+            supervisor.spawn(format!("rcon:{}", server.id), false, move || {
+                rcon::run_rcon_client(config.clone(), init_db_pool(), &receiver);
+            });
+        }
+
+        let watcher_bus = bus.clone();
+        supervisor.spawn(format!("kill_watcher:{}", server.id), true, move || {
+            kill_watcher::watch_console_log(server.clone(), timeout, tracker.clone(), watcher_bus.clone());
+        });
+    }
 
-    // Run player monitor and kill watcher simultaneously in separate threads.
-    let monitor_path = file_path.to_str().unwrap().to_string();
-    let monitor = PlayerMonitor::new(&monitor_path);
+    // Team kill rules; they act through RCon only with TEAMKILL_ENFORCE=true.
+    let teamkill_rules = teamkill::load_rules();
+    if !teamkill_rules.is_empty() {
+        let enforce = env::var("TEAMKILL_ENFORCE").unwrap_or_else(|_| "false".to_string()).to_lowercase() == "true";
+        let bus = bus.clone();
+        supervisor.spawn("teamkill", false, move || {
+            teamkill::run_teamkill_monitor(teamkill_rules.clone(), enforce, init_db_pool(), bus.clone(), rcon_actions.clone())
+        });
+    }
 
-    let monitor_handle = thread::spawn(move || {
-        monitor.start_monitoring();
-    });
+    // Periodic suspicious-performance scan over recent kills; it reads and writes the database only.
+    if let Some(pool) = init_db_pool() {
+        let config = suspicion::load_config();
+        supervisor.spawn("suspicion", false, move || suspicion::run_suspicion_scanner(config.clone(), pool.clone()));
+    }
 
-    let watcher_handle = thread::spawn(move || {
-        kill_watcher::watch_console_log(file_path, timeout);
-    });
+    // Optional Discord kill notifications for linked players (enabled by DISCORD_WEBHOOK_URL).
+    if let Some(config) = DiscordConfig::from_env() {
+        match init_db_pool() {
+            Some(pool) => {
+                let bus = bus.clone();
+                supervisor.spawn("discord", false, move || {
+                    discord::run_discord_notifier(config.clone(), pool.clone(), bus.clone())
+                });
+            }
+            None => warn!("Discord notifications disabled: database is not available"),
+        }
+    }
 
-    // Wait for both (these are long-running loops; joining will block here).
-    let _ = monitor_handle.join();
-    let _ = watcher_handle.join();
+    // Optional outgoing webhooks (rules in webhooks.json); they only need the event bus.
+    if !webhook_rules.is_empty() {
+        let bus = bus.clone();
+        supervisor.spawn("webhooks", false, move || webhooks::run_webhook_dispatcher(webhook_rules.clone(), bus.clone()));
+    }
+
+    // Optional Prometheus metrics and health endpoints (enabled by METRICS_BIND); they work without the database.
+    if let Some(config) = MetricsConfig::from_env() {
+        let bus = bus.clone();
+        supervisor.spawn("metrics", false, move || metrics::run_metrics_server(config.clone(), bus.clone()));
+    }
 
-    Ok(())
+    // Optional HTTP API with live event streams (enabled by API_BIND); it needs the database.
+    if let Some(config) = ApiConfig::from_env() {
+        match init_db_pool() {
+            Some(pool) => {
+                let bus = bus.clone();
+                supervisor.spawn("api", false, move || api::run_api_server(config.clone(), pool.clone(), bus.clone()))
+            }
+            None => warn!("HTTP API disabled: database is not available"),
+        }
+    }
+
+    // The workers run until the process is stopped; we only get past here when a critical one gave up.
+    if let Stop::WorkerFailed(worker) = supervisor.wait() {
+        error!(worker; "Worker {} failed too often; exiting", worker);
+        return Ok(EXIT_WORKER_FAILED);
+    }
+
+    // SIGINT or SIGTERM: the tailers finish their pass and save their checkpoints, then the event
+    // subscribers get what is left in their queues.
+    let timeout = shutdown::timeout();
+    info!("Shutting down (waiting up to {}s)", timeout.as_secs());
+    let deadline = Instant::now() + timeout;
+    if supervisor.wait_stopped(deadline) && bus.drain(deadline) {
+        info!("Shutdown complete");
+    } else {
+        warn!("Shutdown timed out after {}s; exiting anyway", timeout.as_secs());
+    }
+    Ok(0)
 }
 
-fn check_env(path: &str) -> Result<(), String> {
-    if Path::new(path).exists() {
+// Only the log's directory has to exist; the log itself may not be there yet (before the server's
+// first start) and the tailers wait for it.
+fn check_env(log: &Path) -> Result<(), String> {
+    let dir = log.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if dir.is_dir() {
         Ok(())
     } else {
-        Err(format!("The path {} does not exist.", path))
+        Err(format!("The path {} does not exist.", dir.display()))
     }
 }
@@ -0,0 +1,374 @@
+use log::{error, info};
+use mysql::{params, prelude::*, Pool, PooledConn};
+use regex::Regex;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use crate::factions::normalize_faction;
+use crate::metrics;
+use crate::tail::{self, LogTime};
+
+// Lifecycle markers in console.log:
+//   scenario load  - a line mentioning the scenario/mission with its resource, e.g.
+//                    "Loading scenario {ECC61978EDCC2B5A}Missions/23_Campaign.conf"
+//   map load       - a line mentioning the world resource, e.g. "Loading world {853E92315D1D9EFE}worlds/Eden/Eden.ent"
+//   game start     - "Game successfully created."
+//   game end       - "GAME_ENDED: winnerFaction='#WCS-Faction_NATO', reason='...'" (same format as PLAYER_KILLED)
+const GAME_STARTED_MARKER: &str = "Game successfully created.";
+const GAME_ENDED_MARKER: &str = "GAME_ENDED:";
+
+static SCENARIO_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)(?:scenario|mission).*?(\{[0-9A-F]{16}\}[^\s'\x22]+\.conf)").unwrap());
+static WORLD_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)world.*?\{[0-9A-F]{16}\}(?:[^\s'\x22]*/)?([^/\s'\x22]+)\.ent").unwrap());
+
+#[derive(Debug)]
+pub enum MatchEvent {
+    ScenarioLoaded { scenario: String },
+    MapLoaded { map: String },
+    GameStarted,
+    GameEnded { winner_faction: Option<String>, reason: Option<String> },
+}
+
+impl MatchEvent {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            MatchEvent::ScenarioLoaded { .. } => "scenario_loaded",
+            MatchEvent::MapLoaded { .. } => "map_loaded",
+            MatchEvent::GameStarted => "game_started",
+            MatchEvent::GameEnded { .. } => "game_ended",
+        }
+    }
+}
+
+pub fn parse_match_line(line: &str) -> Option<MatchEvent> {
+    if line.contains(GAME_STARTED_MARKER) {
+        return Some(MatchEvent::GameStarted);
+    }
+
+    if let Some(start) = line.find(GAME_ENDED_MARKER) {
+        let mut winner_faction = None;
+        let mut reason = None;
+        for pair in line[start + GAME_ENDED_MARKER.len()..].split(", ") {
+            if let Some((k, v)) = pair.split_once('=') {
+                let val = v.trim().trim_matches('\'');
+                match k.trim() {
+                    "winnerFaction" if !val.is_empty() => winner_faction = Some(normalize_faction(val)),
+                    "reason" if !val.is_empty() => reason = Some(val.to_string()),
+                    _ => {}
+                }
+            }
+        }
+        return Some(MatchEvent::GameEnded { winner_faction, reason });
+    }
+
+    if let Some(caps) = SCENARIO_REGEX.captures(line) {
+        return Some(MatchEvent::ScenarioLoaded { scenario: caps[1].to_string() });
+    }
+
+    if let Some(caps) = WORLD_REGEX.captures(line) {
+        return Some(MatchEvent::MapLoaded { map: caps[1].to_string() });
+    }
+
+    None
+}
+
+#[derive(Default)]
+struct MatchState {
+    current_match: Option<u64>,
+    scenario: Option<String>,
+    map: Option<String>,
+}
+
+// One per server. Shared between that server's kill watcher (which drives the lifecycle, in log order
+// with the kills) and its player monitor (which stamps sessions with the current match).
+#[derive(Clone)]
+pub struct MatchTracker {
+    server_id: String,
+    state: Arc<Mutex<MatchState>>,
+}
+
+impl MatchTracker {
+    pub fn new(server_id: &str) -> Self {
+        Self { server_id: server_id.to_string(), state: Arc::default() }
+    }
+
+    pub fn current_match(&self) -> Option<u64> {
+        self.state.lock().unwrap().current_match
+    }
+
+    // The kill watcher starts tailing at EOF, so pick up a match that was still running when we last stopped.
+    pub fn resume(&self, pool: Option<&Pool>) {
+        let Some(pool) = pool else { return };
+        let open: Result<Option<u64>, _> = pool.get_conn().and_then(|mut conn| {
+            conn.exec_first(
+                "SELECT match_id FROM Matches WHERE server_id = :server AND ended_at IS NULL ORDER BY started_at DESC LIMIT 1",
+                params! { "server" => &self.server_id },
+            )
+        });
+        match open {
+            Ok(Some(match_id)) => {
+                info!(server = self.server_id, match_id; "Resuming match {}", match_id);
+                self.state.lock().unwrap().current_match = Some(match_id);
+            }
+            Ok(None) => {}
+            Err(e) => error!(server = self.server_id; "Failed to look up open match: {}", e),
+        }
+    }
+
+    // `logged` is when the line was logged, which matches start and end at; `event_id` is the line's
+    // id (see `tail::event_id`), so a start line read twice opens one match.
+    pub fn handle_event(&self, event: MatchEvent, logged: &LogTime, event_id: &str, pool: Option<&Pool>) {
+        // The state is only locked to update it, not across the database writes below, so the player
+        // monitor looking up the current match is never held up by them. Only the kill watcher starts
+        // and ends matches, so nothing changes the state in between.
+        let (ended, started) = {
+            let mut state = self.state.lock().unwrap();
+            match event {
+                MatchEvent::ScenarioLoaded { scenario } => {
+                    // A new scenario means the previous game is over even if we never saw it end (restart, crash).
+                    let ended = state.current_match.take().map(|match_id| (match_id, None, Some("scenario change".to_string())));
+                    info!(server = self.server_id, scenario; "Scenario loaded: {}", scenario);
+                    metrics::clear_online(&self.server_id);
+                    state.scenario = Some(scenario);
+                    state.map = None;
+                    (ended, None)
+                }
+                MatchEvent::MapLoaded { map } => {
+                    info!(server = self.server_id, map; "Map loaded: {}", map);
+                    state.map = Some(map);
+                    (None, None)
+                }
+                MatchEvent::GameStarted => {
+                    let ended = state.current_match.take().map(|match_id| (match_id, None, Some("new game".to_string())));
+                    (ended, Some((state.scenario.clone(), state.map.clone())))
+                }
+                MatchEvent::GameEnded { winner_faction, reason } => {
+                    (state.current_match.take().map(|match_id| (match_id, winner_faction, reason)), None)
+                }
+            }
+        };
+        if ended.is_none() && started.is_none() {
+            return;
+        }
+
+        let mut conn = pool.and_then(|p| match p.get_conn() {
+            Ok(c) => Some(c),
+            Err(e) => {
+                error!("Failed to get DB connection from pool: {}", e);
+                None
+            }
+        });
+        if let Some((match_id, winner_faction, reason)) = ended {
+            finish_match(conn.as_mut(), &self.server_id, match_id, logged, winner_faction.as_deref(), reason.as_deref());
+        }
+        if let Some((scenario, map)) = started
+            && let Some(conn) = conn.as_mut()
+        {
+            let start = || start_match(conn, &self.server_id, scenario.as_deref(), map.as_deref(), logged, event_id);
+            match metrics::db_write("match_start", start) {
+                Ok(match_id) => {
+                    info!(server = self.server_id, match_id; "Match {} started", match_id);
+                    self.state.lock().unwrap().current_match = Some(match_id);
+                }
+                Err(e) => error!(server = self.server_id; "Failed to insert Matches: {}", e),
+            }
+        }
+    }
+}
+
+// Returns the match started by the line, which is the existing one when the line was read before.
+fn start_match(
+    conn: &mut PooledConn,
+    server_id: &str,
+    scenario: Option<&str>,
+    map: Option<&str>,
+    logged: &LogTime,
+    event_id: &str,
+) -> Result<u64, mysql::Error> {
+    conn.exec_drop(
+        format!(
+            r"INSERT INTO Matches (server_id, scenario, map, started_at, event_id)
+            VALUES (:server, :scenario, :map, {logged_at}, :event_id)
+            ON DUPLICATE KEY UPDATE match_id = LAST_INSERT_ID(match_id)",
+            logged_at = tail::LOGGED_AT_SQL
+        ),
+        params! {
+            "server" => server_id,
+            "scenario" => scenario,
+            "map" => map,
+            "log_date" => logged.date.as_deref(),
+            "log_time" => logged.time.as_deref(),
+            "event_id" => event_id,
+        },
+    )?;
+    // LAST_INSERT_ID(match_id) makes a duplicate report the existing row's id.
+    Ok(conn.last_insert_id())
+}
+
+// The scoreboard is left to `match-summary` and the API; the log gets one line per match.
+fn finish_match(
+    conn: Option<&mut PooledConn>,
+    server_id: &str,
+    match_id: u64,
+    logged: &LogTime,
+    winner_faction: Option<&str>,
+    reason: Option<&str>,
+) {
+    info!(server = server_id, match_id, winner = winner_faction, reason; "Match {} ended", match_id);
+    let Some(conn) = conn else { return };
+    if let Err(e) = metrics::db_write("match_end", || end_match(conn, match_id, logged, winner_faction, reason)) {
+        error!(server = server_id, match_id; "Failed to close match {}: {}", match_id, e);
+    }
+}
+
+fn end_match(
+    conn: &mut PooledConn,
+    match_id: u64,
+    logged: &LogTime,
+    winner_faction: Option<&str>,
+    reason: Option<&str>,
+) -> Result<(), mysql::Error> {
+    conn.exec_drop(
+        format!(
+            r"UPDATE Matches
+            SET ended_at = {logged_at},
+                duration_seconds = GREATEST(TIMESTAMPDIFF(SECOND, started_at, {logged_at}), 0),
+                winner_faction = :winner,
+                end_reason = :reason
+            WHERE match_id = :mid AND ended_at IS NULL",
+            logged_at = tail::LOGGED_AT_SQL
+        ),
+        params! {
+            "mid" => match_id,
+            "winner" => winner_faction,
+            "reason" => reason,
+            "log_date" => logged.date.as_deref(),
+            "log_time" => logged.time.as_deref(),
+        },
+    )?;
+    // Already ended by an earlier read of the same line: its results were counted then.
+    if conn.affected_rows() == 0 {
+        return Ok(());
+    }
+
+    // Without a winner (restart, crash) the faction results stay undecided.
+    if let Some(winner) = winner_faction {
+        conn.exec_drop(
+            r"UPDATE MatchFactionResults
+            SET result = CASE WHEN faction = :winner THEN 'WIN' ELSE 'LOSS' END
+            WHERE match_id = :mid",
+            params! { "mid" => match_id, "winner" => winner },
+        )?;
+
+        // Carry the result over to each player's record for the faction they fought for
+        conn.exec_drop(
+            r"INSERT INTO PlayerFactionStats (player_id, server_id, faction, wins, losses)
+            SELECT s.player_id, m.server_id, s.faction, s.faction = :winner, s.faction <> :winner
+            FROM MatchPlayerStats s
+            JOIN Matches m ON m.match_id = s.match_id
+            WHERE s.match_id = :mid AND s.faction IS NOT NULL AND s.faction <> ''
+            ON DUPLICATE KEY UPDATE
+                wins = wins + VALUES(wins),
+                losses = losses + VALUES(losses)",
+            params! { "mid" => match_id, "winner" => winner },
+        )?;
+    }
+    Ok(())
+}
+
+// server_id, scenario, map, started_at, ended_at, duration_seconds, winner_faction
+type MatchHeaderRow = (String, Option<String>, Option<String>, String, Option<String>, Option<u32>, Option<String>);
+
+pub fn print_match_summary(conn: &mut PooledConn, match_id: u64) -> Result<(), mysql::Error> {
+    let header: Option<MatchHeaderRow> = conn.exec_first(
+        r"SELECT server_id, scenario, map, DATE_FORMAT(started_at, '%Y-%m-%d %H:%i:%s'),
+            DATE_FORMAT(ended_at, '%Y-%m-%d %H:%i:%s'), duration_seconds, winner_faction
+        FROM Matches WHERE match_id = :mid",
+        params! { "mid" => match_id },
+    )?;
+    let Some((server_id, scenario, map, started_at, ended_at, duration, winner)) = header else {
+        println!("Match {} not found", match_id);
+        return Ok(());
+    };
+
+    println!("=== MATCH {} SUMMARY ===", match_id);
+    println!("Server: {}", server_id);
+    println!("Scenario: {}", scenario.as_deref().unwrap_or("unknown"));
+    println!("Map: {}", map.as_deref().unwrap_or("unknown"));
+    println!("Started: {}", started_at);
+    match (ended_at, duration) {
+        (Some(ended), Some(seconds)) => println!("Ended: {} ({}m {}s)", ended, seconds / 60, seconds % 60),
+        _ => println!("Ended: still running"),
+    }
+    if let Some(winner) = winner {
+        println!("Winner: {}", winner);
+    }
+
+    let factions: Vec<(String, u32, u32, u32, Option<String>)> = conn.exec(
+        r"SELECT faction, kills, deaths, team_kills, result FROM MatchFactionResults
+        WHERE match_id = :mid ORDER BY kills DESC",
+        params! { "mid" => match_id },
+    )?;
+    for (faction, kills, deaths, team_kills, result) in factions {
+        println!(
+            "Faction {}: {} kills, {} deaths, {} team kills{}",
+            faction,
+            kills,
+            deaths,
+            team_kills,
+            result.map(|r| format!(" [{}]", r)).unwrap_or_default()
+        );
+    }
+
+    let scoreboard: Vec<(String, u32, u32, u32)> = conn.exec(
+        r"SELECT COALESCE((SELECT n.username FROM PlayerNames n WHERE n.player_id = s.player_id
+                ORDER BY n.last_used DESC LIMIT 1), CONCAT('#', s.player_id)),
+            s.kills, s.deaths, s.team_kills
+        FROM MatchPlayerStats s
+        WHERE s.match_id = :mid
+        ORDER BY s.kills DESC, s.deaths ASC
+        LIMIT 10",
+        params! { "mid" => match_id },
+    )?;
+    if !scoreboard.is_empty() {
+        println!("Top players:");
+    }
+    for (name, kills, deaths, team_kills) in scoreboard {
+        println!("  {}: {} kills, {} deaths, {} team kills", name, kills, deaths, team_kills);
+    }
+    println!();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_start_and_end() {
+        assert!(matches!(parse_match_line("12:00:00.000  DEFAULT      : Game successfully created."), Some(MatchEvent::GameStarted)));
+
+        let event = parse_match_line("12:30:00.000  SCRIPT       : GAME_ENDED: winnerFaction='#WCS-Faction_NATO', reason='EndModeReason.SCORE'");
+        let Some(MatchEvent::GameEnded { winner_faction, reason }) = event else { panic!("expected game end, got {:?}", event) };
+        assert_eq!(winner_faction.as_deref(), Some("NATO"));
+        assert_eq!(reason.as_deref(), Some("EndModeReason.SCORE"));
+
+        let event = parse_match_line("GAME_ENDED: winnerFaction='', reason=''");
+        assert!(matches!(event, Some(MatchEvent::GameEnded { winner_faction: None, reason: None })));
+    }
+
+    #[test]
+    fn scenario_and_map() {
+        let event = parse_match_line("Loading scenario {ECC61978EDCC2B5A}Missions/23_Campaign.conf");
+        assert!(matches!(event, Some(MatchEvent::ScenarioLoaded { ref scenario }) if scenario == "{ECC61978EDCC2B5A}Missions/23_Campaign.conf"));
+
+        let event = parse_match_line("Loading world {853E92315D1D9EFE}worlds/Eden/Eden.ent");
+        assert!(matches!(event, Some(MatchEvent::MapLoaded { ref map }) if map == "Eden"));
+    }
+
+    #[test]
+    fn other_lines_are_ignored() {
+        assert!(parse_match_line("PLAYER_KILLED: killerName='A', victimName='B'").is_none());
+        assert!(parse_match_line("Loading world without a resource").is_none());
+    }
+}
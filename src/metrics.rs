@@ -0,0 +1,280 @@
+use log::{error, info};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::event_bus::EventBus;
+use crate::health::{self, HealthConfig};
+
+// Everything /metrics exposes: name, Prometheus type and help text, in output order.
+const METRICS: &[(&str, &str, &str)] = &[
+    ("df_backend_log_lines_read_total", "counter", "Lines read from a server's console.log, per reader."),
+    ("df_backend_log_bytes_read_total", "counter", "Bytes read from a server's console.log, per reader."),
+    ("df_backend_tail_lag_seconds", "gauge", "Seconds the log has had unread writes since the reader's last pass (0 when caught up)."),
+    ("df_backend_events_parsed_total", "counter", "Events parsed from the logs, per type."),
+    ("df_backend_parse_failures_total", "counter", "Log lines that looked like an event but could not be parsed."),
+    ("df_backend_duplicate_events_total", "counter", "Events skipped because their event id was already stored."),
+    ("df_backend_db_write_seconds", "histogram", "Duration of database writes, per operation."),
+    ("df_backend_db_write_errors_total", "counter", "Failed database writes, per operation."),
+    ("df_backend_event_queue_depth", "gauge", "Events published on the event bus and not yet taken by a subscriber."),
+    ("df_backend_event_queue_dropped_total", "counter", "Events a subscriber missed because its queue was full."),
+    ("df_backend_players_online", "gauge", "Players connected according to the log."),
+    ("df_backend_kills_per_minute", "gauge", "Kills logged in the last 60 seconds."),
+    ("df_backend_collector_lines_received_total", "counter", "Lines received from agents by the collector."),
+];
+
+// Upper bounds of the database write histogram buckets, in seconds.
+const DB_WRITE_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+static REGISTRY: LazyLock<Mutex<Registry>> = LazyLock::new(|| Mutex::new(Registry::default()));
+
+#[derive(Default)]
+struct Histogram {
+    // Cumulative counts per DB_WRITE_BUCKETS bound.
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+// Where a reader is in its log file; the lag is worked out when /metrics is scraped.
+struct Tail {
+    path: PathBuf,
+    last_read: SystemTime,
+}
+
+#[derive(Default)]
+struct Registry {
+    // Keyed by metric name and rendered labels, e.g. `server="eu1",reader="kill_watcher"`.
+    counters: BTreeMap<(&'static str, String), u64>,
+    histograms: BTreeMap<(&'static str, String), Histogram>,
+    // By (server, reader).
+    tails: BTreeMap<(String, &'static str), Tail>,
+    online: BTreeMap<String, HashSet<String>>,
+    recent_kills: BTreeMap<String, VecDeque<Instant>>,
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+pub fn add(name: &'static str, labels: &[(&str, &str)], value: u64) {
+    *REGISTRY.lock().unwrap().counters.entry((name, render_labels(labels))).or_default() += value;
+}
+
+pub fn inc(name: &'static str, labels: &[(&str, &str)]) {
+    add(name, labels, 1);
+}
+
+// A reader finished a pass over a server's log.
+pub fn record_tail(server_id: &str, reader: &'static str, path: PathBuf, lines: u64, bytes: u64) {
+    let labels = [("server", server_id), ("reader", reader)];
+    add("df_backend_log_lines_read_total", &labels, lines);
+    add("df_backend_log_bytes_read_total", &labels, bytes);
+    REGISTRY
+        .lock()
+        .unwrap()
+        .tails
+        .insert((server_id.to_string(), reader), Tail { path, last_read: SystemTime::now() });
+}
+
+pub fn event_parsed(server_id: &str, event_type: &str) {
+    inc("df_backend_events_parsed_total", &[("server", server_id), ("type", event_type)]);
+}
+
+pub fn parse_failure(server_id: &str, kind: &str) {
+    inc("df_backend_parse_failures_total", &[("server", server_id), ("kind", kind)]);
+}
+
+pub fn duplicate_event(server_id: &str, event_type: &str) {
+    inc("df_backend_duplicate_events_total", &[("server", server_id), ("type", event_type)]);
+}
+
+// Runs a database write and records how long it took and whether it failed.
+pub fn db_write<T, E>(op: &str, write: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    let started = Instant::now();
+    let result = write();
+    let seconds = started.elapsed().as_secs_f64();
+
+    let labels = render_labels(&[("op", op)]);
+    let mut registry = REGISTRY.lock().unwrap();
+    let histogram = registry.histograms.entry(("df_backend_db_write_seconds", labels.clone())).or_default();
+    if histogram.buckets.is_empty() {
+        histogram.buckets = vec![0; DB_WRITE_BUCKETS.len()];
+    }
+    for (bound, count) in DB_WRITE_BUCKETS.iter().zip(histogram.buckets.iter_mut()) {
+        if seconds <= *bound {
+            *count += 1;
+        }
+    }
+    histogram.count += 1;
+    histogram.sum += seconds;
+    if result.is_err() {
+        *registry.counters.entry(("df_backend_db_write_errors_total", labels)).or_default() += 1;
+    }
+    result
+}
+
+pub fn player_connected(server_id: &str, username: &str) {
+    REGISTRY.lock().unwrap().online.entry(server_id.to_string()).or_default().insert(username.to_string());
+}
+
+pub fn player_disconnected(server_id: &str, username: &str) {
+    if let Some(online) = REGISTRY.lock().unwrap().online.get_mut(server_id) {
+        online.remove(username);
+    }
+}
+
+// A new scenario means a restarted game; everyone connects again.
+pub fn clear_online(server_id: &str) {
+    REGISTRY.lock().unwrap().online.remove(server_id);
+}
+
+pub fn kill_logged(server_id: &str) {
+    let now = Instant::now();
+    let mut registry = REGISTRY.lock().unwrap();
+    let kills = registry.recent_kills.entry(server_id.to_string()).or_default();
+    kills.push_back(now);
+    // Pruned here too, so the window stays a minute long when nobody scrapes.
+    prune_kills(kills, now.checked_sub(Duration::from_secs(60)));
+}
+
+fn prune_kills(kills: &mut VecDeque<Instant>, cutoff: Option<Instant>) {
+    while kills.front().is_some_and(|at| cutoff.is_some_and(|cutoff| *at < cutoff)) {
+        kills.pop_front();
+    }
+}
+
+// Seconds each reader's log has had unread writes, by (server, reader); 0 when caught up.
+pub fn tail_lags() -> Vec<(String, &'static str, f64)> {
+    let registry = REGISTRY.lock().unwrap();
+    let now = SystemTime::now();
+    registry
+        .tails
+        .iter()
+        .map(|((server, reader), tail)| {
+            let modified = fs::metadata(&tail.path).and_then(|m| m.modified()).ok();
+            let lag = match modified {
+                Some(modified) if modified > tail.last_read => {
+                    now.duration_since(tail.last_read).unwrap_or_default().as_secs_f64()
+                }
+                _ => 0.0,
+            };
+            (server.clone(), *reader, lag)
+        })
+        .collect()
+}
+
+fn render(bus: &EventBus) -> String {
+    // Gauges are worked out at scrape time: (metric, labels, value).
+    let mut gauges: Vec<(&str, String, f64)> = Vec::new();
+    for (server, reader, lag) in tail_lags() {
+        gauges.push(("df_backend_tail_lag_seconds", render_labels(&[("server", &server), ("reader", reader)]), lag));
+    }
+
+    // Read before locking REGISTRY, so the bus and the registry are never locked together.
+    for (subscriber, depth) in bus.queue_depths() {
+        gauges.push(("df_backend_event_queue_depth", render_labels(&[("subscriber", subscriber)]), depth as f64));
+    }
+
+    let mut registry = REGISTRY.lock().unwrap();
+    let minute_ago = Instant::now().checked_sub(Duration::from_secs(60));
+    for (server, online) in &registry.online {
+        gauges.push(("df_backend_players_online", render_labels(&[("server", server)]), online.len() as f64));
+    }
+    for (server, kills) in registry.recent_kills.iter_mut() {
+        prune_kills(kills, minute_ago);
+        gauges.push(("df_backend_kills_per_minute", render_labels(&[("server", server)]), kills.len() as f64));
+    }
+
+    let mut out = String::new();
+    for (name, kind, help) in METRICS {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+        for ((metric, labels), value) in &registry.counters {
+            if metric == name {
+                out.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+            }
+        }
+        for (metric, labels, value) in &gauges {
+            if metric == name {
+                out.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+            }
+        }
+        for ((metric, labels), histogram) in &registry.histograms {
+            if metric != name {
+                continue;
+            }
+            for (bound, count) in DB_WRITE_BUCKETS.iter().zip(&histogram.buckets) {
+                out.push_str(&format!("{}_bucket{{{},le=\"{}\"}} {}\n", name, labels, bound, count));
+            }
+            out.push_str(&format!("{}_bucket{{{},le=\"+Inf\"}} {}\n", name, labels, histogram.count));
+            out.push_str(&format!("{}_sum{{{}}} {}\n", name, labels, histogram.sum));
+            out.push_str(&format!("{}_count{{{}}} {}\n", name, labels, histogram.count));
+        }
+    }
+    out
+}
+
+#[derive(Clone)]
+pub struct MetricsConfig {
+    pub bind: String,
+}
+
+impl MetricsConfig {
+    // The metrics endpoint is only served when METRICS_BIND is set (e.g. "127.0.0.1:9100").
+    pub fn from_env() -> Option<Self> {
+        let bind = env::var("METRICS_BIND").ok().filter(|b| !b.is_empty())?;
+        Some(Self { bind })
+    }
+}
+
+fn json_response(healthy: bool, body: serde_json::Value) -> Response<io::Cursor<Vec<u8>>> {
+    Response::from_string(body.to_string())
+        .with_status_code(if healthy { 200 } else { 503 })
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+// Serves /metrics and the /healthz and /readyz probes on their own port, apart from the public API.
+// Only /readyz needs the database.
+pub fn run_metrics_server(config: MetricsConfig, bus: EventBus) {
+    let server = match Server::http(&config.bind) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to start metrics endpoint on {}: {}", config.bind, e);
+            return;
+        }
+    };
+    info!("Metrics listening on {}", config.bind);
+    let health = HealthConfig::from_env();
+    // Connected on the first readiness check, and retried by later ones while the database is unavailable.
+    let pool = Mutex::new(None);
+
+    for request in server.incoming_requests() {
+        let response = match (request.method(), request.url()) {
+            (Method::Get, "/metrics") => Response::from_string(render(&bus)).with_header(
+                Header::from_bytes("Content-Type", "text/plain; version=0.0.4; charset=utf-8").unwrap(),
+            ),
+            (Method::Get, "/healthz") => {
+                let (healthy, body) = health::liveness();
+                json_response(healthy, body)
+            }
+            (Method::Get, "/readyz") => {
+                let (ready, body) = health::readiness(&health, &bus, &pool);
+                json_response(ready, body)
+            }
+            _ => Response::from_string("not found").with_status_code(404),
+        };
+        let _ = request.respond(response);
+    }
+}
@@ -0,0 +1,766 @@
+use log::{info, warn};
+use mysql::{params, prelude::*, Params, PooledConn, Row, Transaction, TxOpts};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::achievements;
+use crate::rating;
+
+// The source player's rows in the tables a merge changes, kept in PlayerMerges.snapshot so an undo
+// can put them back. Values are stored as text and converted back by MySQL on insert.
+struct SnapshotTable {
+    name: &'static str,
+    columns: &'static [&'static str],
+    // Which rows belong to the source player (:src)
+    filter: &'static str,
+    // ON DUPLICATE KEY UPDATE clause adding a row the source gained since the merge to its restored
+    // snapshot row with the same key
+    combine: &'static str,
+}
+
+// Combining two rows of the same key: the source's with the target's when merging, and rows the source
+// gained since the merge with its restored ones when undoing it.
+// Names and IP addresses keep the earliest first and the latest last use; connected_at is assigned
+// last so the other columns still compare against the old value.
+const NAMES_COMBINE: &str = r"first_used = LEAST(PlayerNames.first_used, VALUES(first_used)),
+    last_used = GREATEST(PlayerNames.last_used, VALUES(last_used))";
+const CONNECTIONS_COMBINE: &str = r"username = IF(VALUES(connected_at) > ConnectionLogs.connected_at, VALUES(username), ConnectionLogs.username),
+    server_id = IF(VALUES(connected_at) > ConnectionLogs.connected_at, VALUES(server_id), ConnectionLogs.server_id),
+    match_id = IF(VALUES(connected_at) > ConnectionLogs.connected_at, VALUES(match_id), ConnectionLogs.match_id),
+    connected_at = GREATEST(ConnectionLogs.connected_at, VALUES(connected_at))";
+// Totals add up, records keep the best, streaks come from whoever killed last
+const WEAPON_STATS_COMBINE: &str = r"total_kills = PlayerWeaponStats.total_kills + VALUES(total_kills),
+    total_team_kills = PlayerWeaponStats.total_team_kills + VALUES(total_team_kills),
+    total_distance = PlayerWeaponStats.total_distance + VALUES(total_distance),
+    longest_kill = GREATEST(PlayerWeaponStats.longest_kill, VALUES(longest_kill)),
+    last_kill = GREATEST(COALESCE(PlayerWeaponStats.last_kill, VALUES(last_kill)), COALESCE(VALUES(last_kill), PlayerWeaponStats.last_kill))";
+const VERSUS_STATS_COMBINE: &str = r"total_kills = PlayerVsPlayerStats.total_kills + VALUES(total_kills),
+    last_kill = GREATEST(COALESCE(PlayerVsPlayerStats.last_kill, VALUES(last_kill)), COALESCE(VALUES(last_kill), PlayerVsPlayerStats.last_kill))";
+const PLAYER_STATS_COMBINE: &str = r"total_kills = PlayerStats.total_kills + VALUES(total_kills),
+    total_deaths = PlayerStats.total_deaths + VALUES(total_deaths),
+    total_team_kills = PlayerStats.total_team_kills + VALUES(total_team_kills),
+    longest_kill = GREATEST(PlayerStats.longest_kill, VALUES(longest_kill)),
+    favorite_weapon = COALESCE(PlayerStats.favorite_weapon, VALUES(favorite_weapon)),
+    current_streak = IF(COALESCE(VALUES(last_kill_at) > PlayerStats.last_kill_at, PlayerStats.last_kill_at IS NULL),
+        VALUES(current_streak), PlayerStats.current_streak),
+    multi_kill = IF(COALESCE(VALUES(last_kill_at) > PlayerStats.last_kill_at, PlayerStats.last_kill_at IS NULL),
+        VALUES(multi_kill), PlayerStats.multi_kill),
+    best_streak = GREATEST(PlayerStats.best_streak, VALUES(best_streak)),
+    best_multi_kill = GREATEST(PlayerStats.best_multi_kill, VALUES(best_multi_kill)),
+    double_kills = PlayerStats.double_kills + VALUES(double_kills),
+    triple_kills = PlayerStats.triple_kills + VALUES(triple_kills),
+    first_bloods = PlayerStats.first_bloods + VALUES(first_bloods),
+    weapon_records = PlayerStats.weapon_records + VALUES(weapon_records),
+    last_kill_at = GREATEST(COALESCE(PlayerStats.last_kill_at, VALUES(last_kill_at)), COALESCE(VALUES(last_kill_at), PlayerStats.last_kill_at))";
+
+const SNAPSHOT_TABLES: &[SnapshotTable] = &[
+    SnapshotTable {
+        name: "PlayerNames",
+        columns: &["name_id", "player_id", "username", "first_used", "last_used"],
+        filter: "player_id = :src",
+        combine: NAMES_COMBINE,
+    },
+    SnapshotTable {
+        name: "ConnectionLogs",
+        columns: &["player_id", "ip_address", "username", "server_id", "match_id", "connected_at"],
+        filter: "player_id = :src",
+        combine: CONNECTIONS_COMBINE,
+    },
+    // A link made since the merge is kept.
+    SnapshotTable {
+        name: "PlayerDiscordLink",
+        columns: &["player_id", "discord_id"],
+        filter: "player_id = :src",
+        combine: "discord_id = VALUES(discord_id)",
+    },
+    SnapshotTable {
+        name: "PlayerWeaponStats",
+        columns: &[
+            "player_id", "server_id", "weapon", "total_kills", "total_team_kills", "total_distance", "longest_kill",
+            "last_kill",
+        ],
+        filter: "player_id = :src",
+        combine: WEAPON_STATS_COMBINE,
+    },
+    SnapshotTable {
+        name: "PlayerVsPlayerStats",
+        columns: &["killer_id", "victim_id", "server_id", "total_kills", "last_kill"],
+        filter: "killer_id = :src OR victim_id = :src",
+        combine: VERSUS_STATS_COMBINE,
+    },
+    SnapshotTable {
+        name: "PlayerStats",
+        columns: &[
+            "player_id", "server_id", "total_kills", "total_deaths", "total_team_kills", "kd_ratio", "longest_kill",
+            "favorite_weapon", "current_streak", "best_streak", "multi_kill", "best_multi_kill", "double_kills",
+            "triple_kills", "first_bloods", "weapon_records", "last_kill_at",
+        ],
+        filter: "player_id = :src",
+        combine: PLAYER_STATS_COMBINE,
+    },
+    SnapshotTable {
+        name: "PlayerAchievements",
+        columns: &["player_id", "achievement_key", "name", "unlocked_at", "kill_id"],
+        filter: "player_id = :src",
+        // The unlock from before the merge is the earlier one.
+        combine: "unlocked_at = PlayerAchievements.unlocked_at",
+    },
+];
+
+// Per-player statistics that a merge adds to the target's and an undo takes off again. The source's
+// rows are kept in the snapshot like those of SNAPSHOT_TABLES.
+struct CounterTable {
+    name: &'static str,
+    // Columns besides player_id that identify a row
+    key: &'static [&'static str],
+    // Added up
+    counters: &'static [&'static str],
+    // The higher value is kept
+    best: &'static [&'static str],
+    // The target's value is kept when it has one
+    kept: &'static [&'static str],
+    // Which of t.player_id's kills (alias `k`) a row `t` counts, for recomputing its best columns,
+    // which are all longest kills; joined with AND
+    kills: &'static [&'static str],
+}
+
+const COUNTER_TABLES: &[CounterTable] = &[
+    CounterTable {
+        name: "PeriodPlayerStats",
+        key: &["period_type", "period_start", "server_id"],
+        counters: &["kills", "deaths", "team_kills"],
+        best: &["longest_kill"],
+        kept: &[],
+        kills: &[PERIOD_KILLS],
+    },
+    CounterTable {
+        name: "PeriodWeaponStats",
+        key: &["period_type", "period_start", "server_id", "weapon"],
+        counters: &["kills", "team_kills", "total_distance"],
+        best: &["longest_kill"],
+        kept: &[],
+        kills: &[PERIOD_KILLS, "k.weapon = t.weapon"],
+    },
+    CounterTable {
+        name: "SeasonPlayerStats",
+        key: &["season_id", "server_id"],
+        counters: &["kills", "deaths", "team_kills"],
+        best: &["longest_kill"],
+        kept: &["final_rank", "final_rating"],
+        kills: &[
+            "k.server_id = t.server_id",
+            r"EXISTS (SELECT 1 FROM Seasons s WHERE s.season_id = t.season_id
+                AND k.killed_at >= s.starts_at AND (s.ends_at IS NULL OR k.killed_at < s.ends_at))",
+        ],
+    },
+    CounterTable {
+        name: "PlayerFactionStats",
+        key: &["server_id", "faction"],
+        counters: &["kills", "deaths", "team_kills", "wins", "losses"],
+        best: &[],
+        kept: &[],
+        kills: &["k.server_id = t.server_id", "k.killer_faction = t.faction"],
+    },
+    CounterTable {
+        name: "MatchPlayerStats",
+        key: &["match_id"],
+        counters: &["kills", "deaths", "team_kills"],
+        best: &["longest_kill"],
+        kept: &["faction"],
+        kills: &["k.match_id = t.match_id"],
+    },
+];
+
+// The kills falling into a PeriodPlayerStats or PeriodWeaponStats row's day, week or month
+const PERIOD_KILLS: &str = r"k.server_id = t.server_id AND k.killed_at >= t.period_start
+    AND k.killed_at < CASE t.period_type WHEN 'DAY' THEN t.period_start + INTERVAL 1 DAY
+        WHEN 'WEEK' THEN t.period_start + INTERVAL 1 WEEK ELSE t.period_start + INTERVAL 1 MONTH END";
+
+impl CounterTable {
+    fn columns(&self) -> Vec<&'static str> {
+        let mut columns = vec!["player_id"];
+        for group in [self.key, self.counters, self.best, self.kept] {
+            columns.extend_from_slice(group);
+        }
+        columns
+    }
+
+    // ON DUPLICATE KEY UPDATE clause combining two rows of the same key
+    fn combine(&self) -> String {
+        let updates: Vec<String> = self
+            .counters
+            .iter()
+            .map(|c| format!("{c} = {t}.{c} + VALUES({c})", c = c, t = self.name))
+            .chain(self.best.iter().map(|c| format!("{c} = GREATEST({t}.{c}, VALUES({c}))", c = c, t = self.name)))
+            .chain(self.kept.iter().map(|c| format!("{c} = COALESCE({t}.{c}, VALUES({c}))", c = c, t = self.name)))
+            .collect();
+        updates.join(", ")
+    }
+
+    // Adds the source's rows to the target's and removes them from the source.
+    fn merge(&self, tx: &mut Transaction, source: u64, target: u64) -> Result<(), mysql::Error> {
+        let columns = self.columns();
+        tx.exec_drop(
+            format!(
+                "INSERT INTO {t} ({columns}) SELECT :tgt, {rest} FROM {t} s WHERE s.player_id = :src ON DUPLICATE KEY UPDATE {updates}",
+                t = self.name,
+                columns = columns.join(", "),
+                rest = columns[1..].join(", "),
+                updates = self.combine()
+            ),
+            params! { "src" => source, "tgt" => target },
+        )?;
+        tx.exec_drop(format!("DELETE FROM {} WHERE player_id = :src", self.name), params! { "src" => source })
+    }
+
+    // Takes the restored source rows off the target's again, drops the target's rows that are left
+    // empty, which it only had because of the merge, and recomputes the best columns of the others
+    // from the kills the target kept.
+    fn subtract(&self, tx: &mut Transaction, source: u64, target: u64) -> Result<(), mysql::Error> {
+        let join: Vec<String> = self.key.iter().map(|c| format!("s.{c} = t.{c}", c = c)).collect();
+        let set: Vec<String> = self.counters.iter().map(|c| format!("t.{c} = t.{c} - LEAST(t.{c}, s.{c})", c = c)).collect();
+        let empty: Vec<String> = self.counters.iter().map(|c| format!("{} = 0", c)).collect();
+        let ids = params! { "src" => source, "tgt" => target };
+        tx.exec_drop(
+            format!(
+                "UPDATE {t} t JOIN {t} s ON s.player_id = :src AND {join} SET {set} WHERE t.player_id = :tgt",
+                t = self.name,
+                join = join.join(" AND "),
+                set = set.join(", ")
+            ),
+            ids.clone(),
+        )?;
+        tx.exec_drop(format!("DELETE FROM {} WHERE player_id = :tgt AND {}", self.name, empty.join(" AND ")), ids.clone())?;
+        if self.best.is_empty() {
+            return Ok(());
+        }
+        let best: Vec<String> = self
+            .best
+            .iter()
+            .map(|c| {
+                format!(
+                    "t.{c} = COALESCE((SELECT MAX(COALESCE(k.distance, 0)) FROM PlayerKills k WHERE k.killer_id = t.player_id AND {kills}), 0)",
+                    c = c,
+                    kills = self.kills.join(" AND ")
+                )
+            })
+            .collect();
+        tx.exec_drop(format!("UPDATE {} t SET {} WHERE t.player_id = :tgt", self.name, best.join(", ")), ids)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    // Source rows by table, in SNAPSHOT_TABLES column order
+    rows: BTreeMap<String, Vec<Vec<Option<String>>>>,
+    // Names, IP addresses and the Discord link the target only has because of the merge
+    moved_names: Vec<String>,
+    moved_ips: Vec<String>,
+    moved_discord: Option<String>,
+    // Achievements the target only has because of the merge
+    #[serde(default)]
+    moved_achievements: Vec<String>,
+    // The target's ConnectionLogs rows for IP addresses the source also used, which the merge may
+    // update, in TARGET_CONNECTION_COLUMNS order
+    #[serde(default)]
+    target_connections: Vec<Vec<Option<String>>>,
+}
+
+const TARGET_CONNECTION_COLUMNS: &[&str] = &["ip_address", "username", "server_id", "match_id", "connected_at"];
+
+#[derive(Debug)]
+pub enum MergeError {
+    SamePlayer,
+    PlayerNotFound(u64),
+    // The player was already merged into another one by this (not undone) merge.
+    MergedAway { player_id: u64, merge_id: u64 },
+    MergeNotFound(u64),
+    AlreadyUndone(u64),
+    // A later merge involves the same players and has to be undone first.
+    LaterMerge(u64),
+    BadSnapshot(u64),
+    Database(mysql::Error),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::SamePlayer => write!(f, "a player cannot be merged into itself"),
+            MergeError::PlayerNotFound(id) => write!(f, "no player #{}", id),
+            MergeError::MergedAway { player_id, merge_id } => {
+                write!(f, "player #{} was already merged away (merge #{})", player_id, merge_id)
+            }
+            MergeError::MergeNotFound(id) => write!(f, "no merge #{}", id),
+            MergeError::AlreadyUndone(id) => write!(f, "merge #{} was already undone", id),
+            MergeError::LaterMerge(id) => write!(f, "merge #{} involves the same players and has to be undone first", id),
+            MergeError::BadSnapshot(id) => write!(f, "merge #{} has an unreadable snapshot", id),
+            MergeError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+impl From<mysql::Error> for MergeError {
+    fn from(e: mysql::Error) -> Self {
+        MergeError::Database(e)
+    }
+}
+
+pub struct MergeReport {
+    pub merge_id: u64,
+    // Kills re-pointed from the source (as killer or victim)
+    pub kills: u64,
+    pub names: Vec<String>,
+}
+
+// Moves everything of `source` (typically a ghost created for an unknown name in the kill feed) to
+// `target`: names, IP log, Discord link, achievements, weapon records and kills are re-pointed, the
+// statistics added up and the ratings recomputed. The source Players row is kept, so its other
+// history stays intact and the merge can be undone.
+pub fn merge_players(conn: &mut PooledConn, source: u64, target: u64, merged_by: &str) -> Result<MergeReport, MergeError> {
+    if source == target {
+        return Err(MergeError::SamePlayer);
+    }
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    for player_id in [source, target] {
+        let exists: Option<u64> =
+            tx.exec_first("SELECT player_id FROM Players WHERE player_id = :pid FOR UPDATE", params! { "pid" => player_id })?;
+        if exists.is_none() {
+            return Err(MergeError::PlayerNotFound(player_id));
+        }
+        let merged: Option<u64> = tx.exec_first(
+            "SELECT merge_id FROM PlayerMerges WHERE source_id = :pid AND undone_at IS NULL LIMIT 1",
+            params! { "pid" => player_id },
+        )?;
+        if let Some(merge_id) = merged {
+            return Err(MergeError::MergedAway { player_id, merge_id });
+        }
+    }
+
+    let ids = params! { "src" => source, "tgt" => target };
+    let mut rows = BTreeMap::new();
+    for table in SNAPSHOT_TABLES {
+        rows.insert(table.name.to_string(), snapshot_rows(&mut tx, table.name, table.columns, table.filter, &ids)?);
+    }
+    for table in COUNTER_TABLES {
+        rows.insert(table.name.to_string(), snapshot_rows(&mut tx, table.name, &table.columns(), "player_id = :src", &ids)?);
+    }
+    let moved_names: Vec<String> = tx.exec(
+        r"SELECT username FROM PlayerNames s WHERE s.player_id = :src
+          AND NOT EXISTS (SELECT 1 FROM PlayerNames t WHERE t.player_id = :tgt AND t.username = s.username)",
+        ids.clone(),
+    )?;
+    let moved_ips: Vec<String> = tx.exec(
+        r"SELECT ip_address FROM ConnectionLogs s WHERE s.player_id = :src
+          AND NOT EXISTS (SELECT 1 FROM ConnectionLogs t WHERE t.player_id = :tgt AND t.ip_address = s.ip_address)",
+        ids.clone(),
+    )?;
+    let source_discord: Option<String> =
+        tx.exec_first("SELECT discord_id FROM PlayerDiscordLink WHERE player_id = :src", ids.clone())?;
+    let target_discord: Option<String> =
+        tx.exec_first("SELECT discord_id FROM PlayerDiscordLink WHERE player_id = :tgt", ids.clone())?;
+    // The target's own link wins; the source's is only moved when the target has none.
+    let moved_discord = source_discord.filter(|_| target_discord.is_none());
+    let moved_achievements: Vec<String> = tx.exec(
+        r"SELECT achievement_key FROM PlayerAchievements s WHERE s.player_id = :src
+          AND NOT EXISTS (SELECT 1 FROM PlayerAchievements t WHERE t.player_id = :tgt AND t.achievement_key = s.achievement_key)",
+        ids.clone(),
+    )?;
+    let target_connections = snapshot_rows(
+        &mut tx,
+        "ConnectionLogs",
+        TARGET_CONNECTION_COLUMNS,
+        "player_id = :tgt AND ip_address IN (SELECT ip_address FROM ConnectionLogs s WHERE s.player_id = :src)",
+        &ids,
+    )?;
+
+    let snapshot = Snapshot {
+        rows,
+        moved_names: moved_names.clone(),
+        moved_ips,
+        moved_discord: moved_discord.clone(),
+        moved_achievements,
+        target_connections,
+    };
+    tx.exec_drop(
+        r"INSERT INTO PlayerMerges (source_id, target_id, merged_by, snapshot)
+        VALUES (:src, :tgt, :by, :snapshot)",
+        params! {
+            "src" => source,
+            "tgt" => target,
+            "by" => merged_by,
+            "snapshot" => serde_json::to_string(&snapshot).expect("snapshot serializes"),
+        },
+    )?;
+    let merge_id = tx.last_insert_id().unwrap_or(0);
+
+    // Kills: remember which ones were the source's so an undo can move exactly those back
+    tx.exec_drop(
+        r"INSERT INTO PlayerMergeKills (merge_id, kill_id, role)
+        SELECT :mid, kill_id, 'killer' FROM PlayerKills WHERE killer_id = :src
+        UNION ALL
+        SELECT :mid, kill_id, 'victim' FROM PlayerKills WHERE victim_id = :src",
+        params! { "mid" => merge_id, "src" => source },
+    )?;
+    tx.exec_drop("UPDATE PlayerKills SET killer_id = :tgt WHERE killer_id = :src", ids.clone())?;
+    let mut kills = tx.affected_rows();
+    tx.exec_drop("UPDATE PlayerKills SET victim_id = :tgt WHERE victim_id = :src", ids.clone())?;
+    kills += tx.affected_rows();
+
+    tx.exec_drop(
+        format!(
+            r"INSERT INTO PlayerNames (player_id, username, first_used, last_used)
+            SELECT :tgt, username, first_used, last_used FROM PlayerNames s WHERE s.player_id = :src
+            ON DUPLICATE KEY UPDATE {}",
+            NAMES_COMBINE
+        ),
+        ids.clone(),
+    )?;
+    tx.exec_drop("DELETE FROM PlayerNames WHERE player_id = :src", ids.clone())?;
+    tx.exec_drop(
+        format!(
+            r"INSERT INTO ConnectionLogs (player_id, ip_address, username, server_id, match_id, connected_at)
+            SELECT :tgt, ip_address, username, server_id, match_id, connected_at FROM ConnectionLogs s WHERE s.player_id = :src
+            ON DUPLICATE KEY UPDATE {}",
+            CONNECTIONS_COMBINE
+        ),
+        ids.clone(),
+    )?;
+    tx.exec_drop("DELETE FROM ConnectionLogs WHERE player_id = :src", ids.clone())?;
+    if moved_discord.is_some() {
+        tx.exec_drop("UPDATE PlayerDiscordLink SET player_id = :tgt WHERE player_id = :src", ids.clone())?;
+    } else {
+        tx.exec_drop("DELETE FROM PlayerDiscordLink WHERE player_id = :src", ids.clone())?;
+    }
+
+    tx.exec_drop(
+        format!(
+            r"INSERT INTO PlayerWeaponStats
+            (player_id, server_id, weapon, total_kills, total_team_kills, total_distance, longest_kill, last_kill)
+            SELECT :tgt, server_id, weapon, total_kills, total_team_kills, total_distance, longest_kill, last_kill
+            FROM PlayerWeaponStats s WHERE s.player_id = :src
+            ON DUPLICATE KEY UPDATE {}",
+            WEAPON_STATS_COMBINE
+        ),
+        ids.clone(),
+    )?;
+    tx.exec_drop("DELETE FROM PlayerWeaponStats WHERE player_id = :src", ids.clone())?;
+    // Kills between the two players become the target's kills of itself
+    tx.exec_drop(
+        format!(
+            r"INSERT INTO PlayerVsPlayerStats (killer_id, victim_id, server_id, total_kills, last_kill)
+            SELECT IF(killer_id = :src, :tgt, killer_id), IF(victim_id = :src, :tgt, victim_id), server_id, total_kills, last_kill
+            FROM PlayerVsPlayerStats s WHERE s.killer_id = :src OR s.victim_id = :src
+            ON DUPLICATE KEY UPDATE {}",
+            VERSUS_STATS_COMBINE
+        ),
+        ids.clone(),
+    )?;
+    tx.exec_drop("DELETE FROM PlayerVsPlayerStats WHERE killer_id = :src OR victim_id = :src", ids.clone())?;
+    tx.exec_drop(
+        format!(
+            r"INSERT INTO PlayerStats
+            (player_id, server_id, total_kills, total_deaths, total_team_kills, longest_kill, favorite_weapon, current_streak,
+             best_streak, multi_kill, best_multi_kill, double_kills, triple_kills, first_bloods, weapon_records, last_kill_at)
+            SELECT :tgt, server_id, total_kills, total_deaths, total_team_kills, longest_kill, favorite_weapon, current_streak,
+                best_streak, multi_kill, best_multi_kill, double_kills, triple_kills, first_bloods, weapon_records, last_kill_at
+            FROM PlayerStats s WHERE s.player_id = :src
+            ON DUPLICATE KEY UPDATE {}",
+            PLAYER_STATS_COMBINE
+        ),
+        ids.clone(),
+    )?;
+    tx.exec_drop("DELETE FROM PlayerStats WHERE player_id = :src", ids.clone())?;
+    update_kd_ratio(&mut tx, target)?;
+    for table in COUNTER_TABLES {
+        table.merge(&mut tx, source, target)?;
+    }
+    // The target keeps its own achievements; the source's others move over. Weapon records the
+    // source holds go with its kills.
+    tx.exec_drop(
+        r"INSERT INTO PlayerAchievements (player_id, achievement_key, name, unlocked_at, kill_id)
+        SELECT :tgt, achievement_key, name, unlocked_at, kill_id FROM PlayerAchievements s WHERE s.player_id = :src
+        ON DUPLICATE KEY UPDATE unlocked_at = PlayerAchievements.unlocked_at",
+        ids.clone(),
+    )?;
+    tx.exec_drop("DELETE FROM PlayerAchievements WHERE player_id = :src", ids.clone())?;
+    tx.exec_drop("UPDATE WeaponRecords SET player_id = :tgt WHERE player_id = :src", ids.clone())?;
+
+    tx.commit()?;
+    info!(player = target; "Merged player #{} into #{} (merge #{}, {} kill(s)) by {}", source, target, merge_id, kills, merged_by);
+    recompute_ratings(conn);
+    Ok(MergeReport { merge_id, kills, names: moved_names })
+}
+
+// Splits a merged player off again: the source gets back its rows from the snapshot and its kills,
+// and what the merge added to the target is taken off. Kills recorded since the merge stay with the
+// target. Returns the number of kills moved back.
+pub fn undo_merge(conn: &mut PooledConn, merge_id: u64, undone_by: &str) -> Result<u64, MergeError> {
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    let merge: Option<(u64, u64, Option<String>, String)> = tx.exec_first(
+        "SELECT source_id, target_id, CAST(undone_at AS CHAR), snapshot FROM PlayerMerges WHERE merge_id = :mid FOR UPDATE",
+        params! { "mid" => merge_id },
+    )?;
+    let (source, target, undone_at, snapshot) = merge.ok_or(MergeError::MergeNotFound(merge_id))?;
+    if undone_at.is_some() {
+        return Err(MergeError::AlreadyUndone(merge_id));
+    }
+    let later: Option<u64> = tx.exec_first(
+        r"SELECT merge_id FROM PlayerMerges
+        WHERE merge_id > :mid AND undone_at IS NULL
+          AND (source_id IN (:src, :tgt) OR target_id IN (:src, :tgt))
+        ORDER BY merge_id DESC LIMIT 1",
+        params! { "mid" => merge_id, "src" => source, "tgt" => target },
+    )?;
+    if let Some(later) = later {
+        return Err(MergeError::LaterMerge(later));
+    }
+    let snapshot: Snapshot = serde_json::from_str(&snapshot).map_err(|_| MergeError::BadSnapshot(merge_id))?;
+    let ids = params! { "mid" => merge_id, "src" => source, "tgt" => target };
+
+    // A kill between the two players has two rows here, one per role, so the roles are moved separately
+    tx.exec_drop(
+        r"UPDATE PlayerKills k JOIN PlayerMergeKills m ON m.kill_id = k.kill_id AND m.merge_id = :mid AND m.role = 'killer'
+        SET k.killer_id = :src",
+        ids.clone(),
+    )?;
+    let mut kills = tx.affected_rows();
+    tx.exec_drop(
+        r"UPDATE PlayerKills k JOIN PlayerMergeKills m ON m.kill_id = k.kill_id AND m.merge_id = :mid AND m.role = 'victim'
+        SET k.victim_id = :src",
+        ids.clone(),
+    )?;
+    kills += tx.affected_rows();
+
+    for username in &snapshot.moved_names {
+        tx.exec_drop(
+            "DELETE FROM PlayerNames WHERE player_id = :tgt AND username = :name",
+            params! { "tgt" => target, "name" => username },
+        )?;
+    }
+    for ip in &snapshot.moved_ips {
+        tx.exec_drop(
+            "DELETE FROM ConnectionLogs WHERE player_id = :tgt AND ip_address = :ip",
+            params! { "tgt" => target, "ip" => ip },
+        )?;
+    }
+    for key in &snapshot.moved_achievements {
+        tx.exec_drop(
+            "DELETE FROM PlayerAchievements WHERE player_id = :tgt AND achievement_key = :key",
+            params! { "tgt" => target, "key" => key },
+        )?;
+    }
+    if let Some(ref discord_id) = snapshot.moved_discord {
+        tx.exec_drop(
+            "DELETE FROM PlayerDiscordLink WHERE player_id = :tgt AND discord_id = :did",
+            params! { "tgt" => target, "did" => discord_id },
+        )?;
+    }
+    // Rows the source gained since the merge (it connected or played again) are set aside, so the
+    // snapshot goes back as it was and is taken off the target's totals exactly; they are added to it
+    // once that is done.
+    let mut gained = Vec::new();
+    for table in SNAPSHOT_TABLES {
+        let rows = snapshot_rows(&mut tx, table.name, table.columns, table.filter, &ids)?;
+        tx.exec_drop(format!("DELETE FROM {} WHERE {}", table.name, table.filter), ids.clone())?;
+        gained.push((table.name, table.columns.to_vec(), table.combine.to_string(), rows));
+    }
+    for table in COUNTER_TABLES {
+        let rows = snapshot_rows(&mut tx, table.name, &table.columns(), "player_id = :src", &ids)?;
+        tx.exec_drop(format!("DELETE FROM {} WHERE player_id = :src", table.name), ids.clone())?;
+        gained.push((table.name, table.columns(), table.combine(), rows));
+    }
+    for (table, columns, combine, _) in &gained {
+        if let Some(rows) = snapshot.rows.get(*table) {
+            restore_rows(&mut tx, table, columns, combine, rows)?;
+        }
+    }
+    // A shared IP address's row took the source's last connection if that was later. It gets the
+    // target's own back, unless the target has connected from there again since.
+    for row in &snapshot.target_connections {
+        let [ip, username, server_id, match_id, connected_at] = &row[..] else {
+            return Err(MergeError::BadSnapshot(merge_id));
+        };
+        tx.exec_drop(
+            r"UPDATE ConnectionLogs t
+            JOIN ConnectionLogs s ON s.player_id = :src AND s.ip_address = t.ip_address
+            SET t.username = :uname, t.server_id = :server, t.match_id = :match_id, t.connected_at = :connected_at
+            WHERE t.player_id = :tgt AND t.ip_address = :ip AND t.connected_at = s.connected_at",
+            params! {
+                "src" => source,
+                "tgt" => target,
+                "ip" => ip,
+                "uname" => username,
+                "server" => server_id,
+                "match_id" => match_id,
+                "connected_at" => connected_at,
+            },
+        )?;
+    }
+    tx.exec_drop(
+        r"UPDATE WeaponRecords w
+        JOIN PlayerMergeKills m ON m.kill_id = w.kill_id AND m.merge_id = :mid AND m.role = 'killer'
+        SET w.player_id = :src",
+        ids.clone(),
+    )?;
+
+    // The restored source rows are exactly what the merge added to the target's totals
+    tx.exec_drop(
+        r"UPDATE PlayerWeaponStats t
+        JOIN PlayerWeaponStats s ON s.player_id = :src AND s.server_id = t.server_id AND s.weapon = t.weapon
+        SET t.total_kills = t.total_kills - LEAST(t.total_kills, s.total_kills),
+            t.total_team_kills = t.total_team_kills - LEAST(t.total_team_kills, s.total_team_kills),
+            t.total_distance = GREATEST(t.total_distance - s.total_distance, 0)
+        WHERE t.player_id = :tgt",
+        ids.clone(),
+    )?;
+    tx.exec_drop(
+        r"UPDATE PlayerVsPlayerStats t
+        JOIN (
+            SELECT IF(killer_id = :src, :tgt, killer_id) AS killer_id, IF(victim_id = :src, :tgt, victim_id) AS victim_id,
+                server_id, SUM(total_kills) AS total_kills
+            FROM PlayerVsPlayerStats WHERE killer_id = :src OR victim_id = :src
+            GROUP BY 1, 2, server_id
+        ) s ON s.killer_id = t.killer_id AND s.victim_id = t.victim_id AND s.server_id = t.server_id
+        SET t.total_kills = t.total_kills - LEAST(t.total_kills, s.total_kills)",
+        ids.clone(),
+    )?;
+    tx.exec_drop(
+        r"UPDATE PlayerStats t
+        JOIN PlayerStats s ON s.player_id = :src AND s.server_id = t.server_id
+        SET t.total_kills = t.total_kills - LEAST(t.total_kills, s.total_kills),
+            t.total_deaths = t.total_deaths - LEAST(t.total_deaths, s.total_deaths),
+            t.total_team_kills = t.total_team_kills - LEAST(t.total_team_kills, s.total_team_kills),
+            t.double_kills = t.double_kills - LEAST(t.double_kills, s.double_kills),
+            t.triple_kills = t.triple_kills - LEAST(t.triple_kills, s.triple_kills),
+            t.first_bloods = t.first_bloods - LEAST(t.first_bloods, s.first_bloods),
+            t.weapon_records = t.weapon_records - LEAST(t.weapon_records, s.weapon_records)
+        WHERE t.player_id = :tgt",
+        ids.clone(),
+    )?;
+    // Rows the target only had because of the merge are empty now
+    tx.exec_drop(
+        "DELETE FROM PlayerWeaponStats WHERE player_id = :tgt AND total_kills = 0 AND total_team_kills = 0",
+        ids.clone(),
+    )?;
+    tx.exec_drop(
+        "DELETE FROM PlayerVsPlayerStats WHERE (killer_id = :tgt OR victim_id = :tgt) AND total_kills = 0",
+        ids.clone(),
+    )?;
+    tx.exec_drop(
+        "DELETE FROM PlayerStats WHERE player_id = :tgt AND total_kills = 0 AND total_deaths = 0 AND total_team_kills = 0",
+        ids.clone(),
+    )?;
+    // Records the target only held through the source's kills go back to its own best
+    tx.exec_drop(
+        r"UPDATE PlayerWeaponStats t SET t.longest_kill = COALESCE((SELECT MAX(COALESCE(k.distance, 0)) FROM PlayerKills k
+            WHERE k.killer_id = t.player_id AND k.server_id = t.server_id AND k.weapon = t.weapon), 0)
+        WHERE t.player_id = :tgt",
+        ids.clone(),
+    )?;
+    tx.exec_drop(
+        r"UPDATE PlayerStats t SET t.longest_kill = COALESCE((SELECT MAX(COALESCE(k.distance, 0)) FROM PlayerKills k
+            WHERE k.killer_id = t.player_id AND k.server_id = t.server_id), 0)
+        WHERE t.player_id = :tgt",
+        ids.clone(),
+    )?;
+    achievements::recompute_streaks(&mut tx, target)?;
+    for table in COUNTER_TABLES {
+        table.subtract(&mut tx, source, target)?;
+    }
+    for (table, columns, combine, rows) in &gained {
+        restore_rows(&mut tx, table, columns, combine, rows)?;
+    }
+    update_kd_ratio(&mut tx, target)?;
+    update_kd_ratio(&mut tx, source)?;
+
+    tx.exec_drop(
+        "UPDATE PlayerMerges SET undone_at = NOW(), undone_by = :by WHERE merge_id = :mid",
+        params! { "mid" => merge_id, "by" => undone_by },
+    )?;
+    tx.commit()?;
+    info!(player = target; "Undid merge #{} of player #{} into #{} ({} kill(s) moved back) by {}", merge_id, source, target, kills, undone_by);
+    recompute_ratings(conn);
+    Ok(kills)
+}
+
+// Ratings depend on the order of every kill between all players, so they cannot be added up or taken
+// apart like the other statistics; they are recomputed once the kills have moved.
+fn recompute_ratings(conn: &mut PooledConn) {
+    if let Err(e) = rating::recompute_ratings(conn) {
+        warn!("Failed to recompute ratings after a merge; run `recompute-ratings`: {}", e);
+    }
+}
+
+fn snapshot_rows(
+    tx: &mut Transaction,
+    table: &str,
+    columns: &[&str],
+    filter: &str,
+    ids: &Params,
+) -> Result<Vec<Vec<Option<String>>>, mysql::Error> {
+    let columns: Vec<String> = columns.iter().map(|c| format!("CAST({} AS CHAR)", c)).collect();
+    let rows: Vec<Row> = tx.exec(format!("SELECT {} FROM {} WHERE {}", columns.join(", "), table, filter), ids.clone())?;
+    Ok(rows
+        .into_iter()
+        .map(|row| row.unwrap().into_iter().map(|v| mysql::from_value_opt::<Option<String>>(v).ok().flatten()).collect())
+        .collect())
+}
+
+// Inserts snapshotted rows again, combined by `combine` (an ON DUPLICATE KEY UPDATE clause) with
+// rows of the same key.
+fn restore_rows(
+    tx: &mut Transaction,
+    table: &str,
+    columns: &[&str],
+    combine: &str,
+    rows: &[Vec<Option<String>>],
+) -> Result<(), mysql::Error> {
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    tx.exec_batch(
+        format!("INSERT INTO {} ({}) VALUES ({}) ON DUPLICATE KEY UPDATE {}", table, columns.join(", "), placeholders, combine),
+        rows.iter().cloned(),
+    )
+}
+
+fn update_kd_ratio(tx: &mut Transaction, player_id: u64) -> Result<(), mysql::Error> {
+    tx.exec_drop(
+        r"UPDATE PlayerStats
+        SET kd_ratio = CASE WHEN total_deaths = 0 THEN total_kills ELSE total_kills / total_deaths END
+        WHERE player_id = :pid",
+        params! { "pid" => player_id },
+    )
+}
+
+pub struct MergeRecord {
+    pub merge_id: u64,
+    pub source_id: u64,
+    pub target_id: u64,
+    pub merged_by: String,
+    pub merged_at: String,
+    pub undone_by: Option<String>,
+    pub undone_at: Option<String>,
+}
+
+// Most recent merges first, optionally only those involving one player.
+pub fn list_merges(conn: &mut PooledConn, player_id: Option<u64>, limit: u32, offset: u64) -> Result<Vec<MergeRecord>, mysql::Error> {
+    conn.exec_map(
+        r"SELECT merge_id, source_id, target_id, merged_by, CAST(merged_at AS CHAR), undone_by, CAST(undone_at AS CHAR)
+        FROM PlayerMerges
+        WHERE :pid IS NULL OR source_id = :pid OR target_id = :pid
+        ORDER BY merge_id DESC
+        LIMIT :limit OFFSET :offset",
+        params! { "pid" => player_id, "limit" => limit, "offset" => offset },
+        |(merge_id, source_id, target_id, merged_by, merged_at, undone_by, undone_at)| MergeRecord {
+            merge_id,
+            source_id,
+            target_id,
+            merged_by,
+            merged_at,
+            undone_by,
+            undone_at,
+        },
+    )
+}
@@ -1,39 +1,91 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
-use std::path::Path;
-use std::thread;
 use std::time::Duration;
+use log::{debug, error, info, warn};
 use regex::Regex;
 use std::env;
+use std::sync::LazyLock;
+
+use crate::associations::{self, BanEvasionAlert};
+use crate::battleye_events::{self, ModerationEvent};
+use crate::chat_log::{self, ChatMessage};
+use crate::checkpoint::Checkpoint;
+use crate::database_setup;
+use crate::discord;
+use crate::event_bus::{BusEvent, EventBus};
+use crate::match_tracker::MatchTracker;
+use crate::metrics;
+use crate::servers::ServerConfig;
+use crate::shutdown;
+use crate::tail::{self, LogLine, LogTail, LogTime, TailError};
 
 // Add mysql imports
-use mysql::{Pool, prelude::*};
+use mysql::{Pool, PooledConn, TxOpts, prelude::*};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PlayerConnection {
+    // Id of the server the player connected to
+    pub server_id: String,
     pub identity: String,
     pub ip_address: String,
     pub reforger_id: String,
     pub username: String,
     pub battleye_guid: String,
+    // When the connection completed (its "BE GUID:" line was logged)
+    pub logged: LogTime,
+    // Identifies the "BE GUID:" line, so a connection read again is recognised
+    pub event_id: String,
+}
+
+// BattlEye logs leaving players as "Player #3 Name disconnected".
+static DISCONNECT_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"Player #\d+ (.+) disconnected'?\s*$").unwrap());
+
+#[derive(Debug, Clone)]
+pub struct PlayerDisconnect {
+    pub server_id: String,
+    pub username: String,
+}
+
+// Everything the monitor picked up from one pass over the new log lines.
+#[derive(Default)]
+pub struct MonitorEvents {
+    pub connections: Vec<PlayerConnection>,
+    pub disconnections: Vec<PlayerDisconnect>,
+    pub moderation: Vec<ModerationEvent>,
+    pub chat: Vec<ChatMessage>,
+    // Where the pass's lines start, to read them again if they could not be stored
+    pub start: u64,
 }
 
 pub struct PlayerMonitor {
-    log_path: String,
-    file_position: u64,
+    server_id: String,
+    tail: LogTail,
+    checkpoint: Checkpoint,
+    tracker: MatchTracker,
+    bus: EventBus,
 }
 
 impl PlayerMonitor {
-    pub fn new(log_path: &str) -> Self {
+    pub fn new(server: &ServerConfig, tracker: MatchTracker, bus: EventBus) -> Self {
+        // Resume where the last run stopped; without a checkpoint the whole log is read.
+        let checkpoint = Checkpoint::open(&server.id, "player_monitor");
         Self {
-            log_path: log_path.to_string(),
-            file_position: 0,
+            server_id: server.id.clone(),
+            tail: LogTail::new(
+                &server.id,
+                "player_monitor",
+                server.console_log(),
+                checkpoint.position().unwrap_or(0),
+                checkpoint.identity(),
+            ),
+            checkpoint,
+            tracker,
+            bus,
         }
     }
 
     // Changed to take ownership so the monitor can be moved into a thread.
     pub fn start_monitoring(mut self) {
-        println!("Starting player connection monitor...");
+        info!(server = self.server_id; "Starting player connection monitor");
 
         // Try to create a DB pool from env vars. If any are missing or the pool fails,
         // continue running but skip DB writes.
@@ -48,130 +100,224 @@ impl PlayerMonitor {
                 let url = format!("mysql://{}:{}@{}:{}/{}", user, pass, ip, port, db);
                 match Pool::new(url.as_str()) {
                     Ok(p) => {
-                        println!("DB pool created");
+                        debug!("DB pool created");
                         Some(p)
                     }
                     Err(e) => {
-                        eprintln!("Failed to create DB pool: {}", e);
+                        error!("Failed to create DB pool: {}", e);
                         None
                     }
                 }
             }
             _ => {
-                eprintln!("DB env vars missing; database writes disabled");
+                warn!("DB env vars missing; database writes disabled");
                 None
             }
         };
 
         loop {
-            if let Ok(connections) = self.check_for_events() {
-                for player in connections {
-                    println!("New player connected:");
-                    println!("  Username: {}", player.username);
-                    println!("  IP: {}", player.ip_address);
-                    println!("  Reforger ID: {}", player.reforger_id);
-                    println!("  BattlEye GUID: {}", player.battleye_guid);
-                    println!("  Identity: {}", player.identity);
-                    println!();
-
-                    // If DB pool is available, attempt to upsert into Players, PlayerNames, ConnectionLogs
+            let events = self.check_for_events();
+            if let Err(e) = &events {
+                self.tail.report(e);
+            }
+            // Set when the database could not be reached (or a write lost a lock), so the pass is read again.
+            let mut failed = false;
+            let start = events.as_ref().map_or(0, |e| e.start);
+            if let Ok(events) = events {
+                for player in events.connections {
+                    debug!(
+                        server = player.server_id,
+                        username = player.username,
+                        ip = player.ip_address,
+                        reforger_id = player.reforger_id,
+                        battleye_guid = player.battleye_guid,
+                        identity = player.identity;
+                        "Player connected"
+                    );
+
+                    // Announced only once stored, so a connection read again after a failed write is announced once.
+                    // Without a database, or when the write can never succeed, it is announced unrecorded.
+                    let stored = match &db_pool {
+                        None => Some(StoredConnection::default()),
+                        Some(pool) => match pool.get_conn() {
+                            Ok(mut conn) => match metrics::db_write("connection", || {
+                                store_connection(&mut conn, &player, self.tracker.current_match())
+                            }) {
+                                Ok(Some(stored)) => Some(stored),
+                                // Already stored (and announced) by an earlier read of the same line.
+                                Ok(None) => {
+                                    debug!(server = self.server_id, event_id = player.event_id; "Skipping duplicate connection {}", player.event_id);
+                                    metrics::duplicate_event(&self.server_id, "connect");
+                                    None
+                                }
+                                Err(e) => {
+                                    error!(server = self.server_id; "Failed to store connection of {}: {}", player.username, e);
+                                    if database_setup::is_transient(&e) {
+                                        failed = true;
+                                        None
+                                    } else {
+                                        Some(StoredConnection::default())
+                                    }
+                                }
+                            },
+                            Err(e) => {
+                                error!("Failed to get DB connection from pool: {}", e);
+                                failed = true;
+                                None
+                            }
+                        },
+                    };
+                    let Some(stored) = stored else {
+                        continue;
+                    };
+
+                    metrics::player_connected(&player.server_id, &player.username);
+                    self.bus.publish(BusEvent::PlayerConnected(player.clone()));
+                    if stored.is_new_player {
+                        self.bus.publish(BusEvent::NewPlayer(player.clone()));
+                    }
+                    if stored.new_ip {
+                        self.bus.publish(BusEvent::NewIp(player.clone()));
+                    }
+                    if let Some(alert) = stored.ban_evasion {
+                        warn!(
+                            server = self.server_id, banned_player_id = alert.banned_player_id;
+                            "Possible ban evasion: {} ({}) shares an IP or GUID with banned player #{} ({})",
+                            alert.username,
+                            alert.reforger_id,
+                            alert.banned_player_id,
+                            alert.banned_name.as_deref().unwrap_or("unknown")
+                        );
+                        self.bus.publish(BusEvent::BanEvasion(alert));
+                    }
+                }
+
+                for player in events.disconnections {
+                    debug!(server = player.server_id, username = player.username; "Player disconnected");
+                    metrics::player_disconnected(&player.server_id, &player.username);
+                    self.bus.publish(BusEvent::PlayerDisconnected(player));
+                }
+
+                // Moderation events come after connections so a kick of a freshly
+                // connected player can still be linked to its Players row.
+                for event in events.moderation {
+                    battleye_events::log_moderation_event(&self.server_id, &event);
+
                     if let Some(pool) = &db_pool {
                         match pool.get_conn() {
                             Ok(mut conn) => {
-                                // Upsert Players (using reforger_id unique constraint)
-                                let upsert_players = r"INSERT INTO Players (reforger_id, battleye_guid)
-                                    VALUES (?, ?)
-                                    ON DUPLICATE KEY UPDATE
-                                        battleye_guid = VALUES(battleye_guid),
-                                        last_seen = CURRENT_TIMESTAMP";
-                                if let Err(e) = conn.exec_drop(
-                                    upsert_players,
-                                    (player.reforger_id.as_str(), player.battleye_guid.as_str()),
-                                ) {
-                                    eprintln!("Failed to upsert Players: {}", e);
-                                    continue;
+                                match metrics::db_write("moderation", || {
+                                    battleye_events::persist_moderation_event(&mut conn, &self.server_id, &event)
+                                }) {
+                                    Ok(true) => {}
+                                    // Already stored by an earlier read of the same line.
+                                    Ok(false) => {
+                                        debug!(server = self.server_id, event_id = event.event_id; "Skipping duplicate moderation event {}", event.event_id);
+                                        metrics::duplicate_event(&self.server_id, "moderation");
+                                    }
+                                    Err(e) => {
+                                        error!(server = self.server_id; "Failed to insert ModerationActions: {}", e);
+                                        failed |= database_setup::is_transient(&e);
+                                    }
                                 }
+                            }
+                            Err(e) => {
+                                error!("Failed to get DB connection from pool: {}", e);
+                                failed = true;
+                            }
+                        }
+                    }
+                }
+
+                for message in events.chat {
+                    debug!(
+                        server = self.server_id, channel = message.channel, username = message.username;
+                        "Chat: {}", message.message
+                    );
 
-                                // Get player_id
-                                let select_id = "SELECT player_id FROM Players WHERE reforger_id = ?";
-                                let player_id_res: Result<Option<u64>, _> =
-                                    conn.exec_first(select_id, (player.reforger_id.as_str(),));
-                                let player_id = match player_id_res {
-                                    Ok(Some(id)) => id,
-                                    Ok(None) => {
-                                        eprintln!("Inserted player but could not retrieve id");
+                    if let Some(pool) = &db_pool {
+                        match pool.get_conn() {
+                            Ok(mut conn) => {
+                                match metrics::db_write("chat", || chat_log::persist_chat_message(&mut conn, &self.server_id, &message)) {
+                                    Ok(true) => {}
+                                    // Already stored (and any link code redeemed) by an earlier read of the same line.
+                                    Ok(false) => {
+                                        debug!(server = self.server_id, event_id = message.event_id; "Skipping duplicate chat message {}", message.event_id);
+                                        metrics::duplicate_event(&self.server_id, "chat");
                                         continue;
                                     }
                                     Err(e) => {
-                                        eprintln!("Failed to query player id: {}", e);
-                                        continue;
+                                        error!(server = self.server_id; "Failed to insert ChatMessages: {}", e);
+                                        failed |= database_setup::is_transient(&e);
                                     }
-                                };
-
-                                // Upsert PlayerNames (unique (player_id, username))
-                                let upsert_name = r"INSERT INTO PlayerNames (player_id, username)
-                                    VALUES (?, ?)
-                                    ON DUPLICATE KEY UPDATE
-                                        last_used = CURRENT_TIMESTAMP";
-                                if let Err(e) = conn.exec_drop(
-                                    upsert_name,
-                                    (player_id, player.username.as_str()),
-                                ) {
-                                    eprintln!("Failed to upsert PlayerNames: {}", e);
-                                    // continue to connection logs attempt anyway
                                 }
-
-                                // Upsert ConnectionLogs (primary key (player_id, ip_address))
-                                let upsert_conn = r"INSERT INTO ConnectionLogs (player_id, ip_address, username, connected_at)
-                                    VALUES (?, ?, ?, CURRENT_TIMESTAMP)
-                                    ON DUPLICATE KEY UPDATE
-                                        username = VALUES(username),
-                                        connected_at = CURRENT_TIMESTAMP";
-                                if let Err(e) = conn.exec_drop(
-                                    upsert_conn,
-                                    (player_id, player.ip_address.as_str(), player.username.as_str()),
-                                ) {
-                                    eprintln!("Failed to upsert ConnectionLogs: {}", e);
+                                if let Some(code) = discord::parse_link_command(&message.message)
+                                    && let Err(e) = discord::redeem_from_chat(&mut conn, code, &message.username)
+                                {
+                                    error!(server = self.server_id; "Failed to redeem Discord link code: {}", e);
                                 }
                             }
                             Err(e) => {
-                                eprintln!("Failed to get DB connection from pool: {}", e);
+                                error!("Failed to get DB connection from pool: {}", e);
+                                failed = true;
                             }
                         }
                     }
                 }
             }
 
-            thread::sleep(Duration::from_secs(10));
+            // Saved once the pass's events are written, so a restart neither skips nor repeats them. A pass
+            // whose events could not all be written is read again; connections, moderation events and chat
+            // already stored are recognised by their event ids.
+            if failed {
+                warn!(server = self.server_id; "Database writes failed; reading the lines from byte {} again on the next pass", start);
+                self.tail.rewind(start);
+            }
+            self.checkpoint.save(self.tail.position(), self.tail.identity());
+            if shutdown::sleep(Duration::from_secs(10)) {
+                break;
+            }
         }
+        info!(server = self.server_id; "Player monitor stopped at byte {}", self.tail.position());
     }
 
-    fn check_for_events(&mut self) -> Result<Vec<PlayerConnection>, std::io::Error> {
-        let path = Path::new(&self.log_path);
-
-        if !path.exists() {
-            return Ok(Vec::new());
-        }
-
-        let mut file = File::open(path)?;
-        file.seek(SeekFrom::Start(self.file_position))?;
+    // Parses the lines added since the last pass.
+    fn check_for_events(&mut self) -> Result<MonitorEvents, TailError> {
+        let log_lines = self.tail.read_lines()?;
+        let start = log_lines.first().map_or(self.tail.position(), |l| l.offset);
 
-        let reader = BufReader::new(file);
-        let mut lines: Vec<String> = Vec::new();
-
-        for line in reader.lines() {
-            if let Ok(content) = line {
-                self.file_position += content.len() as u64 + 1;
-                lines.push(content);
-            }
+        let events = MonitorEvents {
+            connections: self.parse_player_connections(&log_lines),
+            disconnections: log_lines
+                .iter()
+                .filter_map(|l| DISCONNECT_REGEX.captures(&l.text))
+                .map(|caps| PlayerDisconnect { server_id: self.server_id.clone(), username: caps[1].to_string() })
+                .collect(),
+            // A kick or ban typed into chat is not one.
+            moderation: log_lines
+                .iter()
+                .filter(|l| !chat_log::is_chat_line(&l.text))
+                .filter_map(|l| battleye_events::parse_moderation_line(&l.text, self.tail.event_id(l)))
+                .collect(),
+            chat: log_lines.iter().filter_map(|l| chat_log::parse_chat_line(&l.text, self.tail.event_id(l))).collect(),
+            start,
+        };
+        for (event_type, count) in [
+            ("connect", events.connections.len()),
+            ("disconnect", events.disconnections.len()),
+            ("moderation", events.moderation.len()),
+            ("chat", events.chat.len()),
+        ] {
+            metrics::add(
+                "df_backend_events_parsed_total",
+                &[("server", &self.server_id), ("type", event_type)],
+                count as u64,
+            );
         }
-
-        let connections = self.parse_player_connections(&lines);
-
-        Ok(connections)
+        Ok(events)
     }
 
-    fn parse_player_connections(&self, lines: &[String]) -> Vec<PlayerConnection> {
+    fn parse_player_connections(&self, lines: &[LogLine]) -> Vec<PlayerConnection> {
         let identity_regex = Regex::new(r"identity=(\w+).*address=([0-9.]+)").unwrap();
         let auth_regex = Regex::new(r"identityId=([a-f0-9-]+)\s+name=(\w+)").unwrap();
         let guid_regex = Regex::new(r"BE GUID:\s+(\w+)").unwrap();
@@ -182,42 +328,111 @@ impl PlayerMonitor {
         let mut current_reforger_id = None;
         let mut current_username = None;
 
-        for line in lines {
-            if line.contains("authenticating") {
-                if let Some(caps) = identity_regex.captures(line) {
-                    current_identity = Some(caps[1].to_string());
-                    current_ip = Some(caps[2].to_string());
-                }
+        for log_line in lines {
+            let line = log_line.text.as_str();
+            if line.contains("authenticating")
+                && let Some(caps) = identity_regex.captures(line)
+            {
+                current_identity = Some(caps[1].to_string());
+                current_ip = Some(caps[2].to_string());
             }
 
-            if line.contains("Authenticated player") {
-                if let Some(caps) = auth_regex.captures(line) {
-                    current_reforger_id = Some(caps[1].to_string());
-                    current_username = Some(caps[2].to_string());
-                }
+            if line.contains("Authenticated player")
+                && let Some(caps) = auth_regex.captures(line)
+            {
+                current_reforger_id = Some(caps[1].to_string());
+                current_username = Some(caps[2].to_string());
             }
 
-            if line.contains("BE GUID:") {
-                if let Some(caps) = guid_regex.captures(line) {
-                    if let (Some(id), Some(ip), Some(rid), Some(user)) =
-                        (&current_identity, &current_ip, &current_reforger_id, &current_username) {
-                        connections.push(PlayerConnection {
-                            identity: id.clone(),
-                            ip_address: ip.clone(),
-                            reforger_id: rid.clone(),
-                            username: user.clone(),
-                            battleye_guid: caps[1].to_string(),
-                        });
-
-                        current_identity = None;
-                        current_ip = None;
-                        current_reforger_id = None;
-                        current_username = None;
-                    }
-                }
+            if line.contains("BE GUID:")
+                && let Some(caps) = guid_regex.captures(line)
+                && let (Some(id), Some(ip), Some(rid), Some(user)) =
+                    (&current_identity, &current_ip, &current_reforger_id, &current_username)
+            {
+                connections.push(PlayerConnection {
+                    server_id: self.server_id.clone(),
+                    identity: id.clone(),
+                    ip_address: ip.clone(),
+                    reforger_id: rid.clone(),
+                    username: user.clone(),
+                    battleye_guid: caps[1].to_string(),
+                    logged: tail::line_time(line),
+                    event_id: self.tail.event_id(log_line),
+                });
+
+                current_identity = None;
+                current_ip = None;
+                current_reforger_id = None;
+                current_username = None;
+            } else if line.contains("BE GUID:") {
+                // A GUID line without a complete authentication before it, e.g. when the monitor started mid-connection.
+                metrics::parse_failure(&self.server_id, "connect");
             }
         }
 
         connections
     }
 }
+
+// What storing a connection found out, for the events announced about it.
+#[derive(Default)]
+struct StoredConnection {
+    is_new_player: bool,
+    new_ip: bool,
+    ban_evasion: Option<BanEvasionAlert>,
+}
+
+// Stores a connection in Players, ConnectionHistory, PlayerNames and ConnectionLogs in one transaction,
+// so it is either stored everywhere or not at all. None when its event id is already in ConnectionHistory;
+// nothing is changed then.
+fn store_connection(
+    conn: &mut PooledConn,
+    player: &PlayerConnection,
+    match_id: Option<u64>,
+) -> Result<Option<StoredConnection>, mysql::Error> {
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+
+    // Upsert Players (using reforger_id unique constraint); LAST_INSERT_ID(player_id) makes an update
+    // report the existing row's id.
+    tx.exec_drop(
+        r"INSERT INTO Players (reforger_id, battleye_guid)
+        VALUES (?, ?)
+        ON DUPLICATE KEY UPDATE
+            player_id = LAST_INSERT_ID(player_id),
+            battleye_guid = VALUES(battleye_guid),
+            last_seen = CURRENT_TIMESTAMP",
+        (player.reforger_id.as_str(), player.battleye_guid.as_str()),
+    )?;
+    let player_id = tx.last_insert_id().unwrap_or(0);
+
+    let Some(recorded) = associations::record_connection(&mut tx, player_id, player)? else {
+        tx.rollback()?;
+        return Ok(None);
+    };
+
+    // Upsert PlayerNames (unique (player_id, username))
+    tx.exec_drop(
+        r"INSERT INTO PlayerNames (player_id, username)
+        VALUES (?, ?)
+        ON DUPLICATE KEY UPDATE
+            last_used = CURRENT_TIMESTAMP",
+        (player_id, player.username.as_str()),
+    )?;
+
+    // Upsert ConnectionLogs (primary key (player_id, ip_address))
+    tx.exec_drop(
+        r"INSERT INTO ConnectionLogs (player_id, ip_address, username, server_id, match_id, connected_at)
+        VALUES (?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
+        ON DUPLICATE KEY UPDATE
+            username = VALUES(username),
+            server_id = VALUES(server_id),
+            match_id = VALUES(match_id),
+            connected_at = CURRENT_TIMESTAMP",
+        (player_id, player.ip_address.as_str(), player.username.as_str(), player.server_id.as_str(), match_id),
+    )?;
+    // ON DUPLICATE KEY UPDATE reports 1 affected row for an insert
+    let new_ip = tx.affected_rows() == 1 && !recorded.is_new_player;
+
+    tx.commit()?;
+    Ok(Some(StoredConnection { is_new_player: recorded.is_new_player, new_ip, ban_evasion: recorded.ban_evasion }))
+}
@@ -0,0 +1,202 @@
+use mysql::{params, prelude::*, PooledConn, TxOpts};
+use std::collections::HashMap;
+use std::env;
+use std::f64::consts::{LN_10, PI};
+use std::sync::LazyLock;
+
+// Glicko rating: every PvP kill is a game the killer won and the victim lost.
+const INITIAL_RATING: f64 = 1500.0;
+const MAX_DEVIATION: f64 = 350.0;
+// Keeps very active players' ratings from freezing.
+const MIN_DEVIATION: f64 = 30.0;
+const Q: f64 = LN_10 / 400.0;
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+// Deviation regained per day without a game (Glicko's c), so an inactive player's rating becomes
+// uncertain again: with the default of 25 a settled rating is back to new-player uncertainty in about half a year.
+static DEVIATION_DECAY: LazyLock<f64> =
+    LazyLock::new(|| env::var("RATING_DEVIATION_DECAY").ok().and_then(|v| v.parse().ok()).unwrap_or(25.0));
+
+#[derive(Debug, Clone, Copy)]
+struct Rating {
+    rating: f64,
+    deviation: f64,
+    // Unix time of the player's last rated kill or death
+    last_game: Option<f64>,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self { rating: INITIAL_RATING, deviation: MAX_DEVIATION, last_game: None }
+    }
+}
+
+impl Rating {
+    fn deviation_at(&self, at: f64) -> f64 {
+        let days = self.last_game.map(|last| (at - last).max(0.0) / SECONDS_PER_DAY).unwrap_or(0.0);
+        (self.deviation.powi(2) + DEVIATION_DECAY.powi(2) * days).sqrt().min(MAX_DEVIATION)
+    }
+}
+
+fn g(deviation: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * Q.powi(2) * deviation.powi(2) / PI.powi(2)).sqrt()
+}
+
+// One Glicko rating period containing a single game against `opponent` (score 1 for a win, 0 for a loss).
+fn rate(player: &Rating, opponent: &Rating, score: f64, at: f64) -> Rating {
+    let deviation = player.deviation_at(at);
+    let opponent_g = g(opponent.deviation_at(at));
+    let expected = 1.0 / (1.0 + 10f64.powf(-opponent_g * (player.rating - opponent.rating) / 400.0));
+    let d_squared = 1.0 / (Q.powi(2) * opponent_g.powi(2) * expected * (1.0 - expected));
+    let precision = 1.0 / deviation.powi(2) + 1.0 / d_squared;
+    Rating {
+        rating: player.rating + Q / precision * opponent_g * (score - expected),
+        deviation: (1.0 / precision).sqrt().max(MIN_DEVIATION),
+        last_game: Some(at),
+    }
+}
+
+// Both players are rated against each other's rating from before the kill.
+fn rate_kill(killer: &Rating, victim: &Rating, at: f64) -> (Rating, Rating) {
+    (rate(killer, victim, 1.0, at), rate(victim, killer, 0.0, at))
+}
+
+// SQL for a player's deviation as of now, including the decay since their last game.
+// `alias` is the PlayerRatings table alias.
+pub fn current_deviation_sql(alias: &str) -> String {
+    format!(
+        "LEAST({max}, SQRT(POW({a}.deviation, 2) + {c2} * GREATEST(TIMESTAMPDIFF(SECOND, {a}.last_game, NOW()), 0) / {day}))",
+        max = MAX_DEVIATION,
+        a = alias,
+        c2 = DEVIATION_DECAY.powi(2),
+        day = SECONDS_PER_DAY
+    )
+}
+
+// Locks the player's row until the caller's transaction ends, so concurrent kills of the same player
+// are rated one after the other.
+fn load_for_update<C: Queryable>(conn: &mut C, player_id: u64) -> Result<Rating, mysql::Error> {
+    let row: Option<(f64, f64, Option<f64>)> = conn.exec_first(
+        "SELECT rating, deviation, UNIX_TIMESTAMP(last_game) FROM PlayerRatings WHERE player_id = :pid FOR UPDATE",
+        params! { "pid" => player_id },
+    )?;
+    Ok(row
+        .map(|(rating, deviation, last_game)| Rating { rating, deviation, last_game })
+        .unwrap_or_default())
+}
+
+const UPSERT_RATING: &str = r"INSERT INTO PlayerRatings (player_id, rating, deviation, games, last_game)
+    VALUES (:pid, :rating, :deviation, :games, FROM_UNIXTIME(:at))
+    ON DUPLICATE KEY UPDATE
+        rating = VALUES(rating),
+        deviation = VALUES(deviation),
+        games = games + VALUES(games),
+        last_game = VALUES(last_game)";
+
+// Updates killer and victim for a stored PvP kill; team kills and suicides are not rated. Must run in
+// a transaction, which keeps both ratings locked from reading to writing.
+pub fn record_kill<C: Queryable>(conn: &mut C, kill_id: u64, killer_id: u64, victim_id: u64) -> Result<(), mysql::Error> {
+    let at: Option<f64> = conn.exec_first(
+        "SELECT UNIX_TIMESTAMP(killed_at) FROM PlayerKills WHERE kill_id = :kid",
+        params! { "kid" => kill_id },
+    )?;
+    let Some(at) = at else {
+        return Ok(());
+    };
+
+    // Always locked in player id order, so two kills between the same players cannot deadlock here.
+    let (killer, victim) = if killer_id < victim_id {
+        let killer = load_for_update(conn, killer_id)?;
+        (killer, load_for_update(conn, victim_id)?)
+    } else {
+        let victim = load_for_update(conn, victim_id)?;
+        (load_for_update(conn, killer_id)?, victim)
+    };
+    let (killer, victim) = rate_kill(&killer, &victim, at);
+    conn.exec_batch(
+        UPSERT_RATING,
+        [(killer_id, killer), (victim_id, victim)].iter().map(|(pid, r)| {
+            params! { "pid" => pid, "rating" => r.rating, "deviation" => r.deviation, "games" => 1, "at" => at }
+        }),
+    )
+}
+
+// Rebuilds PlayerRatings from every PvP kill in PlayerKills, in kill order. Returns (players, kills).
+// Kills stored by a running backend while this runs may be missed until the next recomputation.
+pub fn recompute_ratings(conn: &mut PooledConn) -> Result<(usize, usize), mysql::Error> {
+    let kills: Vec<(u64, u64, f64)> = conn.query(
+        r"SELECT killer_id, victim_id, UNIX_TIMESTAMP(killed_at)
+        FROM PlayerKills
+        WHERE is_team_kill = FALSE AND killer_id <> victim_id
+        ORDER BY killed_at, kill_id",
+    )?;
+
+    let mut ratings: HashMap<u64, (Rating, u32)> = HashMap::new();
+    for (killer_id, victim_id, at) in &kills {
+        let killer = ratings.get(killer_id).map(|(r, _)| *r).unwrap_or_default();
+        let victim = ratings.get(victim_id).map(|(r, _)| *r).unwrap_or_default();
+        let (killer, victim) = rate_kill(&killer, &victim, *at);
+        for (pid, rating) in [(*killer_id, killer), (*victim_id, victim)] {
+            let entry = ratings.entry(pid).or_insert((rating, 0));
+            *entry = (rating, entry.1 + 1);
+        }
+    }
+
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    tx.query_drop("DELETE FROM PlayerRatings")?;
+    tx.exec_batch(
+        UPSERT_RATING,
+        ratings.iter().map(|(pid, (r, games))| {
+            params! {
+                "pid" => pid,
+                "rating" => r.rating,
+                "deviation" => r.deviation,
+                "games" => games,
+                "at" => r.last_game,
+            }
+        }),
+    )?;
+    tx.commit()?;
+    Ok((ratings.len(), kills.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn win_against_a_settled_opponent() {
+        let player = Rating { rating: 1500.0, deviation: 200.0, last_game: None };
+        let opponent = Rating { rating: 1400.0, deviation: 30.0, last_game: None };
+        let rated = rate(&player, &opponent, 1.0, 1000.0);
+        assert!(close(rated.rating, 1563.4320485812902), "{:?}", rated);
+        assert!(close(rated.deviation, 175.22023356952303), "{:?}", rated);
+        assert_eq!(rated.last_game, Some(1000.0));
+    }
+
+    #[test]
+    fn a_kill_between_new_players_moves_both_equally() {
+        let (killer, victim) = rate_kill(&Rating::default(), &Rating::default(), 0.0);
+        assert!(close(killer.rating, 1662.2120026057648), "{:?}", killer);
+        assert!(close(victim.rating, 1337.7879973942352), "{:?}", victim);
+        assert!(close(killer.deviation, 290.2305060910912));
+        assert!(close(victim.deviation, killer.deviation));
+    }
+
+    #[test]
+    fn deviation_grows_without_games_and_stays_within_bounds() {
+        let settled = Rating { rating: 1500.0, deviation: 50.0, last_game: Some(0.0) };
+        assert!(close(settled.deviation_at(0.0), 50.0));
+        assert!(close(settled.deviation_at(100.0 * SECONDS_PER_DAY), (50f64.powi(2) + DEVIATION_DECAY.powi(2) * 100.0).sqrt()));
+        assert!(close(settled.deviation_at(10_000.0 * SECONDS_PER_DAY), MAX_DEVIATION));
+        // A clock going backwards does not shrink it.
+        assert!(close(settled.deviation_at(-SECONDS_PER_DAY), 50.0));
+
+        let active = Rating { rating: 1500.0, deviation: MIN_DEVIATION, last_game: Some(0.0) };
+        assert!(rate(&active, &Rating::default(), 1.0, 0.0).deviation >= MIN_DEVIATION);
+    }
+}
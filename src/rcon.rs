@@ -0,0 +1,626 @@
+use log::{debug, error, info, warn};
+use mysql::{params, prelude::*, Pool, PooledConn};
+use regex::Regex;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::io;
+use std::net::UdpSocket;
+use std::sync::LazyLock;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use crate::servers::ServerConfig;
+use crate::shutdown;
+
+// BattlEye RCon packets: 'B' 'E' <CRC32 of everything after it, little endian> 0xFF <type> <payload>
+const PACKET_LOGIN: u8 = 0x00;
+const PACKET_COMMAND: u8 = 0x01;
+const PACKET_SERVER_MESSAGE: u8 = 0x02;
+
+// BattlEye drops clients that stay silent for 45 seconds, so send an empty command well before that.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+static PLAYER_LINE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(\d+)\s+(\S+):\d+\s+(-?\d+)\s+([0-9a-fA-F]{32}|-)(?:\((OK|\?)\))?\s+(.+?)(?:\s+\(Lobby\))?$").unwrap()
+});
+
+#[derive(Debug)]
+pub enum RconError {
+    Io(io::Error),
+    LoginRejected,
+    Timeout,
+    Malformed(&'static str),
+}
+
+impl fmt::Display for RconError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RconError::Io(e) => write!(f, "I/O error: {}", e),
+            RconError::LoginRejected => write!(f, "login rejected (wrong RCON_PASSWORD?)"),
+            RconError::Timeout => write!(f, "no response from server"),
+            RconError::Malformed(what) => write!(f, "malformed packet: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for RconError {}
+
+impl From<io::Error> for RconError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => RconError::Timeout,
+            _ => RconError::Io(e),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RconConfig {
+    pub server_id: String,
+    pub address: String,
+    pub password: String,
+    pub poll_interval: Duration,
+    pub enforce_bans: bool,
+}
+
+impl RconConfig {
+    // RCon is optional; it is only enabled for servers with an RCon password. Polling and ban
+    // enforcement are set for all servers by RCON_POLL_INTERVAL and RCON_ENFORCE_BANS.
+    pub fn for_server(server: &ServerConfig) -> Option<Self> {
+        let password = server.rcon_password.clone().filter(|p| !p.is_empty())?;
+        let host = server.rcon_host.as_deref().unwrap_or("127.0.0.1");
+        let port = server.rcon_port.unwrap_or(2306);
+        let poll_interval = env::var("RCON_POLL_INTERVAL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+        let enforce_bans = env::var("RCON_ENFORCE_BANS")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase() == "true";
+
+        Some(Self {
+            server_id: server.id.clone(),
+            address: format!("{}:{}", host, port),
+            password,
+            poll_interval: Duration::from_secs(poll_interval),
+            enforce_bans,
+        })
+    }
+}
+
+// Actions DF_Backend rules can take on the game server.
+#[derive(Debug)]
+pub enum RconCommand {
+    Say { player_number: u32, message: String },
+    Kick { player_number: u32, reason: String },
+    // minutes == 0 is a permanent ban
+    Ban { battleye_guid: String, minutes: u32, reason: String },
+}
+
+impl RconCommand {
+    fn to_command_string(&self) -> String {
+        match self {
+            RconCommand::Say { player_number, message } => format!("say {} {}", player_number, message),
+            RconCommand::Kick { player_number, reason } => format!("kick {} {}", player_number, reason),
+            RconCommand::Ban { battleye_guid, minutes, reason } => {
+                format!("addBan {} {} {}", battleye_guid, minutes, reason)
+            }
+        }
+    }
+}
+
+// Requests from other threads, addressed by in-game name since that is all console.log gives them.
+// They are resolved against the last player list and dropped if the player is not online.
+#[derive(Debug)]
+pub enum PlayerAction {
+    Warn { name: String, message: String },
+    Kick { name: String, reason: String },
+    Ban { name: String, minutes: u32, reason: String },
+}
+
+impl PlayerAction {
+    fn name(&self) -> &str {
+        match self {
+            PlayerAction::Warn { name, .. } | PlayerAction::Kick { name, .. } | PlayerAction::Ban { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RconPlayer {
+    pub number: u32,
+    pub ip_address: String,
+    pub ping: i32,
+    pub battleye_guid: Option<String>,
+    pub guid_verified: bool,
+    pub name: String,
+}
+
+pub fn build_packet(packet_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut checked = Vec::with_capacity(body.len() + 2);
+    checked.push(0xFF);
+    checked.push(packet_type);
+    checked.extend_from_slice(body);
+
+    let mut packet = Vec::with_capacity(checked.len() + 6);
+    packet.extend_from_slice(b"BE");
+    packet.extend_from_slice(&crc32fast::hash(&checked).to_le_bytes());
+    packet.extend_from_slice(&checked);
+    packet
+}
+
+// Returns (type, body) after validating the header and checksum.
+pub fn parse_packet(data: &[u8]) -> Result<(u8, &[u8]), RconError> {
+    if data.len() < 8 || &data[0..2] != b"BE" || data[6] != 0xFF {
+        return Err(RconError::Malformed("bad header"));
+    }
+    let crc = u32::from_le_bytes([data[2], data[3], data[4], data[5]]);
+    if crc != crc32fast::hash(&data[6..]) {
+        return Err(RconError::Malformed("checksum mismatch"));
+    }
+    Ok((data[7], &data[8..]))
+}
+
+pub struct RconClient {
+    socket: UdpSocket,
+    seq: u8,
+    last_sent: Instant,
+    pending_messages: Vec<String>,
+}
+
+impl RconClient {
+    pub fn connect(address: &str, password: &str) -> Result<Self, RconError> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(address)?;
+        socket.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+
+        let mut client = Self {
+            socket,
+            seq: 0,
+            last_sent: Instant::now(),
+            pending_messages: Vec::new(),
+        };
+
+        client.send(PACKET_LOGIN, password.as_bytes())?;
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        while Instant::now() < deadline {
+            let (packet_type, body) = client.receive()?;
+            if packet_type == PACKET_LOGIN {
+                return match body.first() {
+                    Some(0x01) => Ok(client),
+                    _ => Err(RconError::LoginRejected),
+                };
+            }
+        }
+        Err(RconError::Timeout)
+    }
+
+    pub fn command(&mut self, command: &str) -> Result<String, RconError> {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+
+        let mut body = vec![seq];
+        body.extend_from_slice(command.as_bytes());
+        self.send(PACKET_COMMAND, &body)?;
+
+        // Long responses are split: 0x00 <part count> <part index> <data>
+        let mut parts: Vec<Option<Vec<u8>>> = Vec::new();
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        while Instant::now() < deadline {
+            let (packet_type, body) = self.receive()?;
+            if packet_type != PACKET_COMMAND || body.first() != Some(&seq) {
+                continue;
+            }
+
+            if body.len() >= 4 && body[1] == 0x00 {
+                let count = body[2] as usize;
+                let index = body[3] as usize;
+                if count == 0 || index >= count {
+                    return Err(RconError::Malformed("bad multi-packet header"));
+                }
+                if parts.len() != count {
+                    parts = vec![None; count];
+                }
+                parts[index] = Some(body[4..].to_vec());
+                if parts.iter().all(Option::is_some) {
+                    let joined: Vec<u8> = parts.into_iter().flatten().flatten().collect();
+                    return Ok(String::from_utf8_lossy(&joined).into_owned());
+                }
+            } else {
+                return Ok(String::from_utf8_lossy(&body[1..]).into_owned());
+            }
+        }
+        Err(RconError::Timeout)
+    }
+
+    pub fn execute(&mut self, command: &RconCommand) -> Result<String, RconError> {
+        self.command(&command.to_command_string())
+    }
+
+    // Waits up to `wait` for server messages (chat, admin and BattlEye notices),
+    // then drains whatever else is already queued on the socket.
+    pub fn poll_messages(&mut self, wait: Duration) -> Result<Vec<String>, RconError> {
+        self.socket.set_read_timeout(Some(wait))?;
+        let mut result = Ok(());
+        loop {
+            match self.receive() {
+                Ok(_) => self.socket.set_read_timeout(Some(Duration::from_millis(10)))?,
+                Err(RconError::Timeout) => break,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.socket.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+        result?;
+
+        Ok(std::mem::take(&mut self.pending_messages))
+    }
+
+    pub fn needs_keep_alive(&self) -> bool {
+        self.last_sent.elapsed() >= KEEP_ALIVE
+    }
+
+    fn send(&mut self, packet_type: u8, body: &[u8]) -> Result<(), RconError> {
+        self.socket.send(&build_packet(packet_type, body))?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    // Server messages are acknowledged and queued here so they are never lost
+    // while we are waiting for a command response.
+    fn receive(&mut self) -> Result<(u8, Vec<u8>), RconError> {
+        let mut buf = [0u8; 4096];
+        let len = self.socket.recv(&mut buf)?;
+        let (packet_type, body) = parse_packet(&buf[..len])?;
+
+        if packet_type == PACKET_SERVER_MESSAGE {
+            let seq = *body.first().ok_or(RconError::Malformed("empty server message"))?;
+            self.pending_messages.push(String::from_utf8_lossy(&body[1..]).into_owned());
+            self.send(PACKET_SERVER_MESSAGE, &[seq])?;
+        }
+
+        Ok((packet_type, body.to_vec()))
+    }
+}
+
+pub fn parse_player_list(response: &str) -> Vec<RconPlayer> {
+    response
+        .lines()
+        .filter_map(|line| {
+            let caps = PLAYER_LINE_REGEX.captures(line.trim())?;
+            Some(RconPlayer {
+                number: caps[1].parse().ok()?,
+                ip_address: caps[2].to_string(),
+                ping: caps[3].parse().unwrap_or(-1),
+                battleye_guid: Some(caps[4].to_lowercase()).filter(|g| g != "-"),
+                guid_verified: caps.get(5).is_some_and(|m| m.as_str() == "OK"),
+                name: caps[6].to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn run_rcon_client(config: RconConfig, pool: Option<Pool>, actions: &Receiver<PlayerAction>) {
+    info!(server = config.server_id; "Starting BattlEye RCon client for {}", config.address);
+
+    while !shutdown::requested() {
+        match RconClient::connect(&config.address, &config.password) {
+            Ok(client) => {
+                info!(server = config.server_id; "RCon logged in to {}", config.address);
+                if let Err(e) = run_session(client, &config, pool.as_ref(), actions) {
+                    warn!(server = config.server_id; "RCon session ended: {}", e);
+                }
+            }
+            Err(e) => warn!(server = config.server_id; "RCon connect to {} failed: {}", config.address, e),
+        }
+
+        if shutdown::sleep(RECONNECT_DELAY) {
+            break;
+        }
+    }
+    info!(server = config.server_id; "RCon client stopped");
+}
+
+fn run_session(
+    mut client: RconClient,
+    config: &RconConfig,
+    pool: Option<&Pool>,
+    actions: &Receiver<PlayerAction>,
+) -> Result<(), RconError> {
+    let mut roster: HashMap<u32, RconPlayer> = HashMap::new();
+    let mut next_poll = Instant::now();
+
+    // Returns within about a second of a shutdown request, the longest poll_messages waits.
+    while !shutdown::requested() {
+        // Chat and admin messages are also written to console.log, where the player monitor records
+        // them; here they are only logged, so they are not stored twice.
+        for message in client.poll_messages(Duration::from_secs(1))? {
+            info!(server = config.server_id; "RCon message: {}", message);
+        }
+
+        if Instant::now() >= next_poll {
+            let players = parse_player_list(&client.command("players")?);
+            reconcile_roster(&mut client, &mut roster, players, config, pool)?;
+            next_poll = Instant::now() + config.poll_interval;
+        } else if client.needs_keep_alive() {
+            client.command("")?;
+        }
+
+        while let Ok(action) = actions.try_recv() {
+            perform_action(&mut client, &roster, action, &config.server_id)?;
+        }
+    }
+    Ok(())
+}
+
+fn perform_action(
+    client: &mut RconClient,
+    roster: &HashMap<u32, RconPlayer>,
+    action: PlayerAction,
+    server_id: &str,
+) -> Result<(), RconError> {
+    let Some(player) = roster.values().find(|p| p.name == action.name()) else {
+        warn!(server = server_id; "RCon: {} is not online; skipping {:?}", action.name(), action);
+        return Ok(());
+    };
+
+    match action {
+        PlayerAction::Warn { message, .. } => {
+            client.execute(&RconCommand::Say { player_number: player.number, message })?;
+        }
+        PlayerAction::Kick { reason, .. } => {
+            client.execute(&RconCommand::Kick { player_number: player.number, reason })?;
+        }
+        PlayerAction::Ban { minutes, reason, .. } => {
+            match player.battleye_guid.clone() {
+                Some(battleye_guid) => {
+                    client.execute(&RconCommand::Ban { battleye_guid, minutes, reason: reason.clone() })?;
+                }
+                None => warn!(server = server_id; "RCon: no GUID for {}; kicking instead of banning", player.name),
+            }
+            client.execute(&RconCommand::Kick { player_number: player.number, reason })?;
+        }
+    }
+    info!(server = server_id, player = player.name; "RCon: action sent for #{} {}", player.number, player.name);
+    Ok(())
+}
+
+fn reconcile_roster(
+    client: &mut RconClient,
+    roster: &mut HashMap<u32, RconPlayer>,
+    players: Vec<RconPlayer>,
+    config: &RconConfig,
+    pool: Option<&Pool>,
+) -> Result<(), RconError> {
+    let current: HashMap<u32, RconPlayer> = players.into_iter().map(|p| (p.number, p)).collect();
+
+    for (number, player) in roster.iter() {
+        if !current.contains_key(number) {
+            debug!(server = config.server_id, player = player.name; "RCon: #{} {} left", player.number, player.name);
+        }
+    }
+
+    let mut conn = pool.and_then(|p| match p.get_conn() {
+        Ok(c) => Some(c),
+        Err(e) => {
+            error!("Failed to get DB connection from pool: {}", e);
+            None
+        }
+    });
+
+    for player in current.values() {
+        let is_new = roster
+            .get(&player.number)
+            .is_none_or(|known| known.battleye_guid != player.battleye_guid);
+        if is_new {
+            debug!(
+                server = config.server_id, player = player.name;
+                "RCon: #{} {} online ({}, ping {}, GUID {}{})",
+                player.number,
+                player.name,
+                player.ip_address,
+                player.ping,
+                player.battleye_guid.as_deref().unwrap_or("-"),
+                if player.guid_verified { "" } else { " unverified" }
+            );
+        }
+
+        let (Some(conn), Some(guid)) = (conn.as_mut(), player.battleye_guid.as_deref()) else {
+            continue;
+        };
+
+        if let Err(e) = sync_player(conn, player, guid) {
+            error!(server = config.server_id; "Failed to reconcile RCon player {}: {}", player.name, e);
+        }
+
+        if is_new && config.enforce_bans {
+            match active_ban(conn, guid) {
+                Ok(Some((reason, minutes))) => {
+                    warn!(server = config.server_id, player = player.name; "RCon: enforcing DF_Backend ban on #{} {}", player.number, player.name);
+                    client.execute(&RconCommand::Ban {
+                        battleye_guid: guid.to_string(),
+                        minutes,
+                        reason: reason.clone(),
+                    })?;
+                    client.execute(&RconCommand::Kick { player_number: player.number, reason })?;
+                }
+                Ok(None) => {}
+                Err(e) => error!(server = config.server_id; "Failed to check bans for {}: {}", player.name, e),
+            }
+        }
+    }
+
+    *roster = current;
+    Ok(())
+}
+
+// Keeps Players in step with who RCon says is online and fills in GUIDs we have not seen in console.log yet.
+fn sync_player(conn: &mut PooledConn, player: &RconPlayer, guid: &str) -> Result<(), mysql::Error> {
+    conn.exec_drop(
+        "UPDATE Players SET last_seen = CURRENT_TIMESTAMP WHERE battleye_guid = :guid",
+        params! { "guid" => guid },
+    )?;
+    if conn.affected_rows() > 0 {
+        return Ok(());
+    }
+
+    // Only attach the GUID when the name points at exactly one player without one.
+    let candidates: Vec<u64> = conn.exec(
+        r"SELECT DISTINCT p.player_id FROM Players p
+        JOIN PlayerNames n ON n.player_id = p.player_id
+        WHERE n.username = :name AND p.battleye_guid IS NULL",
+        params! { "name" => player.name.as_str() },
+    )?;
+    if let [player_id] = candidates[..] {
+        conn.exec_drop(
+            "UPDATE Players SET battleye_guid = :guid, last_seen = CURRENT_TIMESTAMP WHERE player_id = :pid",
+            params! { "guid" => guid, "pid" => player_id },
+        )?;
+    }
+    Ok(())
+}
+
+// Returns (reason, remaining minutes) for a ban recorded in ModerationActions that is still running.
+// BattlEye uses 0 minutes for permanent bans.
+fn active_ban(conn: &mut PooledConn, guid: &str) -> Result<Option<(String, u32)>, mysql::Error> {
+    let row: Option<(Option<String>, Option<i64>)> = conn.exec_first(
+        r"SELECT reason, TIMESTAMPDIFF(MINUTE, NOW(), created_at + INTERVAL duration_minutes MINUTE)
+        FROM ModerationActions
+        WHERE battleye_guid = :guid AND action_type = 'BAN'
+            AND (duration_minutes IS NULL OR created_at + INTERVAL duration_minutes MINUTE > NOW())
+        ORDER BY duration_minutes IS NULL DESC, created_at DESC
+        LIMIT 1",
+        params! { "guid" => guid },
+    )?;
+
+    Ok(row.map(|(reason, remaining)| {
+        let minutes = remaining.map(|m| m.clamp(1, u32::MAX as i64) as u32).unwrap_or(0);
+        (reason.unwrap_or_else(|| "Banned".to_string()), minutes)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::thread::{self, JoinHandle};
+
+    // A BattlEye server on 127.0.0.1 that each test scripts packet by packet.
+    struct MockServer {
+        socket: UdpSocket,
+        client: Option<SocketAddr>,
+    }
+
+    impl MockServer {
+        fn receive(&mut self) -> (u8, Vec<u8>) {
+            let mut buf = [0u8; 4096];
+            let (len, from) = self.socket.recv_from(&mut buf).expect("no packet from the client");
+            self.client = Some(from);
+            let (packet_type, body) = parse_packet(&buf[..len]).expect("client sent a malformed packet");
+            (packet_type, body.to_vec())
+        }
+
+        fn send(&self, packet_type: u8, body: &[u8]) {
+            self.socket.send_to(&build_packet(packet_type, body), self.client.unwrap()).unwrap();
+        }
+
+        fn accept_login(&mut self, password: &str) {
+            assert_eq!(self.receive(), (PACKET_LOGIN, password.as_bytes().to_vec()));
+            self.send(PACKET_LOGIN, &[0x01]);
+        }
+    }
+
+    // Runs `script` as the server side of one client session and returns the address to connect to.
+    fn mock_server(script: impl FnOnce(&mut MockServer) + Send + 'static) -> (String, JoinHandle<()>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || script(&mut MockServer { socket, client: None }));
+        (address, handle)
+    }
+
+    const PLAYERS: &str = "Players on server:\n\
+        [#] [IP Address]:[Port] [Ping] [GUID] [Name]\n\
+        --------------------------------------------------\n\
+        0   192.0.2.10:2304     31   0123456789ABCDEF0123456789abcdef(OK) Alice\n\
+        1   192.0.2.11:2304     45   fedcba9876543210fedcba9876543210(?) Bob Smith (Lobby)\n\
+        2   192.0.2.12:2304     -1   - Carol\n\
+        (3 players in total)";
+
+    #[test]
+    fn login_is_accepted() {
+        let (address, server) = mock_server(|server| server.accept_login("secret"));
+        assert!(RconClient::connect(&address, "secret").is_ok());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn login_is_rejected() {
+        let (address, server) = mock_server(|server| {
+            server.receive();
+            server.send(PACKET_LOGIN, &[0x00]);
+        });
+        assert!(matches!(RconClient::connect(&address, "wrong"), Err(RconError::LoginRejected)));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn multi_packet_response_is_joined_and_server_messages_are_acknowledged() {
+        let (address, server) = mock_server(|server| {
+            server.accept_login("secret");
+            let (packet_type, body) = server.receive();
+            assert_eq!(packet_type, PACKET_COMMAND);
+            assert_eq!(body, b"\x00players");
+
+            // A server message in the middle of the response must be acknowledged with its sequence number.
+            server.send(PACKET_SERVER_MESSAGE, b"\x07RCon admin #0: (Global) hello");
+            assert_eq!(server.receive(), (PACKET_SERVER_MESSAGE, vec![0x07]));
+
+            // The parts may arrive in any order.
+            let (first, second) = PLAYERS.as_bytes().split_at(100);
+            server.send(PACKET_COMMAND, &[&[0x00, 0x00, 0x02, 0x01][..], second].concat());
+            server.send(PACKET_COMMAND, &[&[0x00, 0x00, 0x02, 0x00][..], first].concat());
+        });
+
+        let mut client = RconClient::connect(&address, "secret").unwrap();
+        let response = client.command("players").unwrap();
+        server.join().unwrap();
+
+        assert_eq!(response, PLAYERS);
+        assert_eq!(client.poll_messages(Duration::from_millis(10)).unwrap(), vec!["RCon admin #0: (Global) hello"]);
+    }
+
+    #[test]
+    fn player_list_keeps_unverified_guids() {
+        let players = parse_player_list(PLAYERS);
+        assert_eq!(players.len(), 3);
+
+        assert_eq!(players[0].number, 0);
+        assert_eq!(players[0].ip_address, "192.0.2.10");
+        assert_eq!(players[0].ping, 31);
+        assert_eq!(players[0].battleye_guid.as_deref(), Some("0123456789abcdef0123456789abcdef"));
+        assert!(players[0].guid_verified);
+        assert_eq!(players[0].name, "Alice");
+
+        assert_eq!(players[1].battleye_guid.as_deref(), Some("fedcba9876543210fedcba9876543210"));
+        assert!(!players[1].guid_verified);
+        assert_eq!(players[1].name, "Bob Smith");
+
+        assert_eq!(players[2].ping, -1);
+        assert_eq!(players[2].battleye_guid, None);
+        assert!(!players[2].guid_verified);
+    }
+
+    #[test]
+    fn packets_with_a_bad_checksum_are_rejected() {
+        let mut packet = build_packet(PACKET_COMMAND, b"\x00players");
+        assert_eq!(parse_packet(&packet).unwrap(), (PACKET_COMMAND, &b"\x00players"[..]));
+        *packet.last_mut().unwrap() ^= 0xFF;
+        assert!(matches!(parse_packet(&packet), Err(RconError::Malformed("checksum mismatch"))));
+    }
+}
@@ -0,0 +1,234 @@
+use log::info;
+use mysql::{params, prelude::*, Params, PooledConn, TxOpts};
+
+// Calendar buckets kept in PeriodPlayerStats / PeriodWeaponStats. Weeks start on Monday.
+const PERIODS_SQL: &str = "(SELECT 'DAY' AS period_type UNION ALL SELECT 'WEEK' UNION ALL SELECT 'MONTH')";
+
+// SQL for the first day of the `period` bucket (a period_type expression) containing `time`.
+pub fn period_start_sql(period: &str, time: &str) -> String {
+    format!(
+        "CASE {p} WHEN 'DAY' THEN DATE({t}) WHEN 'WEEK' THEN DATE({t}) - INTERVAL WEEKDAY({t}) DAY \
+        ELSE DATE(DATE_FORMAT({t}, '%Y-%m-01')) END",
+        p = period,
+        t = time
+    )
+}
+
+// Every kill as a kill for the killer and a death for the victim, like PlayerStats counts them.
+fn kill_events_sql(filter: &str) -> String {
+    format!(
+        "SELECT k.killer_id AS player_id, k.server_id, 1 AS kills, 0 AS deaths, k.is_team_kill AS team_kills,
+            COALESCE(k.distance, 0) AS longest_kill, k.killed_at
+        FROM PlayerKills k WHERE {filter}
+        UNION ALL
+        SELECT k.victim_id, k.server_id, 0, 1, 0, 0, k.killed_at
+        FROM PlayerKills k WHERE {filter}",
+        filter = filter
+    )
+}
+
+const ADD_COUNTERS: &str = r"ON DUPLICATE KEY UPDATE
+    kills = kills + VALUES(kills),
+    deaths = deaths + VALUES(deaths),
+    team_kills = team_kills + VALUES(team_kills),
+    longest_kill = GREATEST(longest_kill, VALUES(longest_kill))";
+
+// Adds the PlayerKills rows matching `filter` (SQL over alias `k`) to the day, week and month buckets.
+fn add_period_kills<C: Queryable>(conn: &mut C, filter: &str, params: Params) -> Result<(), mysql::Error> {
+    conn.exec_drop(
+        format!(
+            "INSERT INTO PeriodPlayerStats
+            (period_type, period_start, player_id, server_id, kills, deaths, team_kills, longest_kill)
+            SELECT p.period_type, {start}, e.player_id, e.server_id, e.kills, e.deaths, e.team_kills, e.longest_kill
+            FROM ({events}) e CROSS JOIN {periods} p
+            {add}",
+            start = period_start_sql("p.period_type", "e.killed_at"),
+            events = kill_events_sql(filter),
+            periods = PERIODS_SQL,
+            add = ADD_COUNTERS
+        ),
+        params.clone(),
+    )?;
+    conn.exec_drop(
+        format!(
+            "INSERT INTO PeriodWeaponStats
+            (period_type, period_start, player_id, server_id, weapon, kills, team_kills, total_distance, longest_kill)
+            SELECT p.period_type, {start}, k.killer_id, k.server_id, k.weapon, 1, k.is_team_kill,
+                COALESCE(k.distance, 0), COALESCE(k.distance, 0)
+            FROM PlayerKills k CROSS JOIN {periods} p
+            WHERE {filter}
+            ON DUPLICATE KEY UPDATE
+                kills = kills + VALUES(kills),
+                team_kills = team_kills + VALUES(team_kills),
+                total_distance = total_distance + VALUES(total_distance),
+                longest_kill = GREATEST(longest_kill, VALUES(longest_kill))",
+            start = period_start_sql("p.period_type", "k.killed_at"),
+            periods = PERIODS_SQL,
+            filter = filter
+        ),
+        params,
+    )
+}
+
+// Adds the PlayerKills rows matching `kill_filter` to the seasons matching `season_filter` (alias `s`)
+// that they fall into.
+fn add_season_kills<C: Queryable>(
+    conn: &mut C,
+    kill_filter: &str,
+    season_filter: &str,
+    params: Params,
+) -> Result<(), mysql::Error> {
+    conn.exec_drop(
+        format!(
+            "INSERT INTO SeasonPlayerStats (season_id, player_id, server_id, kills, deaths, team_kills, longest_kill)
+            SELECT s.season_id, e.player_id, e.server_id, e.kills, e.deaths, e.team_kills, e.longest_kill
+            FROM ({events}) e
+            JOIN Seasons s ON e.killed_at >= s.starts_at AND (s.ends_at IS NULL OR e.killed_at < s.ends_at)
+            WHERE {season_filter}
+            {add}",
+            events = kill_events_sql(kill_filter),
+            season_filter = season_filter,
+            add = ADD_COUNTERS
+        ),
+        params,
+    )
+}
+
+// Counts a stored kill towards its day, week and month and any season still running. Seasons that
+// have ended should be archived first (`archive_ended_seasons`).
+pub fn record_kill<C: Queryable>(conn: &mut C, kill_id: u64) -> Result<(), mysql::Error> {
+    add_period_kills(conn, "k.kill_id = :kid", params! { "kid" => kill_id })?;
+    add_season_kills(conn, "k.kill_id = :kid", "s.archived_at IS NULL", params! { "kid" => kill_id })
+}
+
+// Rebuilds the day/week/month buckets from every stored kill. Returns the number of kills counted.
+pub fn rebuild_periods(conn: &mut PooledConn) -> Result<u64, mysql::Error> {
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    tx.query_drop("DELETE FROM PeriodWeaponStats")?;
+    tx.query_drop("DELETE FROM PeriodPlayerStats")?;
+    add_period_kills(&mut tx, "TRUE", Params::Empty)?;
+    let kills: Option<u64> = tx.query_first("SELECT COUNT(*) FROM PlayerKills")?;
+    tx.commit()?;
+    Ok(kills.unwrap_or(0))
+}
+
+fn rebuild_season<C: Queryable>(conn: &mut C, season_id: u64) -> Result<(), mysql::Error> {
+    conn.exec_drop("DELETE FROM SeasonPlayerStats WHERE season_id = :sid", params! { "sid" => season_id })?;
+    add_season_kills(conn, "TRUE", "s.season_id = :sid", params! { "sid" => season_id })
+}
+
+// Creates a season; kills already stored since `starts_at` count towards it.
+pub fn create_season(
+    conn: &mut PooledConn,
+    name: &str,
+    starts_at: &str,
+    ends_at: Option<&str>,
+) -> Result<u64, mysql::Error> {
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    tx.exec_drop(
+        "INSERT INTO Seasons (name, starts_at, ends_at) VALUES (:name, :starts, :ends)",
+        params! { "name" => name, "starts" => starts_at, "ends" => ends_at },
+    )?;
+    let season_id = tx.last_insert_id().unwrap_or(0);
+    rebuild_season(&mut tx, season_id)?;
+    tx.commit()?;
+    Ok(season_id)
+}
+
+// Ends a running season at `ends_at` (default now). Once the end has passed, its results are archived.
+// Returns false if there is no such running season.
+pub fn end_season(conn: &mut PooledConn, season_id: u64, ends_at: Option<&str>) -> Result<bool, mysql::Error> {
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    tx.exec_drop(
+        "UPDATE Seasons SET ends_at = COALESCE(:ends, NOW()) WHERE season_id = :sid AND archived_at IS NULL",
+        params! { "ends" => ends_at, "sid" => season_id },
+    )?;
+    if tx.affected_rows() == 0 {
+        return Ok(false);
+    }
+    // Kills after an end set in the past were already counted.
+    rebuild_season(&mut tx, season_id)?;
+    tx.commit()?;
+    archive_ended_seasons(conn)?;
+    Ok(true)
+}
+
+// Freezes the results of seasons whose end has passed: final network-wide rank by kills (stored on each of the
+// player's per-server rows) and each player's rating at the time.
+pub fn archive_ended_seasons(conn: &mut PooledConn) -> Result<(), mysql::Error> {
+    let ended: Vec<u64> =
+        conn.query("SELECT season_id FROM Seasons WHERE archived_at IS NULL AND ends_at <= NOW()")?;
+    for season_id in ended {
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        tx.exec_drop(
+            r"UPDATE SeasonPlayerStats sp
+            JOIN (
+                SELECT player_id, ROW_NUMBER() OVER (ORDER BY SUM(kills) DESC, SUM(deaths), player_id) AS final_rank
+                FROM SeasonPlayerStats WHERE season_id = :sid
+                GROUP BY player_id
+            ) ranked ON ranked.player_id = sp.player_id
+            LEFT JOIN PlayerRatings r ON r.player_id = sp.player_id
+            SET sp.final_rank = ranked.final_rank, sp.final_rating = r.rating
+            WHERE sp.season_id = :sid",
+            params! { "sid" => season_id },
+        )?;
+        tx.exec_drop(
+            "UPDATE Seasons SET archived_at = NOW() WHERE season_id = :sid",
+            params! { "sid" => season_id },
+        )?;
+        tx.commit()?;
+        info!(season_id; "Archived season {}", season_id);
+    }
+    Ok(())
+}
+
+// The running season that started most recently, if any.
+pub fn current_season(conn: &mut PooledConn) -> Result<Option<u64>, mysql::Error> {
+    conn.query_first(
+        r"SELECT season_id FROM Seasons
+        WHERE starts_at <= NOW() AND (ends_at IS NULL OR ends_at > NOW())
+        ORDER BY starts_at DESC LIMIT 1",
+    )
+}
+
+// season_id, name, starts_at, ends_at, archived_at, players, top player
+type SeasonRow = (u64, String, String, Option<String>, Option<String>, u64, Option<String>);
+
+pub fn print_seasons(conn: &mut PooledConn) -> Result<(), mysql::Error> {
+    let rows: Vec<SeasonRow> = conn.query(
+        r"SELECT s.season_id, s.name,
+            DATE_FORMAT(s.starts_at, '%Y-%m-%d %H:%i:%s'), DATE_FORMAT(s.ends_at, '%Y-%m-%d %H:%i:%s'),
+            DATE_FORMAT(s.archived_at, '%Y-%m-%d %H:%i:%s'),
+            (SELECT COUNT(DISTINCT sp.player_id) FROM SeasonPlayerStats sp WHERE sp.season_id = s.season_id),
+            (SELECT n.username FROM PlayerNames n
+                WHERE n.player_id = (SELECT sp.player_id FROM SeasonPlayerStats sp
+                    WHERE sp.season_id = s.season_id
+                    GROUP BY sp.player_id
+                    ORDER BY SUM(sp.kills) DESC, SUM(sp.deaths) LIMIT 1)
+                ORDER BY n.last_used DESC LIMIT 1)
+        FROM Seasons s
+        ORDER BY s.starts_at DESC",
+    )?;
+
+    println!("=== SEASONS ===");
+    if rows.is_empty() {
+        println!("None");
+    }
+    for (id, name, starts_at, ends_at, archived_at, players, top) in rows {
+        let state = match (&ends_at, &archived_at) {
+            (_, Some(at)) => format!("archived {}", at),
+            (Some(end), None) => format!("until {}", end),
+            (None, None) => "open-ended".to_string(),
+        };
+        println!(
+            "#{} {} - from {} ({}), {} player(s), top: {}",
+            id,
+            name,
+            starts_at,
+            state,
+            players,
+            top.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use log::info;
+use mysql::{params, prelude::*, PooledConn};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ingest;
+
+// Longest server id the server_id columns hold.
+const MAX_ID_LENGTH: usize = 32;
+
+// One Reforger server: the directory holding its console.log (or the agent token of a remote server
+// whose log arrives through the collector) and, optionally, its BattlEye RCon endpoint.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+    // Set for remote servers, whose agent authenticates with it.
+    #[serde(default)]
+    pub agent_token: Option<String>,
+    #[serde(default)]
+    pub rcon_host: Option<String>,
+    #[serde(default)]
+    pub rcon_port: Option<u16>,
+    // RCon is enabled for the server when this is set.
+    #[serde(default)]
+    pub rcon_password: Option<String>,
+}
+
+impl ServerConfig {
+    pub fn console_log(&self) -> PathBuf {
+        match &self.path {
+            Some(path) => Path::new(path).join("console.log"),
+            None => ingest::spool_log(&self.id),
+        }
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.id)
+    }
+}
+
+// The single server from SERVER_PATH and the RCON_* variables, used when there is no servers file.
+fn server_from_env() -> Result<ServerConfig, String> {
+    let path = env::var("SERVER_PATH").map_err(|_| "SERVER_PATH is not set and no servers file was loaded".to_string())?;
+    Ok(ServerConfig {
+        id: env::var("SERVER_ID").ok().filter(|id| !id.is_empty()).unwrap_or_else(|| "default".to_string()),
+        name: None,
+        path: Some(path),
+        agent_token: None,
+        rcon_host: env::var("RCON_HOST").ok(),
+        rcon_port: env::var("RCON_PORT").ok().and_then(|p| p.parse().ok()),
+        rcon_password: env::var("RCON_PASSWORD").ok().filter(|p| !p.is_empty()),
+    })
+}
+
+fn validate(servers: &[ServerConfig]) -> Result<(), String> {
+    if servers.is_empty() {
+        return Err("no servers configured".to_string());
+    }
+    for (i, server) in servers.iter().enumerate() {
+        if server.id.is_empty() || server.id.len() > MAX_ID_LENGTH {
+            return Err(format!("server id '{}' must be 1-{} characters", server.id, MAX_ID_LENGTH));
+        }
+        // Ids name checkpoint and spool files.
+        if !server.id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("server id '{}' may only contain letters, digits, '_' and '-'", server.id));
+        }
+        if servers[..i].iter().any(|s| s.id == server.id) {
+            return Err(format!("duplicate server id '{}'", server.id));
+        }
+        if server.path.is_some() == server.agent_token.is_some() {
+            return Err(format!("server '{}' needs either a path or an agent_token", server.id));
+        }
+        if server.agent_token.as_deref() == Some("") {
+            return Err(format!("server '{}' has an empty agent_token", server.id));
+        }
+    }
+    Ok(())
+}
+
+// SERVERS_PATH (default `servers.json`) lists the servers to monitor; without it the backend watches
+// the single server at SERVER_PATH, with id SERVER_ID (default `default`). A servers file that cannot be
+// used is an error rather than a reason to fall back, which would drop every server it lists.
+pub fn load_servers() -> Result<Vec<ServerConfig>, String> {
+    let explicit_path = env::var("SERVERS_PATH").ok();
+    let path = explicit_path.clone().unwrap_or_else(|| "servers.json".to_string());
+    match fs::read_to_string(&path) {
+        Ok(content) => {
+            let servers: Vec<ServerConfig> =
+                serde_json::from_str(&content).map_err(|e| format!("Invalid servers file {}: {}", path, e))?;
+            validate(&servers).map_err(|e| format!("Invalid servers file {}: {}", path, e))?;
+            info!("Loaded {} server(s) from {}", servers.len(), path);
+            Ok(servers)
+        }
+        // The file is optional unless it was configured explicitly.
+        Err(e) if explicit_path.is_some() => Err(format!("Failed to read servers file {}: {}", path, e)),
+        Err(_) => {
+            let servers = vec![server_from_env()?];
+            validate(&servers)?;
+            Ok(servers)
+        }
+    }
+}
+
+// Records the configured servers so the API can list them; servers dropped from the config keep their rows and data.
+pub fn register_servers(conn: &mut PooledConn, servers: &[ServerConfig]) -> Result<(), mysql::Error> {
+    conn.exec_batch(
+        r"INSERT INTO Servers (server_id, name, log_path, last_started)
+        VALUES (:id, :name, :path, NOW())
+        ON DUPLICATE KEY UPDATE
+            name = VALUES(name),
+            log_path = VALUES(log_path),
+            last_started = VALUES(last_started)",
+        servers.iter().map(|s| {
+            params! {
+                "id" => &s.id,
+                "name" => s.display_name(),
+                "path" => s.console_log().display().to_string(),
+            }
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(id: &str) -> ServerConfig {
+        serde_json::from_value(serde_json::json!({ "id": id, "path": "/srv/reforger/logs" })).unwrap()
+    }
+
+    #[test]
+    fn server_ids_must_be_safe_in_file_names() {
+        assert!(validate(&[server("eu-1_main")]).is_ok());
+        for id in ["", "../eu1", "eu/1", "eu 1", "eu.1", "ëu1", &"x".repeat(MAX_ID_LENGTH + 1)] {
+            assert!(validate(&[server(id)]).is_err(), "{:?} was accepted", id);
+        }
+    }
+
+    #[test]
+    fn server_ids_must_be_unique() {
+        assert_eq!(validate(&[server("eu1"), server("eu1")]), Err("duplicate server id 'eu1'".to_string()));
+    }
+}
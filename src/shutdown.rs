@@ -0,0 +1,49 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Exit code when a second Ctrl-C or SIGTERM stops the process without waiting for the shutdown.
+const EXIT_INTERRUPTED: i32 = 130;
+// How often sleeping workers look for a shutdown request.
+const POLL: Duration = Duration::from_millis(200);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_signal: libc::c_int) {
+    // Only async-signal-safe work here: set the flag, or leave at once on the second signal.
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(EXIT_INTERRUPTED) };
+    }
+}
+
+// SIGINT and SIGTERM ask the workers to finish what they are doing and stop.
+pub fn install() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as *const () as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+// Sleeps for `duration`, waking early when shutdown is requested; returns whether it was.
+pub fn sleep(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    while !requested() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return false;
+        }
+        thread::sleep(left.min(POLL));
+    }
+    true
+}
+
+// How long the workers get to finish after a signal (SHUTDOWN_TIMEOUT seconds, default 10).
+pub fn timeout() -> Duration {
+    Duration::from_secs(env::var("SHUTDOWN_TIMEOUT").ok().and_then(|v| v.parse().ok()).unwrap_or(10))
+}
@@ -0,0 +1,469 @@
+use log::{error, info, warn};
+use mysql::{params, prelude::*, Pool, PooledConn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+pub const REVIEW_STATUSES: &[&str] = &["OPEN", "CONFIRMED", "DISMISSED"];
+
+// Per-weapon limits. `min_kill_interval_ms` is the fastest two kills can plausibly follow each other
+// with that weapon (roughly its time between shots); `effective_range` is in metres.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WeaponThreshold {
+    #[serde(default = "default_kill_interval_ms")]
+    pub min_kill_interval_ms: u64,
+    #[serde(default = "default_effective_range")]
+    pub effective_range: f64,
+    // Explosives, launchers and vehicles legitimately kill several players at once or far away.
+    #[serde(default)]
+    pub exempt: bool,
+}
+
+fn default_kill_interval_ms() -> u64 {
+    100
+}
+
+fn default_effective_range() -> f64 {
+    600.0
+}
+
+impl Default for WeaponThreshold {
+    fn default() -> Self {
+        Self { min_kill_interval_ms: default_kill_interval_ms(), effective_range: default_effective_range(), exempt: false }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SuspicionConfig {
+    // How often players are scored, and how far back each scan looks.
+    pub scan_interval_minutes: u64,
+    pub window_minutes: u64,
+    // Flag this many consecutive kills with the same weapon, each faster than its kill interval.
+    pub rapid_kills: usize,
+    // Flag this many kills beyond the weapon's effective range within the window.
+    pub long_range_kills: usize,
+    // Flag more kills per minute online than this, once a player has at least `min_kills_for_rate` kills.
+    pub max_kills_per_minute: f64,
+    pub min_kills_for_rate: usize,
+    pub default_weapon: WeaponThreshold,
+    // Keys match weapon names case-insensitively as substrings; the longest matching key wins.
+    pub weapons: HashMap<String, WeaponThreshold>,
+}
+
+impl Default for SuspicionConfig {
+    fn default() -> Self {
+        let weapon = |interval, range, exempt| WeaponThreshold {
+            min_kill_interval_ms: interval,
+            effective_range: range,
+            exempt,
+        };
+        Self {
+            scan_interval_minutes: 5,
+            window_minutes: 60,
+            rapid_kills: 3,
+            long_range_kills: 3,
+            max_kills_per_minute: 2.0,
+            min_kills_for_rate: 15,
+            default_weapon: WeaponThreshold::default(),
+            weapons: HashMap::from([
+                ("M21".to_string(), weapon(400, 900.0, false)),
+                ("SVD".to_string(), weapon(400, 900.0, false)),
+                ("M16".to_string(), weapon(70, 550.0, false)),
+                ("AK".to_string(), weapon(90, 450.0, false)),
+                ("M249".to_string(), weapon(60, 700.0, false)),
+                ("PKM".to_string(), weapon(70, 800.0, false)),
+                ("M9".to_string(), weapon(150, 60.0, false)),
+                ("PM".to_string(), weapon(150, 60.0, false)),
+                ("Grenade".to_string(), weapon(0, 60.0, true)),
+                ("RPG".to_string(), weapon(0, 500.0, true)),
+                ("M72".to_string(), weapon(0, 300.0, true)),
+            ]),
+        }
+    }
+}
+
+impl SuspicionConfig {
+    // Kills without a weapon (vehicles, explosions, falls) are never judged.
+    fn threshold(&self, weapon: &str) -> Option<&WeaponThreshold> {
+        if weapon.is_empty() {
+            return None;
+        }
+        let weapon = weapon.to_lowercase();
+        let threshold = self
+            .weapons
+            .iter()
+            .filter(|(key, _)| weapon.contains(&key.to_lowercase()))
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, t)| t)
+            .unwrap_or(&self.default_weapon);
+        (!threshold.exempt).then_some(threshold)
+    }
+}
+
+// SUSPICION_CONFIG_PATH (default `suspicion.json`) overrides the built-in thresholds; missing fields keep their defaults.
+pub fn load_config() -> SuspicionConfig {
+    let explicit_path = env::var("SUSPICION_CONFIG_PATH").ok();
+    let path = explicit_path.clone().unwrap_or_else(|| "suspicion.json".to_string());
+    match fs::read_to_string(&path) {
+        Ok(content) => match serde_json::from_str::<SuspicionConfig>(&content) {
+            Ok(config) => {
+                info!("Loaded suspicion thresholds from {}", path);
+                return config;
+            }
+            Err(e) => warn!("Ignoring suspicion thresholds {}: {}", path, e),
+        },
+        // The file is optional unless it was configured explicitly.
+        Err(e) if explicit_path.is_some() => warn!("Failed to read suspicion thresholds {}: {}", path, e),
+        Err(_) => {}
+    }
+    SuspicionConfig::default()
+}
+
+struct Kill {
+    kill_id: u64,
+    weapon: String,
+    distance: f64,
+    // Milliseconds since the epoch, from PlayerKills.killed_at
+    at_ms: i64,
+}
+
+struct Incident {
+    kind: &'static str,
+    // How far past the threshold the player was (1.0 = exactly at it)
+    score: f64,
+    details: String,
+    evidence: Vec<u64>,
+}
+
+pub fn run_suspicion_scanner(config: SuspicionConfig, pool: Pool) {
+    info!(
+        "Suspicious-performance scan every {} minute(s) over the last {} minute(s)",
+        config.scan_interval_minutes, config.window_minutes
+    );
+    loop {
+        match pool.get_conn() {
+            Ok(mut conn) => {
+                if let Err(e) = scan(&mut conn, &config) {
+                    error!("Suspicion scan failed: {}", e);
+                }
+            }
+            Err(e) => error!("Failed to get DB connection from pool: {}", e),
+        }
+        thread::sleep(Duration::from_secs(config.scan_interval_minutes.max(1) * 60));
+    }
+}
+
+fn scan(conn: &mut PooledConn, config: &SuspicionConfig) -> Result<(), mysql::Error> {
+    let players: Vec<u64> = conn.exec(
+        "SELECT DISTINCT killer_id FROM PlayerKills WHERE killed_at >= NOW() - INTERVAL :window MINUTE",
+        params! { "window" => config.window_minutes },
+    )?;
+
+    for player_id in players {
+        let kills: Vec<Kill> = conn
+            .exec::<(u64, String, Option<f64>, i64), _, _>(
+                r"SELECT kill_id, weapon, distance, CAST(UNIX_TIMESTAMP(killed_at) * 1000 AS SIGNED)
+                FROM PlayerKills
+                WHERE killer_id = :pid AND killer_id <> victim_id
+                    AND killed_at >= NOW() - INTERVAL :window MINUTE
+                ORDER BY killed_at, kill_id",
+                params! { "pid" => player_id, "window" => config.window_minutes },
+            )?
+            .into_iter()
+            .map(|(kill_id, weapon, distance, at_ms)| Kill { kill_id, weapon, distance: distance.unwrap_or(0.0), at_ms })
+            .collect();
+
+        // Last connection before the player's first kill in the window (MAX is NULL if we never saw one).
+        let online_since: Option<i64> = conn.exec_first::<Option<i64>, _, _>(
+            r"SELECT CAST(UNIX_TIMESTAMP(MAX(connected_at)) * 1000 AS SIGNED) FROM ConnectionHistory
+            WHERE player_id = :pid AND connected_at <= (SELECT MIN(killed_at) FROM PlayerKills
+                WHERE killer_id = :pid AND killed_at >= NOW() - INTERVAL :window MINUTE)",
+            params! { "pid" => player_id, "window" => config.window_minutes },
+        )?
+        .flatten();
+
+        let incidents = [
+            rapid_kills(&kills, config),
+            long_range_kills(&kills, config),
+            kill_rate(&kills, online_since, config),
+        ];
+        for incident in incidents.into_iter().flatten() {
+            record_incident(conn, player_id, incident)?;
+        }
+    }
+    Ok(())
+}
+
+// Consecutive kills with the same weapon, each closer together than the weapon can fire.
+fn rapid_kills(kills: &[Kill], config: &SuspicionConfig) -> Option<Incident> {
+    let mut best: Option<(Vec<&Kill>, f64)> = None;
+    let mut streak: Vec<&Kill> = Vec::new();
+    let mut worst_ratio: f64 = 0.0;
+
+    for kill in kills {
+        let threshold = config.threshold(&kill.weapon);
+        let continues = match (streak.last(), threshold) {
+            (Some(prev), Some(t)) => {
+                let gap = (kill.at_ms - prev.at_ms).max(0) as f64;
+                let fast = prev.weapon == kill.weapon && gap < t.min_kill_interval_ms as f64;
+                if fast {
+                    worst_ratio = worst_ratio.max(t.min_kill_interval_ms as f64 / gap.max(1.0));
+                }
+                fast
+            }
+            _ => false,
+        };
+        if !continues {
+            if streak.len() >= config.rapid_kills && best.as_ref().is_none_or(|(b, _)| streak.len() > b.len()) {
+                best = Some((streak.clone(), worst_ratio));
+            }
+            streak.clear();
+            worst_ratio = 0.0;
+        }
+        if threshold.is_some() {
+            streak.push(kill);
+        }
+    }
+    if streak.len() >= config.rapid_kills && best.as_ref().is_none_or(|(b, _)| streak.len() > b.len()) {
+        best = Some((streak, worst_ratio));
+    }
+
+    let (streak, ratio) = best?;
+    Some(Incident {
+        kind: "RAPID_KILLS",
+        score: ratio,
+        details: format!(
+            "{} kills with {} in {} ms",
+            streak.len(),
+            streak[0].weapon,
+            streak[streak.len() - 1].at_ms - streak[0].at_ms
+        ),
+        evidence: streak.iter().map(|k| k.kill_id).collect(),
+    })
+}
+
+// Kills beyond the weapon's effective range.
+fn long_range_kills(kills: &[Kill], config: &SuspicionConfig) -> Option<Incident> {
+    let long: Vec<(&Kill, f64)> = kills
+        .iter()
+        .filter_map(|k| {
+            let t = config.threshold(&k.weapon)?;
+            (k.distance > t.effective_range).then(|| (k, k.distance / t.effective_range))
+        })
+        .collect();
+    if long.len() < config.long_range_kills.max(1) {
+        return None;
+    }
+
+    let (farthest, ratio) = long.iter().max_by(|a, b| a.1.total_cmp(&b.1))?;
+    Some(Incident {
+        kind: "LONG_RANGE",
+        score: *ratio,
+        details: format!(
+            "{} kills beyond effective range; longest {:.0} m with {}",
+            long.len(),
+            farthest.distance,
+            farthest.weapon
+        ),
+        evidence: long.iter().map(|(k, _)| k.kill_id).collect(),
+    })
+}
+
+// Kills per minute since the player connected (or since their first kill in the window when we missed the connect).
+fn kill_rate(kills: &[Kill], online_since: Option<i64>, config: &SuspicionConfig) -> Option<Incident> {
+    if kills.len() < config.min_kills_for_rate.max(1) {
+        return None;
+    }
+    let first = kills.first()?.at_ms;
+    let last = kills.last()?.at_ms;
+    let start = online_since.unwrap_or(first).min(first);
+    // At least five minutes, so a short burst right after connecting is not extrapolated.
+    let minutes = ((last - start) as f64 / 60_000.0).max(5.0);
+    let rate = kills.len() as f64 / minutes;
+    if rate <= config.max_kills_per_minute {
+        return None;
+    }
+
+    Some(Incident {
+        kind: "KILL_RATE",
+        score: rate / config.max_kills_per_minute,
+        details: format!("{} kills in {:.1} minutes online ({:.2}/min)", kills.len(), minutes, rate),
+        evidence: kills.iter().map(|k| k.kill_id).collect(),
+    })
+}
+
+// Kills already attached to an incident of the same kind are not reported again; the incident is
+// only written when something new remains.
+fn record_incident(conn: &mut PooledConn, player_id: u64, incident: Incident) -> Result<(), mysql::Error> {
+    let known: Vec<u64> = conn.exec(
+        r"SELECT e.kill_id FROM SuspicionEvidence e
+        JOIN SuspicionIncidents i ON i.incident_id = e.incident_id
+        WHERE i.player_id = :pid AND i.kind = :kind",
+        params! { "pid" => player_id, "kind" => incident.kind },
+    )?;
+    let new_evidence: Vec<u64> = incident.evidence.iter().copied().filter(|id| !known.contains(id)).collect();
+    if new_evidence.is_empty() {
+        return Ok(());
+    }
+
+    warn!(player_id, kind = incident.kind, score = incident.score; "Player {} flagged for {}: {}", player_id, incident.kind, incident.details);
+    conn.exec_drop(
+        r"INSERT INTO SuspicionIncidents (player_id, kind, score, details)
+        VALUES (:pid, :kind, :score, :details)",
+        params! {
+            "pid" => player_id,
+            "kind" => incident.kind,
+            "score" => incident.score,
+            "details" => &incident.details,
+        },
+    )?;
+    let incident_id = conn.last_insert_id();
+    conn.exec_batch(
+        "INSERT INTO SuspicionEvidence (incident_id, kill_id) VALUES (:iid, :kid)",
+        incident.evidence.iter().map(|kill_id| params! { "iid" => incident_id, "kid" => kill_id }),
+    )
+}
+
+// incident_id, player name, kind, score, details, status, created_at, evidence kill ids
+type IncidentRow = (u64, Option<String>, String, f64, String, String, String, Option<String>);
+
+pub fn print_incidents(conn: &mut PooledConn, status: &str, limit: u32) -> Result<(), mysql::Error> {
+    let rows: Vec<IncidentRow> = conn.exec(
+        r"SELECT i.incident_id,
+            (SELECT n.username FROM PlayerNames n WHERE n.player_id = i.player_id ORDER BY n.last_used DESC LIMIT 1),
+            i.kind, i.score, i.details, i.status, DATE_FORMAT(i.created_at, '%Y-%m-%d %H:%i:%s'),
+            (SELECT GROUP_CONCAT(e.kill_id ORDER BY e.kill_id) FROM SuspicionEvidence e WHERE e.incident_id = i.incident_id)
+        FROM SuspicionIncidents i
+        WHERE i.status = :status
+        ORDER BY i.score DESC, i.created_at DESC
+        LIMIT :limit",
+        params! { "status" => status, "limit" => limit },
+    )?;
+
+    println!("=== {} INCIDENTS ===", status);
+    if rows.is_empty() {
+        println!("None");
+    }
+    for (id, name, kind, score, details, _, created_at, evidence) in rows {
+        println!(
+            "#{} [{}] {} {} (score {:.2}): {}",
+            id,
+            created_at,
+            name.as_deref().unwrap_or("unknown"),
+            kind,
+            score,
+            details
+        );
+        println!("    kills: {}", evidence.as_deref().unwrap_or("-"));
+    }
+    Ok(())
+}
+
+pub fn review_incident(conn: &mut PooledConn, incident_id: u64, status: &str, reviewer: &str) -> Result<bool, mysql::Error> {
+    conn.exec_drop(
+        r"UPDATE SuspicionIncidents
+        SET status = :status, reviewed_by = :reviewer, reviewed_at = NOW()
+        WHERE incident_id = :iid",
+        params! { "status" => status, "reviewer" => reviewer, "iid" => incident_id },
+    )?;
+    Ok(conn.affected_rows() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kill(kill_id: u64, weapon: &str, distance: f64, at_ms: i64) -> Kill {
+        Kill { kill_id, weapon: weapon.to_string(), distance, at_ms }
+    }
+
+    #[test]
+    fn weapon_thresholds() {
+        let mut config = SuspicionConfig::default();
+        config.weapons.insert("M16A2 M203".to_string(), WeaponThreshold { exempt: true, ..WeaponThreshold::default() });
+
+        assert_eq!(config.threshold("Rifle_M16A2").map(|t| t.min_kill_interval_ms), Some(70));
+        // The longest matching key wins.
+        assert!(config.threshold("m16a2 m203").is_none());
+        assert_eq!(config.threshold("Unknown rifle").map(|t| t.effective_range), Some(600.0));
+        assert!(config.threshold("Grenade_RGD5").is_none());
+        assert!(config.threshold("").is_none());
+    }
+
+    #[test]
+    fn rapid_kills_need_a_streak_faster_than_the_weapon() {
+        let config = SuspicionConfig::default();
+        let fast = [kill(1, "M16A2", 50.0, 0), kill(2, "M16A2", 50.0, 30), kill(3, "M16A2", 50.0, 60)];
+        let incident = rapid_kills(&fast, &config).unwrap();
+        assert_eq!(incident.kind, "RAPID_KILLS");
+        assert_eq!(incident.evidence, vec![1, 2, 3]);
+        assert!((incident.score - 70.0 / 30.0).abs() < 1e-9);
+        assert_eq!(incident.details, "3 kills with M16A2 in 60 ms");
+
+        let slow = [kill(1, "M16A2", 50.0, 0), kill(2, "M16A2", 50.0, 100), kill(3, "M16A2", 50.0, 200)];
+        assert!(rapid_kills(&slow, &config).is_none());
+
+        // A different weapon starts a new streak.
+        let mixed = [kill(1, "M16A2", 50.0, 0), kill(2, "AK74", 50.0, 10), kill(3, "M16A2", 50.0, 20)];
+        assert!(rapid_kills(&mixed, &config).is_none());
+
+        // Explosives legitimately kill several players at once.
+        let grenade = [kill(1, "Grenade_M67", 5.0, 0), kill(2, "Grenade_M67", 5.0, 0), kill(3, "Grenade_M67", 5.0, 0)];
+        assert!(rapid_kills(&grenade, &config).is_none());
+    }
+
+    #[test]
+    fn rapid_kills_report_the_longest_streak() {
+        let config = SuspicionConfig::default();
+        let kills = [
+            kill(1, "AK74", 50.0, 0),
+            kill(2, "AK74", 50.0, 10),
+            kill(3, "AK74", 50.0, 20),
+            kill(4, "AK74", 50.0, 10_000),
+            kill(5, "AK74", 50.0, 10_010),
+            kill(6, "AK74", 50.0, 10_020),
+            kill(7, "AK74", 50.0, 10_030),
+        ];
+        assert_eq!(rapid_kills(&kills, &config).unwrap().evidence, vec![4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn long_range_kills_beyond_effective_range() {
+        let config = SuspicionConfig::default();
+        let kills = [
+            kill(1, "M16A2", 600.0, 0),
+            kill(2, "M16A2", 100.0, 1_000),
+            kill(3, "M16A2", 825.0, 2_000),
+            kill(4, "RPG7", 2_000.0, 3_000),
+            kill(5, "Unknown rifle", 650.0, 4_000),
+        ];
+        let incident = long_range_kills(&kills, &config).unwrap();
+        assert_eq!(incident.kind, "LONG_RANGE");
+        assert_eq!(incident.evidence, vec![1, 3, 5]);
+        assert!((incident.score - 1.5).abs() < 1e-9);
+        assert_eq!(incident.details, "3 kills beyond effective range; longest 825 m with M16A2");
+
+        assert!(long_range_kills(&kills[..3], &config).is_none());
+    }
+
+    #[test]
+    fn kill_rate_per_minute_online() {
+        let config = SuspicionConfig::default();
+        // 15 kills in a minute count as 15 in five minutes: 3 per minute.
+        let kills: Vec<Kill> = (0..15).map(|i| kill(i, "M16A2", 50.0, i as i64 * 4_000)).collect();
+        let incident = kill_rate(&kills, None, &config).unwrap();
+        assert_eq!(incident.kind, "KILL_RATE");
+        assert!((incident.score - 1.5).abs() < 1e-9);
+        assert_eq!(incident.evidence.len(), 15);
+
+        // Online for ten minutes before the first kill.
+        assert!(kill_rate(&kills, Some(-600_000), &config).is_none());
+        // Too few kills to judge.
+        assert!(kill_rate(&kills[..14], None, &config).is_none());
+    }
+}
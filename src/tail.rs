@@ -0,0 +1,474 @@
+use log::{info, warn};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+use crate::metrics;
+
+// Why a pass over console.log failed. None of these stop a tailer; it tries again on its next pass.
+#[derive(Debug)]
+pub enum TailError {
+    // Not created yet (before the server's first start) or in the middle of a rotation.
+    Missing,
+    Open(io::Error),
+    Seek(io::Error),
+    Read(io::Error),
+}
+
+impl fmt::Display for TailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TailError::Missing => write!(f, "file does not exist"),
+            TailError::Open(e) => write!(f, "failed to open: {}", e),
+            TailError::Seek(e) => write!(f, "failed to seek: {}", e),
+            TailError::Read(e) => write!(f, "failed to read: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TailError {}
+
+// How much of the first line identifies a log file.
+const IDENTITY_BYTES: u64 = 4096;
+
+// Console lines start with the time of day ("12:34:56.789  SCRIPT : ..."), sometimes preceded by a date.
+static LOG_TIME_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(?:(\d{4}-\d{2}-\d{2})[ T])?(\d{2}:\d{2}:\d{2}(?:\.\d{1,3})?)").unwrap());
+
+// When a line was logged, for the `:log_date` and `:log_time` parameters of LOGGED_AT_SQL.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LogTime {
+    pub date: Option<String>,
+    pub time: Option<String>,
+}
+
+// SQL for when a line was logged, so events read in one batch (or long after) keep their own times.
+// Lines without a date are placed on the most recent day that time occurred, lines without a time at now.
+pub const LOGGED_AT_SQL: &str =
+    "COALESCE(TIMESTAMP(COALESCE(:log_date, CURDATE() - INTERVAL (TIME(:log_time) > CURTIME(3)) DAY), :log_time), NOW(3))";
+
+pub fn line_time(line: &str) -> LogTime {
+    let caps = LOG_TIME_REGEX.captures(line);
+    LogTime {
+        date: caps.as_ref().and_then(|c| c.get(1)).map(|m| m.as_str().to_string()),
+        time: caps.as_ref().and_then(|c| c.get(2)).map(|m| m.as_str().to_string()),
+    }
+}
+
+impl LogTime {
+    // Time of day in milliseconds, if the line had one.
+    pub fn millis_of_day(&self) -> Option<u64> {
+        let time = self.time.as_deref()?;
+        let (hms, fraction) = time.split_once('.').unwrap_or((time, "0"));
+        let mut parts = hms.split(':').map(|p| p.parse::<u64>().ok());
+        let (h, m, s) = (parts.next()??, parts.next()??, parts.next()??);
+        // ".5" is half a second
+        let millis: u64 = format!("{:0<3}", fraction).parse().ok()?;
+        Some(((h * 60 + m) * 60 + s) * 1000 + millis)
+    }
+
+    // Days from 1970-01-01 to the line's date, if it had one.
+    pub fn epoch_day(&self) -> Option<u64> {
+        let mut parts = self.date.as_deref()?.split('-').map(|p| p.parse::<i64>().ok());
+        let (y, m, d) = (parts.next()??, parts.next()??, parts.next()??);
+        // Proleptic Gregorian calendar in 400-year eras, counting years from March so leap days come last.
+        let y = if m <= 2 { y - 1 } else { y };
+        let era = y.div_euclid(400);
+        let year_of_era = y - era * 400;
+        let day_of_year = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        u64::try_from(era * 146_097 + day_of_era - 719_468).ok()
+    }
+}
+
+// A complete line and the byte offset it starts at.
+pub struct LogLine {
+    pub offset: u64,
+    pub text: String,
+}
+
+// Up to `max_lines` complete lines from a reader positioned at `start`, and the offset after the last
+// one. A last line without its newline is still being written and is left for the next read. CRLF
+// endings are stripped, and bytes that are not UTF-8 are replaced rather than dropping the line.
+pub fn read_complete_lines(reader: &mut impl BufRead, start: u64, max_lines: usize) -> io::Result<(Vec<LogLine>, u64)> {
+    let mut lines = Vec::new();
+    let mut offset = start;
+    let mut buf = Vec::new();
+    while lines.len() < max_lines {
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf)?;
+        if read == 0 || buf.last() != Some(&b'\n') {
+            break;
+        }
+        let text = String::from_utf8_lossy(&buf).trim_end_matches(['\n', '\r']).to_string();
+        lines.push(LogLine { offset, text });
+        offset += read as u64;
+    }
+    Ok((lines, offset))
+}
+
+// The same event read twice (a replay, a rotation that was not one, a backfill) gets the same id:
+// a hash of the server, the log file, where the line starts in it and the line itself.
+pub fn event_id(server_id: &str, log_identity: &str, line: &LogLine) -> String {
+    let digest = Sha256::digest(format!("{}\n{}\n{}\n{}", server_id, log_identity, line.offset, line.text));
+    digest[..16].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Tells log files apart by their first line (the server's start-up banner with its timestamp), which
+// survives the file growing and changes when it is rotated or recreated. Empty while the first line
+// is still being written, since it would change once the line is complete.
+pub fn log_identity(file: &mut File) -> io::Result<String> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut head = Vec::new();
+    BufReader::new(file.by_ref().take(IDENTITY_BYTES)).read_until(b'\n', &mut head)?;
+    if head.is_empty() || (head.last() != Some(&b'\n') && (head.len() as u64) < IDENTITY_BYTES) {
+        return Ok(String::new());
+    }
+    Ok(Sha256::digest(&head)[..8].iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// A reader's position in a server's console.log, kept across rotation, truncation and the file
+// disappearing for a while.
+pub struct LogTail {
+    server_id: String,
+    reader: &'static str,
+    path: PathBuf,
+    position: u64,
+    // Hash of the log's first line, for event ids and to notice the log being replaced by one that
+    // has already grown past the position; see `log_identity`. Empty when not known.
+    identity: String,
+    // Whether the file existed at the last pass; one that comes back after going missing is a new log.
+    seen: bool,
+    // The problem last logged, so a log that is missing for an hour is reported once, not every pass.
+    problem: Option<String>,
+}
+
+impl LogTail {
+    // `identity` is that of the log `position` belongs to (from a checkpoint), or empty if not known.
+    pub fn new(server_id: &str, reader: &'static str, path: PathBuf, position: u64, identity: &str) -> Self {
+        Self {
+            server_id: server_id.to_string(),
+            reader,
+            path,
+            position,
+            identity: identity.to_string(),
+            seen: false,
+            problem: None,
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    // The deterministic id of a line returned by the last read.
+    pub fn event_id(&self, line: &LogLine) -> String {
+        event_id(&self.server_id, &self.identity, line)
+    }
+
+    // The complete lines written since the last read, moving the position past them. When reading
+    // fails nothing is consumed, so the next read starts at the same line.
+    pub fn read_lines(&mut self) -> Result<Vec<LogLine>, TailError> {
+        let file = self.open()?;
+        let (lines, end) =
+            read_complete_lines(&mut BufReader::new(file), self.position, usize::MAX).map_err(TailError::Read)?;
+        let bytes = end - self.position;
+        self.position = end;
+        metrics::record_tail(&self.server_id, self.reader, self.path.clone(), lines.len() as u64, bytes);
+        Ok(lines)
+    }
+
+    // Moves the position back to `offset`, the start of a line returned by the last read, so the next
+    // read returns that line again (after it could not be stored).
+    pub fn rewind(&mut self, offset: u64) {
+        self.position = self.position.min(offset);
+    }
+
+    // Opens the log at the current position. A log that is shorter than the position, whose first
+    // line changed, or that came back after going missing, is read from the start.
+    fn open(&mut self) -> Result<File, TailError> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if self.seen {
+                    self.position = 0;
+                    self.seen = false;
+                }
+                return Err(TailError::Missing);
+            }
+            Err(e) => return Err(TailError::Open(e)),
+        };
+        self.seen = true;
+
+        let len = file.metadata().map_err(TailError::Open)?.len();
+        if len < self.position {
+            info!(
+                server = self.server_id, reader = self.reader;
+                "Log file {} was truncated or rotated (len {} < position {}); reading it from the start",
+                self.path.display(), len, self.position
+            );
+            self.position = 0;
+        }
+        let identity = log_identity(&mut file).map_err(TailError::Read)?;
+        // A position past the start needs a complete first line, so an empty identity means a new log too.
+        let replaced = identity.is_empty() || (!self.identity.is_empty() && identity != self.identity);
+        if self.position > 0 && replaced {
+            info!(
+                server = self.server_id, reader = self.reader;
+                "Log file {} was replaced (its first line changed); reading it from the start",
+                self.path.display()
+            );
+            self.position = 0;
+        }
+        self.identity = identity;
+        file.seek(SeekFrom::Start(self.position)).map_err(TailError::Seek)?;
+
+        if self.problem.take().is_some() {
+            info!(server = self.server_id, reader = self.reader; "Reading {} again at byte {}", self.path.display(), self.position);
+        }
+        Ok(file)
+    }
+
+    // Logs a failed pass, unless it is the same problem as the last one.
+    pub fn report(&mut self, error: &TailError) {
+        let problem = error.to_string();
+        if self.problem.as_ref() == Some(&problem) {
+            return;
+        }
+        match error {
+            TailError::Missing => info!(server = self.server_id, reader = self.reader; "Waiting for {} to appear", self.path.display()),
+            _ => warn!(server = self.server_id, reader = self.reader; "Log file {}: {}; retrying", self.path.display(), error),
+        }
+        self.problem = Some(problem);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read_all(data: &[u8], start: u64) -> (Vec<(u64, String)>, u64) {
+        let mut reader = Cursor::new(&data[start as usize..]);
+        let (lines, end) = read_complete_lines(&mut reader, start, usize::MAX).unwrap();
+        (lines.into_iter().map(|l| (l.offset, l.text)).collect(), end)
+    }
+
+    #[test]
+    fn trailing_partial_line_is_not_consumed() {
+        let (lines, end) = read_all(b"first\nsecond\nthi", 0);
+        assert_eq!(lines, vec![(0, "first".to_string()), (6, "second".to_string())]);
+        assert_eq!(end, 13);
+
+        // Once the rest of the line is written it is read from where the last read stopped.
+        let (lines, end) = read_all(b"first\nsecond\nthird\n", end);
+        assert_eq!(lines, vec![(13, "third".to_string())]);
+        assert_eq!(end, 19);
+    }
+
+    #[test]
+    fn crlf_is_trimmed_and_counted_in_offsets() {
+        let (lines, end) = read_all(b"one\r\ntwo\r\n", 0);
+        assert_eq!(lines, vec![(0, "one".to_string()), (5, "two".to_string())]);
+        assert_eq!(end, 10);
+    }
+
+    #[test]
+    fn invalid_utf8_does_not_shift_offsets() {
+        let (lines, end) = read_all(b"ok\nbad \xff\xfe byte\nnext\n", 0);
+        assert_eq!(
+            lines,
+            vec![(0, "ok".to_string()), (3, "bad \u{fffd}\u{fffd} byte".to_string()), (15, "next".to_string())]
+        );
+        assert_eq!(end, 20);
+    }
+
+    #[test]
+    fn max_lines_stops_at_a_line_boundary() {
+        let mut reader = Cursor::new(&b"a\nb\nc\n"[..]);
+        let (lines, end) = read_complete_lines(&mut reader, 0, 2).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(end, 4);
+    }
+
+    #[test]
+    fn event_id_is_stable_across_a_reread() {
+        let data = b"banner\n12:00:00.000 PLAYER_KILLED: killerName='A', victimName='B'\n";
+        let (first, _) = read_all(data, 0);
+        let (again, _) = read_all(data, 7);
+        let id = |(offset, text): &(u64, String)| event_id("default", "abc", &LogLine { offset: *offset, text: text.clone() });
+
+        assert_eq!(id(&first[1]), id(&again[0]));
+        assert_eq!(id(&first[1]).len(), 32);
+        // The same text elsewhere in the log, in another log or on another server is another event.
+        let line = LogLine { offset: 7, text: first[1].1.clone() };
+        assert_ne!(event_id("default", "abc", &line), event_id("default", "abc", &LogLine { offset: 8, ..line }));
+        let line = LogLine { offset: 7, text: first[1].1.clone() };
+        assert_ne!(event_id("default", "abc", &line), event_id("default", "def", &line));
+        assert_ne!(event_id("default", "abc", &line), event_id("other", "abc", &line));
+    }
+
+    #[test]
+    fn line_time_reads_the_date_and_time_of_day() {
+        let logged = line_time("2026-10-19 12:34:56.789  SCRIPT : PLAYER_KILLED: ...");
+        assert_eq!(logged.date.as_deref(), Some("2026-10-19"));
+        assert_eq!(logged.time.as_deref(), Some("12:34:56.789"));
+
+        let logged = line_time(" 12:34:56  BattlEye Server: ...");
+        assert_eq!((logged.date, logged.time.as_deref()), (None, Some("12:34:56")));
+
+        assert_eq!(line_time("no time here"), LogTime::default());
+    }
+
+    #[test]
+    fn log_time_as_numbers() {
+        let logged = line_time("2026-10-19 12:34:56.789  SCRIPT : PLAYER_KILLED: ...");
+        assert_eq!(logged.epoch_day(), Some(20_745));
+        assert_eq!(logged.millis_of_day(), Some(45_296_789));
+
+        let logged = line_time(" 00:00:01.5  BattlEye Server: ...");
+        assert_eq!((logged.epoch_day(), logged.millis_of_day()), (None, Some(1_500)));
+
+        assert_eq!(line_time("1970-01-01 00:00:00").epoch_day(), Some(0));
+        assert_eq!(line_time("2000-02-29 00:00:00").epoch_day(), Some(11_016));
+        assert_eq!(LogTime::default().millis_of_day(), None);
+    }
+
+    // A console.log in its own temporary directory, removed again when the test ends.
+    struct TempLog {
+        dir: PathBuf,
+    }
+
+    impl TempLog {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("df_backend_tail_{}_{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self { dir }
+        }
+
+        fn path(&self) -> PathBuf {
+            self.dir.join("console.log")
+        }
+
+        fn write(&self, content: &str) {
+            std::fs::write(self.path(), content).unwrap();
+        }
+
+        fn append(&self, content: &str) {
+            use std::io::Write;
+            std::fs::OpenOptions::new().append(true).open(self.path()).unwrap().write_all(content.as_bytes()).unwrap();
+        }
+
+        fn tail(&self, position: u64, identity: &str) -> LogTail {
+            LogTail::new("test", "test", self.path(), position, identity)
+        }
+
+        fn identity(&self) -> String {
+            log_identity(&mut File::open(self.path()).unwrap()).unwrap()
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn texts(lines: Result<Vec<LogLine>, TailError>) -> Vec<String> {
+        lines.unwrap().into_iter().map(|l| l.text).collect()
+    }
+
+    #[test]
+    fn follows_appended_lines() {
+        let log = TempLog::new("append");
+        log.write("banner\nfirst\n");
+        let mut tail = log.tail(0, "");
+        assert_eq!(texts(tail.read_lines()), vec!["banner", "first"]);
+        log.append("second\nthi");
+        assert_eq!(texts(tail.read_lines()), vec!["second"]);
+        log.append("rd\n");
+        assert_eq!(texts(tail.read_lines()), vec!["third"]);
+        assert_eq!(tail.position(), 26);
+        assert_eq!(tail.identity(), log.identity());
+    }
+
+    #[test]
+    fn truncated_log_is_read_from_the_start() {
+        let log = TempLog::new("truncate");
+        log.write("banner\nfirst\nsecond\n");
+        let mut tail = log.tail(0, "");
+        tail.read_lines().unwrap();
+        // Same first line, but shorter than the position.
+        log.write("banner\nnew\n");
+        assert_eq!(texts(tail.read_lines()), vec!["banner", "new"]);
+    }
+
+    #[test]
+    fn replaced_log_is_read_from_the_start() {
+        let log = TempLog::new("replace");
+        log.write("banner 1\nfirst\n");
+        let mut tail = log.tail(0, "");
+        tail.read_lines().unwrap();
+        // Already longer than the position when it is next read.
+        log.write("banner 2\nfirst line of the new log\n");
+        assert_eq!(texts(tail.read_lines()), vec!["banner 2", "first line of the new log"]);
+        assert_eq!(tail.identity(), log.identity());
+    }
+
+    #[test]
+    fn missing_log_is_waited_for() {
+        let log = TempLog::new("missing");
+        let mut tail = log.tail(0, "");
+        assert!(matches!(tail.read_lines(), Err(TailError::Missing)));
+        assert!(matches!(tail.read_lines(), Err(TailError::Missing)));
+        log.write("banner\nfirst\n");
+        assert_eq!(texts(tail.read_lines()), vec!["banner", "first"]);
+
+        // A log that comes back after going away is a new one, even if it looks the same.
+        std::fs::remove_file(log.path()).unwrap();
+        assert!(matches!(tail.read_lines(), Err(TailError::Missing)));
+        log.write("banner\nfirst\nsecond\n");
+        assert_eq!(texts(tail.read_lines()), vec!["banner", "first", "second"]);
+    }
+
+    #[test]
+    fn resumes_from_a_checkpoint_of_the_same_log() {
+        let log = TempLog::new("resume");
+        log.write("banner\nfirst\nsecond\n");
+        assert_eq!(texts(log.tail(13, &log.identity()).read_lines()), vec!["second"]);
+        // Checkpoints from before identities were recorded are trusted.
+        assert_eq!(texts(log.tail(13, "").read_lines()), vec!["second"]);
+        // A checkpoint of another log is not.
+        assert_eq!(texts(log.tail(13, "0123456789abcdef").read_lines()), vec!["banner", "first", "second"]);
+    }
+
+    #[test]
+    fn checkpoint_past_an_incomplete_first_line_starts_over() {
+        let log = TempLog::new("incomplete");
+        log.write("new banner still being writ");
+        let mut tail = log.tail(13, "0123456789abcdef");
+        assert!(texts(tail.read_lines()).is_empty());
+        assert_eq!(tail.position(), 0);
+        log.append("ten\n");
+        assert_eq!(texts(tail.read_lines()), vec!["new banner still being written"]);
+    }
+
+    #[test]
+    fn rewind_reads_lines_again() {
+        let log = TempLog::new("rewind");
+        log.write("banner\nfirst\nsecond\n");
+        let mut tail = log.tail(0, "");
+        let lines = tail.read_lines().unwrap();
+        tail.rewind(lines[1].offset);
+        assert_eq!(texts(tail.read_lines()), vec!["first", "second"]);
+    }
+}
@@ -0,0 +1,439 @@
+use log::{error, info, warn};
+use mysql::{params, prelude::*, Pool, PooledConn};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use crate::event_bus::{BusEvent, EventBus};
+use crate::kill_watcher::KillEvent;
+use crate::rcon::PlayerAction;
+use crate::tail::LogTime;
+
+// Ordered by severity; when several rules trip on the same team kill only the most severe one acts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TeamKillAction {
+    Warning,
+    Kick,
+    TempBan,
+}
+
+impl TeamKillAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TeamKillAction::Warning => "TK_WARNING",
+            TeamKillAction::Kick => "TK_KICK",
+            TeamKillAction::TempBan => "TK_BAN",
+        }
+    }
+}
+
+// A rule trips when a player has `team_kills` team kills within `window_minutes`, counting only
+// team kills made within `after_spawn_seconds` of spawning when that is set. Team kills that
+// already tripped a rule do not count towards it again.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TeamKillRule {
+    pub name: String,
+    #[serde(default = "default_team_kills")]
+    pub team_kills: usize,
+    #[serde(default = "default_window_minutes")]
+    pub window_minutes: u64,
+    pub after_spawn_seconds: Option<u64>,
+    pub action: TeamKillAction,
+    #[serde(default = "default_ban_minutes")]
+    pub ban_minutes: u32,
+}
+
+fn default_team_kills() -> usize {
+    1
+}
+
+fn default_window_minutes() -> u64 {
+    10
+}
+
+fn default_ban_minutes() -> u32 {
+    60
+}
+
+fn default_rules() -> Vec<TeamKillRule> {
+    let rule = |name: &str, team_kills, window_minutes, after_spawn_seconds, action| TeamKillRule {
+        name: name.to_string(),
+        team_kills,
+        window_minutes,
+        after_spawn_seconds,
+        action,
+        ban_minutes: default_ban_minutes(),
+    };
+    vec![
+        rule("spawn-team-kill", 1, 10, Some(30), TeamKillAction::Warning),
+        rule("repeat-team-kills", 2, 10, None, TeamKillAction::Warning),
+        rule("team-kill-kick", 3, 10, None, TeamKillAction::Kick),
+        rule("team-kill-ban", 5, 30, None, TeamKillAction::TempBan),
+    ]
+}
+
+// TEAMKILL_RULES_PATH (default `teamkill_rules.json`) replaces the built-in rules; `[]` turns detection off.
+pub fn load_rules() -> Vec<TeamKillRule> {
+    let explicit_path = env::var("TEAMKILL_RULES_PATH").ok();
+    let path = explicit_path.clone().unwrap_or_else(|| "teamkill_rules.json".to_string());
+    match fs::read_to_string(&path) {
+        Ok(content) => match serde_json::from_str::<Vec<TeamKillRule>>(&content) {
+            Ok(rules) => {
+                info!("Loaded {} team kill rule(s) from {}", rules.len(), path);
+                return rules;
+            }
+            Err(e) => warn!("Ignoring team kill rules {}: {}", path, e),
+        },
+        // The file is optional unless it was configured explicitly.
+        Err(e) if explicit_path.is_some() => warn!("Failed to read team kill rules {}: {}", path, e),
+        Err(_) => {}
+    }
+    default_rules()
+}
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+// One server's log times on a single timeline, so team kills are timed by when they were logged rather
+// than when they were read (a backlog read in one pass keeps its spacing). A time without a date is taken
+// on the day of the server's previous event, or the day after when it would fall more than 12 hours
+// before it (the log crossed midnight). A line without a time counts as the previous event's time.
+#[derive(Default)]
+struct LogClock {
+    last: Option<u64>,
+}
+
+impl LogClock {
+    fn at(&mut self, logged: &LogTime) -> Duration {
+        let millis = match (logged.epoch_day(), logged.millis_of_day()) {
+            (Some(day), Some(millis)) => day * DAY_MILLIS + millis,
+            (None, Some(millis)) => {
+                let last = self.last.unwrap_or(0);
+                let at = last - last % DAY_MILLIS + millis;
+                if at + DAY_MILLIS / 2 < last { at + DAY_MILLIS } else { at }
+            }
+            _ => self.last.unwrap_or(0),
+        };
+        self.last = Some(millis);
+        Duration::from_millis(millis)
+    }
+}
+
+struct TeamKill {
+    at: Duration,
+    // Counts the player's team kills, to tell apart team kills logged in the same millisecond.
+    seq: u64,
+    kill_id: Option<u64>,
+    // Time since the killer last spawned (connected or died), if we saw it.
+    since_spawn: Option<Duration>,
+}
+
+#[derive(Default)]
+struct PlayerRecord {
+    team_kills: VecDeque<TeamKill>,
+    recorded: u64,
+    // The last team kill (by seq) each rule tripped on for this player.
+    tripped: HashMap<String, u64>,
+}
+
+struct Decision<'a> {
+    rule: &'a TeamKillRule,
+    evidence: Vec<u64>,
+    count: usize,
+}
+
+// Players are tracked per server (server id, name): names are only unique on one server at a time.
+struct TeamKillMonitor {
+    rules: Vec<TeamKillRule>,
+    clocks: HashMap<String, LogClock>,
+    spawns: HashMap<(String, String), Duration>,
+    players: HashMap<(String, String), PlayerRecord>,
+}
+
+impl TeamKillMonitor {
+    fn new(rules: Vec<TeamKillRule>) -> Self {
+        Self { rules, clocks: HashMap::new(), spawns: HashMap::new(), players: HashMap::new() }
+    }
+
+    fn longest_window(&self) -> Duration {
+        let minutes = self.rules.iter().map(|r| r.window_minutes).max().unwrap_or(0);
+        Duration::from_secs(minutes * 60)
+    }
+
+    fn log_time(&mut self, server_id: &str, logged: &LogTime) -> Duration {
+        self.clocks.entry(server_id.to_string()).or_default().at(logged)
+    }
+
+    // Spawns are approximated by connections and deaths; console.log has no spawn line.
+    fn record_spawn(&mut self, server_id: &str, name: &str, logged: &LogTime) {
+        let at = self.log_time(server_id, logged);
+        self.spawns.insert((server_id.to_string(), name.to_string()), at);
+    }
+
+    fn record_team_kill(
+        &mut self,
+        server_id: &str,
+        killer: &str,
+        kill_id: Option<u64>,
+        logged: &LogTime,
+    ) -> Option<Decision<'_>> {
+        let now = self.log_time(server_id, logged);
+        let longest_window = self.longest_window();
+        let key = (server_id.to_string(), killer.to_string());
+        let since_spawn = self.spawns.get(&key).map(|at| now.saturating_sub(*at));
+        let record = self.players.entry(key).or_default();
+        record.recorded += 1;
+        record.team_kills.push_back(TeamKill { at: now, seq: record.recorded, kill_id, since_spawn });
+        while record.team_kills.front().is_some_and(|tk| now.saturating_sub(tk.at) > longest_window) {
+            record.team_kills.pop_front();
+        }
+
+        let mut decision: Option<Decision> = None;
+        for rule in &self.rules {
+            let window = Duration::from_secs(rule.window_minutes * 60);
+            let tripped_on = record.tripped.get(&rule.name).copied();
+            let evidence: Vec<&TeamKill> = record
+                .team_kills
+                .iter()
+                .filter(|tk| now.saturating_sub(tk.at) <= window)
+                .filter(|tk| tripped_on.is_none_or(|seq| tk.seq > seq))
+                .filter(|tk| {
+                    rule.after_spawn_seconds
+                        .is_none_or(|s| tk.since_spawn.is_some_and(|d| d <= Duration::from_secs(s)))
+                })
+                .collect();
+            if evidence.len() < rule.team_kills.max(1) {
+                continue;
+            }
+
+            record.tripped.insert(rule.name.clone(), record.recorded);
+            if decision.as_ref().is_none_or(|d| rule.action > d.rule.action) {
+                decision = Some(Decision {
+                    rule,
+                    evidence: evidence.iter().filter_map(|tk| tk.kill_id).collect(),
+                    count: evidence.len(),
+                });
+            }
+        }
+        decision
+    }
+}
+
+fn describe(rule: &TeamKillRule, count: usize) -> String {
+    match rule.after_spawn_seconds {
+        Some(seconds) => format!("Team killing: {} team kill(s) within {}s of spawning", count, seconds),
+        None => format!("Team killing: {} team kill(s) within {} minutes", count, rule.window_minutes),
+    }
+}
+
+// Watches kills on the event bus. With `enforce` and an RCon connection to the server the kill happened on
+// (`rcon` is keyed by server id) the action is carried out; otherwise it is only recorded as a
+// recommendation for the admins.
+pub fn run_teamkill_monitor(
+    rules: Vec<TeamKillRule>,
+    enforce: bool,
+    pool: Option<Pool>,
+    bus: EventBus,
+    rcon: HashMap<String, Sender<PlayerAction>>,
+) {
+    let events = bus.subscribe("teamkill");
+    let mut monitor = TeamKillMonitor::new(rules);
+    info!(
+        "Team kill detection enabled ({})",
+        if enforce && !rcon.is_empty() { "enforcing" } else { "recommendations only" }
+    );
+
+    for event in events {
+        match event {
+            BusEvent::PlayerConnected(p) => monitor.record_spawn(&p.server_id, &p.username, &p.logged),
+            BusEvent::Kill { kill, kill_id, .. } => {
+                monitor.record_spawn(&kill.server_id, &kill.victim_name, &kill.logged);
+                if !kill.is_team_kill || kill.killer_name == kill.victim_name {
+                    continue;
+                }
+
+                let Some(decision) = monitor.record_team_kill(&kill.server_id, &kill.killer_name, kill_id, &kill.logged) else {
+                    continue;
+                };
+                let rule = decision.rule;
+                let reason = describe(rule, decision.count);
+                let server_rcon = rcon.get(&kill.server_id).filter(|_| enforce);
+                let enforcing = server_rcon.is_some();
+                warn!(
+                    server = kill.server_id, player = kill.killer_name, rule = rule.name, action = rule.action.as_str(), enforced = enforcing;
+                    "Team kill: {} tripped rule '{}': {} -> {}{}",
+                    kill.killer_name,
+                    rule.name,
+                    reason,
+                    rule.action.as_str(),
+                    if enforcing { "" } else { " (recommended)" }
+                );
+
+                if let Some(rcon) = server_rcon {
+                    let name = kill.killer_name.clone();
+                    let action = match rule.action {
+                        TeamKillAction::Warning => PlayerAction::Warn { name, message: format!("Warning: {}", reason) },
+                        TeamKillAction::Kick => PlayerAction::Kick { name, reason: reason.clone() },
+                        TeamKillAction::TempBan => {
+                            PlayerAction::Ban { name, minutes: rule.ban_minutes, reason: reason.clone() }
+                        }
+                    };
+                    if rcon.send(action).is_err() {
+                        error!(server = kill.server_id; "RCon client is not running; team kill action not sent");
+                    }
+                }
+
+                if let Some(pool) = &pool {
+                    let recorded = pool.get_conn().and_then(|mut conn| {
+                        record_action(&mut conn, &kill, rule, &reason, &decision.evidence, enforcing)
+                    });
+                    if let Err(e) = recorded {
+                        error!("Failed to record team kill action: {}", e);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn record_action(
+    conn: &mut PooledConn,
+    kill: &KillEvent,
+    rule: &TeamKillRule,
+    reason: &str,
+    evidence: &[u64],
+    enforced: bool,
+) -> Result<(), mysql::Error> {
+    let player: Option<(u64, Option<String>)> = conn.exec_first(
+        r"SELECT p.player_id, p.battleye_guid FROM PlayerNames n
+        JOIN Players p ON p.player_id = n.player_id
+        WHERE n.username = :u ORDER BY n.last_used DESC LIMIT 1",
+        params! { "u" => &kill.killer_name },
+    )?;
+    let (player_id, guid) = player.unzip();
+
+    conn.exec_drop(
+        r"INSERT INTO ModerationActions
+        (player_id, battleye_guid, username, server_id, action_type, reason, duration_minutes, issued_by, status)
+        VALUES (:pid, :guid, :uname, :server, :kind, :reason, :duration, :issued_by, :status)",
+        params! {
+            "pid" => player_id,
+            "guid" => guid.flatten(),
+            "uname" => &kill.killer_name,
+            "server" => &kill.server_id,
+            "kind" => rule.action.as_str(),
+            "reason" => reason,
+            "duration" => (rule.action == TeamKillAction::TempBan).then_some(rule.ban_minutes),
+            "issued_by" => format!("DF_Backend rule '{}'", rule.name),
+            "status" => if enforced { "ENFORCED" } else { "RECOMMENDED" },
+        },
+    )?;
+    let action_id = conn.last_insert_id();
+
+    conn.exec_batch(
+        "INSERT INTO ModerationEvidence (action_id, kill_id) VALUES (:aid, :kid)",
+        evidence.iter().map(|kill_id| params! { "aid" => action_id, "kid" => kill_id }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logged(time: &str) -> LogTime {
+        LogTime { date: None, time: Some(time.to_string()) }
+    }
+
+    fn action_at(
+        monitor: &mut TeamKillMonitor,
+        server: &str,
+        killer: &str,
+        kill_id: u64,
+        time: &str,
+    ) -> Option<(TeamKillAction, Vec<u64>, usize)> {
+        monitor.record_team_kill(server, killer, Some(kill_id), &logged(time)).map(|d| (d.rule.action, d.evidence, d.count))
+    }
+
+    fn action(monitor: &mut TeamKillMonitor, killer: &str, kill_id: u64) -> Option<(TeamKillAction, Vec<u64>, usize)> {
+        action_at(monitor, "main", killer, kill_id, "12:00:00.000")
+    }
+
+    #[test]
+    fn default_rules_escalate() {
+        let mut monitor = TeamKillMonitor::new(default_rules());
+        // No spawn seen, so the spawn rule cannot trip and one team kill is not enough for the others.
+        assert_eq!(action(&mut monitor, "Alpha", 1), None);
+        assert_eq!(action(&mut monitor, "Alpha", 2), Some((TeamKillAction::Warning, vec![1, 2], 2)));
+        assert_eq!(action(&mut monitor, "Alpha", 3), Some((TeamKillAction::Kick, vec![1, 2, 3], 3)));
+        // Team kills that tripped a rule do not count towards it again.
+        assert_eq!(action(&mut monitor, "Alpha", 4), Some((TeamKillAction::Warning, vec![3, 4], 2)));
+        assert_eq!(action(&mut monitor, "Alpha", 5), Some((TeamKillAction::TempBan, vec![1, 2, 3, 4, 5], 5)));
+    }
+
+    #[test]
+    fn players_are_counted_separately() {
+        let mut monitor = TeamKillMonitor::new(default_rules());
+        assert_eq!(action(&mut monitor, "Alpha", 1), None);
+        assert_eq!(action(&mut monitor, "Bravo", 2), None);
+        assert_eq!(action(&mut monitor, "Alpha", 3), Some((TeamKillAction::Warning, vec![1, 3], 2)));
+    }
+
+    #[test]
+    fn servers_are_counted_separately() {
+        let mut monitor = TeamKillMonitor::new(default_rules());
+        assert_eq!(action_at(&mut monitor, "eu", "Alpha", 1, "12:00:00"), None);
+        assert_eq!(action_at(&mut monitor, "us", "Alpha", 2, "12:00:01"), None);
+        assert_eq!(action_at(&mut monitor, "eu", "Alpha", 3, "12:00:02"), Some((TeamKillAction::Warning, vec![1, 3], 2)));
+    }
+
+    #[test]
+    fn team_kills_are_timed_by_the_log() {
+        let mut monitor = TeamKillMonitor::new(default_rules());
+        // Read in one go (e.g. catching up on a backlog), but logged hours apart.
+        for (kill_id, time) in [(1, "08:00:00"), (2, "10:30:00"), (3, "13:00:00"), (4, "15:30:00"), (5, "18:00:00")] {
+            assert_eq!(action_at(&mut monitor, "main", "Alpha", kill_id, time), None);
+        }
+        // Across midnight the window still holds.
+        assert_eq!(action_at(&mut monitor, "main", "Alpha", 6, "23:58:00"), None);
+        assert_eq!(action_at(&mut monitor, "main", "Alpha", 7, "00:01:00"), Some((TeamKillAction::Warning, vec![6, 7], 2)));
+    }
+
+    #[test]
+    fn team_kill_right_after_spawning() {
+        let mut monitor = TeamKillMonitor::new(default_rules());
+        monitor.record_spawn("main", "Alpha", &logged("12:00:00"));
+        let decision = monitor.record_team_kill("main", "Alpha", None, &logged("12:00:20")).unwrap();
+        assert_eq!(decision.rule.name, "spawn-team-kill");
+        assert_eq!((decision.evidence, decision.count), (Vec::new(), 1));
+        assert_eq!(describe(decision.rule, 1), "Team killing: 1 team kill(s) within 30s of spawning");
+
+        // Logged a minute after the next spawn, however soon it is read.
+        monitor.record_spawn("main", "Alpha", &logged("12:05:00"));
+        assert!(monitor.record_team_kill("main", "Alpha", None, &logged("12:06:00")).is_none_or(|d| d.rule.name != "spawn-team-kill"));
+    }
+
+    #[test]
+    fn the_most_severe_rule_acts() {
+        let rule = |name: &str, action| TeamKillRule {
+            name: name.to_string(),
+            team_kills: 1,
+            window_minutes: 10,
+            after_spawn_seconds: None,
+            action,
+            ban_minutes: 60,
+        };
+        let mut monitor = TeamKillMonitor::new(vec![
+            rule("ban", TeamKillAction::TempBan),
+            rule("warn", TeamKillAction::Warning),
+            rule("kick", TeamKillAction::Kick),
+        ]);
+        assert_eq!(action(&mut monitor, "Alpha", 1).unwrap().0, TeamKillAction::TempBan);
+        // Every rule that tripped is spent, not only the one that acted.
+        assert_eq!(monitor.players[&("main".to_string(), "Alpha".to_string())].tripped.len(), 3);
+    }
+}
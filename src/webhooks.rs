@@ -0,0 +1,326 @@
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::Sha256;
+use std::env;
+use std::fs;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::event_bus::{BusEvent, EventBus, EVENT_TYPES};
+
+// Deliveries waiting per rule; further events for a rule that is this far behind are dropped.
+const QUEUE_SIZE: usize = 100;
+const MAX_BACKOFF_SECONDS: u64 = 300;
+
+// One entry of webhooks.json. Filters only apply to events that carry the field they test
+// (e.g. `min_distance` to kills), so a rule can combine kills with other event types.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookRule {
+    pub name: String,
+    pub url: String,
+    // HMAC-SHA256 key; requests are signed when set.
+    pub secret: Option<String>,
+    pub events: Vec<String>,
+    pub min_distance: Option<f64>,
+    pub team_kill: Option<bool>,
+    pub weapon: Option<String>,
+    #[serde(default)]
+    pub players: Vec<String>,
+    // Server ids; every server when empty.
+    #[serde(default)]
+    pub servers: Vec<String>,
+    #[serde(default)]
+    pub milestones: Vec<u32>,
+    // JSON body with {placeholders}; the event's own JSON is sent when omitted.
+    pub payload: Option<Value>,
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+impl WebhookRule {
+    fn matches(&self, event: &BusEvent) -> bool {
+        if !self.events.iter().any(|e| e == event.event_type()) {
+            return false;
+        }
+        if !self.players.is_empty() && !self.players.iter().any(|p| event.involves(p)) {
+            return false;
+        }
+        if !self.servers.is_empty() && !self.servers.iter().any(|s| s == event.server_id()) {
+            return false;
+        }
+
+        match event {
+            BusEvent::Kill { kill, .. } => {
+                self.min_distance.is_none_or(|min| kill.distance.is_some_and(|d| d >= min))
+                    && self.team_kill.is_none_or(|tk| kill.is_team_kill == tk)
+                    && self.weapon.as_ref().is_none_or(|w| {
+                        kill.weapon.as_ref().is_some_and(|kw| kw.eq_ignore_ascii_case(w))
+                    })
+            }
+            BusEvent::Milestone { kills, .. } => self.milestones.is_empty() || self.milestones.contains(kills),
+            _ => true,
+        }
+    }
+}
+
+// Rules come from WEBHOOKS_PATH (default `webhooks.json`). The file is optional unless configured
+// explicitly; a file that cannot be used stops startup rather than silently disabling its webhooks.
+pub fn load_rules() -> Result<Vec<WebhookRule>, String> {
+    let explicit_path = env::var("WEBHOOKS_PATH").ok();
+    let path = explicit_path.clone().unwrap_or_else(|| "webhooks.json".to_string());
+    let content = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) if explicit_path.is_some() => return Err(format!("Failed to read webhook rules {}: {}", path, e)),
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let rules: Vec<WebhookRule> =
+        serde_json::from_str(&content).map_err(|e| format!("Invalid webhook rules {}: {}", path, e))?;
+    for rule in &rules {
+        if let Some(unknown) = rule.events.iter().find(|e| !EVENT_TYPES.contains(&e.as_str())) {
+            return Err(format!("Invalid webhook rules {}: rule '{}' has unknown event '{}'", path, rule.name, unknown));
+        }
+    }
+    info!("Loaded {} webhook rule(s) from {}", rules.len(), path);
+    Ok(rules)
+}
+
+struct Delivery {
+    event_type: &'static str,
+    body: String,
+}
+
+pub fn run_webhook_dispatcher(rules: Vec<WebhookRule>, bus: EventBus) {
+    let events = bus.subscribe("webhooks");
+
+    // One sender thread per rule so a slow or failing endpoint only delays its own deliveries.
+    let queues: Vec<(WebhookRule, SyncSender<Delivery>)> = rules
+        .into_iter()
+        .map(|rule| {
+            let (tx, rx) = mpsc::sync_channel(QUEUE_SIZE);
+            let name = rule.name.clone();
+            let url = rule.url.clone();
+            let secret = rule.secret.clone();
+            let max_attempts = rule.max_attempts.max(1);
+            thread::spawn(move || deliver_loop(&name, &url, secret.as_deref(), max_attempts, rx));
+            (rule, tx)
+        })
+        .collect();
+
+    for event in events {
+        for (rule, queue) in &queues {
+            if !rule.matches(&event) {
+                continue;
+            }
+            let body = render_payload(rule.payload.as_ref(), &event_context(&event));
+            let delivery = Delivery { event_type: event.event_type(), body: body.to_string() };
+            match queue.try_send(delivery) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => warn!(webhook = rule.name; "Webhook '{}' is backed up; dropping event", rule.name),
+                Err(TrySendError::Disconnected(_)) => error!(webhook = rule.name; "Webhook '{}' sender stopped", rule.name),
+            }
+        }
+    }
+}
+
+// Fields available to payload templates: the public event JSON, plus the IP address for
+// connection events (webhooks are configured by the server owner, unlike the public streams).
+fn event_context(event: &BusEvent) -> Map<String, Value> {
+    let mut context = match event.to_json() {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    };
+    if let BusEvent::PlayerConnected(p) | BusEvent::NewPlayer(p) | BusEvent::NewIp(p) = event {
+        context.insert("ip".to_string(), Value::String(p.ip_address.clone()));
+    }
+    context
+}
+
+// A string that is exactly "{field}" is replaced by the field's JSON value (so numbers stay numbers);
+// placeholders inside longer strings are replaced by the value's text. Unknown placeholders are left as-is.
+// Each string is scanned once, so a substituted value (e.g. a player named "{ip}") is never expanded itself.
+fn render_payload(template: Option<&Value>, context: &Map<String, Value>) -> Value {
+    match template {
+        None => Value::Object(context.clone()),
+        Some(template) => render_value(template, context),
+    }
+}
+
+fn render_value(value: &Value, context: &Map<String, Value>) -> Value {
+    match value {
+        Value::String(s) => {
+            if let Some(key) = s.strip_prefix('{').and_then(|k| k.strip_suffix('}'))
+                && let Some(field) = context.get(key)
+            {
+                return field.clone();
+            }
+            let mut out = String::with_capacity(s.len());
+            let mut rest = s.as_str();
+            while let Some(open) = rest.find('{') {
+                out.push_str(&rest[..open]);
+                rest = &rest[open..];
+                let field = rest[1..].find(['{', '}']).filter(|&end| rest.as_bytes()[end + 1] == b'}').and_then(|end| {
+                    let key = &rest[1..end + 1];
+                    context.get(key).map(|field| (field, key.len() + 2))
+                });
+                match field {
+                    Some((field, len)) => {
+                        match field {
+                            Value::String(s) => out.push_str(s),
+                            Value::Null => {}
+                            other => out.push_str(&other.to_string()),
+                        }
+                        rest = &rest[len..];
+                    }
+                    None => {
+                        out.push('{');
+                        rest = &rest[1..];
+                    }
+                }
+            }
+            out.push_str(rest);
+            Value::String(out)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|v| render_value(v, context)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), render_value(v, context))).collect()),
+        other => other.clone(),
+    }
+}
+
+fn deliver_loop(name: &str, url: &str, secret: Option<&str>, max_attempts: u32, deliveries: Receiver<Delivery>) {
+    for delivery in deliveries {
+        for attempt in 1..=max_attempts {
+            match send(url, secret, &delivery) {
+                Ok(()) => break,
+                Err((message, retryable)) if retryable && attempt < max_attempts => {
+                    let backoff = (1u64 << attempt.min(16)).min(MAX_BACKOFF_SECONDS);
+                    warn!(
+                        webhook = name;
+                        "Webhook '{}' attempt {}/{} failed: {}; retrying in {}s",
+                        name, attempt, max_attempts, message, backoff
+                    );
+                    thread::sleep(Duration::from_secs(backoff));
+                }
+                Err((message, _)) => {
+                    error!(webhook = name, event = delivery.event_type; "Webhook '{}' dropped {} event: {}", name, delivery.event_type, message);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Err carries a message and whether the request is worth retrying (network errors, 429 and 5xx).
+fn send(url: &str, secret: Option<&str>, delivery: &Delivery) -> Result<(), (String, bool)> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let mut request = ureq::post(url)
+        .timeout(Duration::from_secs(10))
+        .set("Content-Type", "application/json")
+        .set("X-DF-Event", delivery.event_type)
+        .set("X-DF-Timestamp", &timestamp.to_string());
+    if let Some(secret) = secret {
+        request = request.set("X-DF-Signature", &sign(secret, timestamp, &delivery.body));
+    }
+
+    match request.send_string(&delivery.body) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(status, _)) => Err((format!("HTTP {}", status), status == 429 || status >= 500)),
+        Err(e) => Err((e.to_string(), true)),
+    }
+}
+
+// "sha256=<hex>" over "<timestamp>.<body>", so receivers can also reject replayed requests.
+fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    let digest: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn context() -> Map<String, Value> {
+        match json!({ "killer": "Alpha", "distance": 123.5, "weapon": null, "team_kill": false }) {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    fn milestone_rule(milestones: Vec<u32>) -> WebhookRule {
+        serde_json::from_value(json!({
+            "name": "milestones", "url": "http://127.0.0.1/", "events": ["milestone"], "milestones": milestones
+        }))
+        .unwrap()
+    }
+
+    fn milestone(kills: u32) -> BusEvent {
+        BusEvent::Milestone { server_id: "default".to_string(), username: "Alpha".to_string(), kills }
+    }
+
+    #[test]
+    fn signature_is_hmac_sha256_of_timestamp_and_body() {
+        assert_eq!(
+            sign("secret", 1700000000, r#"{"a":1}"#),
+            "sha256=49f24e537407743fa4a0242bb63b94b9a47ee99cbbe071ccd8a22550ae411686"
+        );
+        assert_ne!(sign("secret", 1700000001, r#"{"a":1}"#), sign("secret", 1700000000, r#"{"a":1}"#));
+    }
+
+    #[test]
+    fn whole_placeholders_keep_their_json_type() {
+        let template = json!({ "distance": "{distance}", "team_kill": "{team_kill}", "nested": ["{killer}"], "fixed": 7 });
+        assert_eq!(
+            render_payload(Some(&template), &context()),
+            json!({ "distance": 123.5, "team_kill": false, "nested": ["Alpha"], "fixed": 7 })
+        );
+    }
+
+    #[test]
+    fn placeholders_inside_text_are_replaced_by_their_text() {
+        let template = json!({ "content": "{killer} got a kill at {distance}m with {weapon}{unknown}" });
+        assert_eq!(
+            render_payload(Some(&template), &context()),
+            json!({ "content": "Alpha got a kill at 123.5m with {unknown}" })
+        );
+    }
+
+    #[test]
+    fn without_a_template_the_event_is_sent() {
+        assert_eq!(render_payload(None, &context()), Value::Object(context()));
+    }
+
+    #[test]
+    fn substituted_values_are_not_expanded_again() {
+        let mut context = context();
+        context.insert("killer".to_string(), json!("{ip}"));
+        context.insert("ip".to_string(), json!("10.0.0.1"));
+        let template = json!({ "content": "{killer} killed someone {{weapon}}{" });
+        assert_eq!(render_payload(Some(&template), &context), json!({ "content": "{ip} killed someone {}{" }));
+    }
+
+    #[test]
+    fn rules_filter_by_event_type_server_player_and_milestone() {
+        let rule = milestone_rule(vec![100]);
+        assert!(rule.matches(&milestone(100)));
+        assert!(!rule.matches(&milestone(50)));
+        assert!(milestone_rule(Vec::new()).matches(&milestone(50)));
+
+        let rule = WebhookRule { servers: vec!["eu2".to_string()], ..milestone_rule(Vec::new()) };
+        assert!(!rule.matches(&milestone(100)));
+        let rule = WebhookRule { players: vec!["alpha".to_string()], ..milestone_rule(Vec::new()) };
+        assert!(rule.matches(&milestone(100)));
+        let rule = WebhookRule { events: vec!["kill".to_string()], ..milestone_rule(Vec::new()) };
+        assert!(!rule.matches(&milestone(100)));
+    }
+}